@@ -0,0 +1,546 @@
+use crate::clients::{
+    retry::HttpError,
+    social::{Http, Platform, SocialPost},
+};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+
+pub const DEFAULT_SERVICE_URL: &str = "https://bsky.social";
+
+// Notifications fetched per poll.
+const NOTIFICATIONS_FETCH_SIZE: usize = 50;
+
+pub struct BlueskyAuth {
+    pub handle: String,
+    pub app_password: String,
+    pub base_url: String,
+}
+
+pub struct Client {
+    http: Http,
+    base_url: String,
+    handle: String,
+    app_password: String,
+    did: String,
+    access_jwt: Mutex<String>,
+    // `indexedAt` of the newest notification seen.
+    latest_indexed_at: Mutex<Option<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    access_jwt: String,
+    did: String,
+    handle: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StrongRef {
+    uri: String,
+    cid: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReplyRef {
+    root: StrongRef,
+    parent: StrongRef,
+}
+
+#[derive(Deserialize)]
+struct PostRecord {
+    #[serde(default)]
+    text: String,
+    reply: Option<ReplyRef>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    did: String,
+    handle: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Notification {
+    uri: String,
+    author: Author,
+    reason: String,
+    record: PostRecord,
+    indexed_at: String,
+}
+
+#[derive(Deserialize)]
+struct Notifications {
+    notifications: Vec<Notification>,
+}
+
+#[derive(Deserialize)]
+struct PostView {
+    uri: String,
+    cid: String,
+    author: Author,
+    record: PostRecord,
+}
+
+#[derive(Deserialize)]
+struct Posts {
+    posts: Vec<PostView>,
+}
+
+// A post in a thread with its parents; blocked or deleted parents come without `post`.
+#[derive(Deserialize)]
+struct ThreadView {
+    post: Option<PostView>,
+    parent: Option<Box<ThreadView>>,
+}
+
+#[derive(Deserialize)]
+struct Thread {
+    thread: ThreadView,
+}
+
+impl Client {
+    pub async fn new(auth: BlueskyAuth) -> Result<Self> {
+        let mut client = Self {
+            http: Http::new(Platform::Bluesky),
+            base_url: auth.base_url.trim_end_matches('/').to_string(),
+            handle: auth.handle,
+            app_password: auth.app_password,
+            did: String::new(),
+            access_jwt: Mutex::new(String::new()),
+            latest_indexed_at: Mutex::new(None),
+        };
+
+        let session = client.create_session().await?;
+        client.did = session.did;
+        client.handle = session.handle;
+        info!("[BLUESKY_CLIENT] Authenticated as @{}", client.handle);
+
+        // Start after the latest notification so old mentions aren't answered.
+        let latest = client
+            .get::<Notifications>(
+                "app.bsky.notification.listNotifications",
+                &[("limit", "1".to_string())],
+            )
+            .await?;
+        *client.latest_indexed_at.lock().unwrap() = latest
+            .notifications
+            .into_iter()
+            .next()
+            .map(|notification| notification.indexed_at);
+
+        Ok(client)
+    }
+
+    pub fn http(&self) -> &Http {
+        &self.http
+    }
+
+    pub async fn post(&self, text: &str, reply_to: Option<&str>) -> Result<String> {
+        let mut record = json!({
+            "$type": "app.bsky.feed.post",
+            "text": text,
+            "createdAt": Utc::now().to_rfc3339(),
+        });
+
+        if let Some(reply_to) = reply_to {
+            let parent = self
+                .get::<Posts>("app.bsky.feed.getPosts", &[("uris", reply_to.to_string())])
+                .await?
+                .posts
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("[BLUESKY_CLIENT] post {} not found", reply_to))?;
+            let parent_ref = StrongRef {
+                uri: parent.uri,
+                cid: parent.cid,
+            };
+            let reply = ReplyRef {
+                root: parent
+                    .record
+                    .reply
+                    .map_or(parent_ref.clone(), |reply| reply.root),
+                parent: parent_ref,
+            };
+            record["reply"] = json!(reply);
+
+            // Links the `@handle` of the replied-to author, if used, so they are notified.
+            let facets = mention_facets(text, &parent.author);
+            if !facets.is_empty() {
+                record["facets"] = json!(facets);
+            }
+        }
+
+        let created = self
+            .post_json::<StrongRef>(
+                "com.atproto.repo.createRecord",
+                json!({
+                    "repo": self.did,
+                    "collection": "app.bsky.feed.post",
+                    "record": record,
+                }),
+            )
+            .await?;
+        info!("[BLUESKY_CLIENT] Agent posted (URI: {})", created.uri);
+
+        Ok(created.uri)
+    }
+
+    // Mentions of and replies to the agent, oldest first.
+    pub async fn fetch_mentions(&self) -> Result<Vec<SocialPost>> {
+        let notifications = self
+            .get::<Notifications>(
+                "app.bsky.notification.listNotifications",
+                &[("limit", NOTIFICATIONS_FETCH_SIZE.to_string())],
+            )
+            .await?
+            .notifications;
+
+        let latest_indexed_at = self.latest_indexed_at.lock().unwrap().clone();
+        let mentions = notifications
+            .iter()
+            .rev()
+            .filter(|notification| {
+                matches!(notification.reason.as_str(), "mention" | "reply")
+                    && notification.author.did != self.did
+                    && latest_indexed_at.as_ref() < Some(&notification.indexed_at)
+            })
+            .map(|notification| SocialPost {
+                id: notification.uri.clone(),
+                text: notification.record.text.clone(),
+                author_id: notification.author.did.clone(),
+                author_name: notification.author.handle.clone(),
+                in_reply_to_id: notification
+                    .record
+                    .reply
+                    .as_ref()
+                    .map(|reply| reply.parent.uri.clone()),
+                is_own: false,
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(max) = notifications
+            .into_iter()
+            .map(|notification| notification.indexed_at)
+            .max()
+        {
+            let mut latest_indexed_at = self.latest_indexed_at.lock().unwrap();
+            if latest_indexed_at.as_ref() < Some(&max) {
+                *latest_indexed_at = Some(max);
+            }
+        }
+        info!("[BLUESKY_CLIENT] Agent fetched {} mentions", mentions.len());
+
+        Ok(mentions)
+    }
+
+    pub async fn fetch_thread(
+        &self,
+        mention: &SocialPost,
+        max_depth: usize,
+    ) -> Result<Vec<SocialPost>> {
+        let Some(in_reply_to_id) = &mention.in_reply_to_id else {
+            return Ok(vec![]);
+        };
+
+        let thread = self
+            .get::<Thread>(
+                "app.bsky.feed.getPostThread",
+                &[
+                    ("uri", in_reply_to_id.clone()),
+                    ("depth", "0".to_string()),
+                    ("parentHeight", max_depth.to_string()),
+                ],
+            )
+            .await?
+            .thread;
+
+        let mut posts = Vec::new();
+        let mut next = Some(&thread);
+        while let Some(view) = next.take() {
+            let Some(post) = &view.post else {
+                break;
+            };
+            if posts.len() >= max_depth {
+                break;
+            }
+            posts.push(SocialPost {
+                id: post.uri.clone(),
+                text: post.record.text.clone(),
+                author_id: post.author.did.clone(),
+                author_name: post.author.handle.clone(),
+                in_reply_to_id: post
+                    .record
+                    .reply
+                    .as_ref()
+                    .map(|reply| reply.parent.uri.clone()),
+                is_own: post.author.did == self.did,
+            });
+            next = view.parent.as_deref();
+        }
+
+        posts.reverse();
+        Ok(posts)
+    }
+
+    pub fn cursor(&self) -> Option<String> {
+        self.latest_indexed_at.lock().unwrap().clone()
+    }
+
+    pub fn set_cursor(&self, cursor: &str) {
+        *self.latest_indexed_at.lock().unwrap() = Some(cursor.to_string());
+    }
+
+    async fn create_session(&self) -> Result<Session> {
+        let url = format!("{}/xrpc/com.atproto.server.createSession", self.base_url);
+        let body = json!({ "identifier": self.handle, "password": self.app_password });
+        let session = self
+            .http
+            .send::<Session>(|http| http.post(&url).json(&body))
+            .await?;
+        *self.access_jwt.lock().unwrap() = session.access_jwt.clone();
+
+        Ok(session)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, method: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = format!("{}/xrpc/{}", self.base_url, method);
+        self.with_session(|| async {
+            let token = self.access_jwt.lock().unwrap().clone();
+            self.http
+                .send::<T>(|http| http.get(&url).query(query).bearer_auth(&token))
+                .await
+        })
+        .await
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T> {
+        let url = format!("{}/xrpc/{}", self.base_url, method);
+        self.with_session(|| async {
+            let token = self.access_jwt.lock().unwrap().clone();
+            self.http
+                .send::<T>(|http| http.post(&url).json(&body).bearer_auth(&token))
+                .await
+        })
+        .await
+    }
+
+    // Access tokens expire after a few hours: logs in again once and retries.
+    async fn with_session<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        match call().await {
+            Err(e)
+                if e.downcast_ref::<HttpError>()
+                    .is_some_and(|e| e.body.contains("ExpiredToken")) =>
+            {
+                warn!("[BLUESKY_CLIENT] Session expired, logging in again...");
+                self.create_session().await?;
+                call().await
+            }
+            result => result,
+        }
+    }
+}
+
+// Mention facets for every `@handle` of `author` in `text`. Facets index the text in
+// UTF-8 bytes.
+fn mention_facets(text: &str, author: &Author) -> Vec<serde_json::Value> {
+    let handle = format!("@{}", author.handle);
+    text.match_indices(&handle)
+        .map(|(start, _)| {
+            json!({
+                "index": { "byteStart": start, "byteEnd": start + handle.len() },
+                "features": [{ "$type": "app.bsky.richtext.facet#mention", "did": author.did }],
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{fake_server::FakeServer, retry::UnknownOutcome};
+
+    const SESSION: &str = "/xrpc/com.atproto.server.createSession";
+    const NOTIFICATIONS: &str = "/xrpc/app.bsky.notification.listNotifications";
+    const CREATE_RECORD: &str = "/xrpc/com.atproto.repo.createRecord";
+    const GET_POSTS: &str = "/xrpc/app.bsky.feed.getPosts";
+
+    async fn connect(server: &FakeServer) -> Client {
+        server
+            .on(
+                "POST",
+                SESSION,
+                200,
+                json!({ "accessJwt": "jwt-1", "did": "did:plc:lore", "handle": "lore.bsky.social" }),
+            )
+            .on(
+                "GET",
+                NOTIFICATIONS,
+                200,
+                json!({ "notifications": [
+                    notification("at://old", "did:plc:bob", "mention", "2024-01-01T00:00:00Z", "old"),
+                ] }),
+            );
+        Client::new(BlueskyAuth {
+            handle: "lore.bsky.social".to_string(),
+            app_password: "password".to_string(),
+            base_url: server.url.clone(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn notification(
+        uri: &str,
+        did: &str,
+        reason: &str,
+        indexed_at: &str,
+        text: &str,
+    ) -> serde_json::Value {
+        json!({
+            "uri": uri,
+            "author": { "did": did, "handle": "bob.bsky.social" },
+            "reason": reason,
+            "record": { "text": text },
+            "indexedAt": indexed_at,
+        })
+    }
+
+    #[tokio::test]
+    async fn posts_replies_in_the_thread_of_the_parent() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server
+            .on(
+                "GET",
+                GET_POSTS,
+                200,
+                json!({ "posts": [{
+                    "uri": "at://parent",
+                    "cid": "cid-parent",
+                    "author": { "did": "did:plc:bob", "handle": "bob.bsky.social" },
+                    "record": {
+                        "text": "hi",
+                        "reply": {
+                            "root": { "uri": "at://root", "cid": "cid-root" },
+                            "parent": { "uri": "at://root", "cid": "cid-root" },
+                        },
+                    },
+                }] }),
+            )
+            .on(
+                "POST",
+                CREATE_RECORD,
+                200,
+                json!({ "uri": "at://reply", "cid": "cid-reply" }),
+            );
+
+        let uri = client
+            .post("@bob.bsky.social hello", Some("at://parent"))
+            .await
+            .unwrap();
+
+        assert_eq!(uri, "at://reply");
+        let created = server.requests("POST", CREATE_RECORD)[0].json();
+        assert_eq!(created["repo"], "did:plc:lore");
+        assert_eq!(created["record"]["reply"]["root"]["uri"], "at://root");
+        assert_eq!(created["record"]["reply"]["parent"]["uri"], "at://parent");
+        assert_eq!(created["record"]["facets"][0]["index"]["byteEnd"], 16);
+        assert_eq!(
+            created["record"]["facets"][0]["features"][0]["did"],
+            "did:plc:bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_new_mentions_and_replies() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "GET",
+            NOTIFICATIONS,
+            200,
+            json!({ "notifications": [
+                notification("at://like", "did:plc:bob", "like", "2024-01-01T00:00:04Z", ""),
+                notification("at://reply", "did:plc:bob", "reply", "2024-01-01T00:00:03Z", "and you?"),
+                notification("at://mention", "did:plc:bob", "mention", "2024-01-01T00:00:02Z", "what is lore?"),
+                notification("at://old", "did:plc:bob", "mention", "2024-01-01T00:00:00Z", "old"),
+            ] }),
+        );
+
+        let mentions = client.fetch_mentions().await.unwrap();
+
+        assert_eq!(
+            mentions
+                .iter()
+                .map(|post| (post.id.as_str(), post.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("at://mention", "what is lore?"),
+                ("at://reply", "and you?")
+            ]
+        );
+        assert_eq!(client.cursor().as_deref(), Some("2024-01-01T00:00:04Z"));
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_when_the_session_expired() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server
+            .on(
+                "POST",
+                SESSION,
+                200,
+                json!({ "accessJwt": "jwt-2", "did": "did:plc:lore", "handle": "lore.bsky.social" }),
+            )
+            .on(
+                "POST",
+                CREATE_RECORD,
+                400,
+                json!({ "error": "ExpiredToken", "message": "Token has expired" }),
+            )
+            .on(
+                "POST",
+                CREATE_RECORD,
+                200,
+                json!({ "uri": "at://post", "cid": "cid-post" }),
+            );
+
+        let uri = client.post("hello", None).await.unwrap();
+
+        assert_eq!(uri, "at://post");
+        assert_eq!(server.requests("POST", SESSION).len(), 2);
+        assert_eq!(server.requests("POST", CREATE_RECORD).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn never_recreates_a_record_after_a_server_error() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "POST",
+            CREATE_RECORD,
+            500,
+            json!({ "error": "InternalServerError" }),
+        );
+
+        let e = client.post("hello", None).await.unwrap_err();
+
+        assert!(e.downcast_ref::<UnknownOutcome>().is_some());
+        assert_eq!(server.requests("POST", CREATE_RECORD).len(), 1);
+    }
+}
@@ -0,0 +1 @@
+pub mod bluesky;
\ No newline at end of file
@@ -0,0 +1,342 @@
+use crate::clients::social::{Http, Platform, SocialPost};
+use anyhow::Result;
+use log::{error, info};
+use reqwest::{header::AUTHORIZATION, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::sync::Mutex;
+
+pub const DEFAULT_API_URL: &str = "https://discord.com/api/v10";
+
+// Messages fetched per poll, the most the API returns at once.
+const MESSAGES_FETCH_SIZE: usize = 100;
+
+pub struct DiscordAuth {
+    pub bot_token: String,
+    // Channel the agent posts to and answers in.
+    pub channel_id: String,
+    pub base_url: String,
+}
+
+pub struct Client {
+    http: Http,
+    base_url: String,
+    bot_token: String,
+    channel_id: String,
+    bot_id: String,
+    latest_message_id: Mutex<Option<String>>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct MessageReference {
+    message_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    id: String,
+    content: String,
+    author: User,
+    #[serde(default)]
+    mentions: Vec<User>,
+    message_reference: Option<MessageReference>,
+    referenced_message: Option<Box<Message>>,
+}
+
+impl Client {
+    pub async fn new(auth: DiscordAuth) -> Result<Self> {
+        let mut client = Self {
+            http: Http::new(Platform::Discord),
+            base_url: auth.base_url.trim_end_matches('/').to_string(),
+            bot_token: auth.bot_token,
+            channel_id: auth.channel_id,
+            bot_id: String::new(),
+            latest_message_id: Mutex::new(None),
+        };
+
+        let bot = client.get::<User>("/users/@me", &[]).await?;
+        info!("[DISCORD_CLIENT] Authenticated as bot {}", bot.username);
+        client.bot_id = bot.id;
+
+        // Start after the latest message so old messages aren't answered.
+        let latest = client
+            .get::<Vec<Message>>(
+                &format!("/channels/{}/messages", client.channel_id),
+                &[("limit", "1".to_string())],
+            )
+            .await?;
+        *client.latest_message_id.lock().unwrap() =
+            latest.into_iter().next().map(|message| message.id);
+
+        Ok(client)
+    }
+
+    pub fn http(&self) -> &Http {
+        &self.http
+    }
+
+    pub async fn post(&self, text: &str, reply_to: Option<&str>) -> Result<String> {
+        let mut body = json!({ "content": text });
+        if let Some(reply_to) = reply_to {
+            body["message_reference"] = json!({ "message_id": reply_to });
+        }
+
+        let url = format!("{}/channels/{}/messages", self.base_url, self.channel_id);
+        let message = self
+            .http
+            .send::<Message>(|http| self.authorize(http.post(&url).json(&body)))
+            .await?;
+        info!("[DISCORD_CLIENT] Agent sent message (ID: {})", message.id);
+
+        Ok(message.id)
+    }
+
+    // Messages in the channel that mention the bot or reply to one of its messages.
+    pub async fn fetch_mentions(&self) -> Result<Vec<SocialPost>> {
+        let mut query = vec![("limit", MESSAGES_FETCH_SIZE.to_string())];
+        if let Some(after) = self.latest_message_id.lock().unwrap().clone() {
+            query.push(("after", after));
+        }
+
+        let messages = self
+            .get::<Vec<Message>>(&format!("/channels/{}/messages", self.channel_id), &query)
+            .await?;
+        if let Some(max_id) = messages.iter().map(|message| snowflake(&message.id)).max() {
+            *self.latest_message_id.lock().unwrap() = Some(max_id.to_string());
+        }
+
+        // Messages come newest first.
+        let mentions = messages
+            .iter()
+            .rev()
+            .filter(|message| {
+                message.author.id != self.bot_id
+                    && (message.mentions.iter().any(|user| user.id == self.bot_id)
+                        || message
+                            .referenced_message
+                            .as_ref()
+                            .is_some_and(|replied_to| replied_to.author.id == self.bot_id))
+            })
+            .map(|message| SocialPost {
+                text: strip_mention(&message.content, &self.bot_id),
+                ..self.to_post(message)
+            })
+            .collect::<Vec<_>>();
+        info!("[DISCORD_CLIENT] Agent fetched {} mentions", mentions.len());
+
+        Ok(mentions)
+    }
+
+    // Walks up the reply chain one message at a time.
+    pub async fn fetch_thread(
+        &self,
+        mention: &SocialPost,
+        max_depth: usize,
+    ) -> Result<Vec<SocialPost>> {
+        let mut thread = Vec::new();
+        let mut next_id = mention.in_reply_to_id.clone();
+
+        while let Some(id) = next_id.take() {
+            if thread.len() >= max_depth {
+                break;
+            }
+
+            match self
+                .get::<Message>(
+                    &format!("/channels/{}/messages/{}", self.channel_id, id),
+                    &[],
+                )
+                .await
+            {
+                Ok(message) => {
+                    let post = SocialPost {
+                        text: strip_mention(&message.content, &self.bot_id),
+                        ..self.to_post(&message)
+                    };
+                    next_id = post.in_reply_to_id.clone();
+                    thread.push(post);
+                }
+                Err(e) if thread.is_empty() => return Err(e),
+                Err(e) => {
+                    error!(
+                        "[DISCORD_CLIENT] Failed to fetch thread message {}: {}. Stopping walk...",
+                        id, e
+                    );
+                    break;
+                }
+            }
+        }
+
+        thread.reverse();
+        Ok(thread)
+    }
+
+    pub fn cursor(&self) -> Option<String> {
+        self.latest_message_id.lock().unwrap().clone()
+    }
+
+    pub fn set_cursor(&self, cursor: &str) {
+        *self.latest_message_id.lock().unwrap() = Some(cursor.to_string());
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        self.http
+            .send::<T>(|http| self.authorize(http.get(&url).query(query)))
+            .await
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        request.header(AUTHORIZATION, format!("Bot {}", self.bot_token))
+    }
+
+    fn to_post(&self, message: &Message) -> SocialPost {
+        SocialPost {
+            id: message.id.clone(),
+            text: message.content.clone(),
+            author_id: message.author.id.clone(),
+            author_name: message.author.username.clone(),
+            in_reply_to_id: message
+                .message_reference
+                .as_ref()
+                .and_then(|reference| reference.message_id.clone()),
+            is_own: message.author.id == self.bot_id,
+        }
+    }
+}
+
+// Message ids are snowflakes, which sort by creation time.
+fn snowflake(id: &str) -> u64 {
+    id.parse::<u64>().unwrap_or_default()
+}
+
+// Removes `<@id>` / `<@!id>` mentions of the bot from a message.
+fn strip_mention(content: &str, bot_id: &str) -> String {
+    content
+        .replace(&format!("<@{}>", bot_id), "")
+        .replace(&format!("<@!{}>", bot_id), "")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{fake_server::FakeServer, retry::HttpError};
+    use reqwest::StatusCode;
+
+    const MESSAGES: &str = "/channels/55/messages";
+
+    async fn connect(server: &FakeServer) -> Client {
+        server
+            .on(
+                "GET",
+                "/users/@me",
+                200,
+                json!({ "id": "1", "username": "lore" }),
+            )
+            .on("GET", MESSAGES, 200, json!([message("100", "2", "hi all")]));
+        Client::new(DiscordAuth {
+            bot_token: "TOKEN".to_string(),
+            channel_id: "55".to_string(),
+            base_url: server.url.clone(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn message(id: &str, author_id: &str, content: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "content": content,
+            "author": { "id": author_id, "username": format!("user{}", author_id) },
+        })
+    }
+
+    #[tokio::test]
+    async fn posts_replies_to_the_channel() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on("POST", MESSAGES, 200, message("101", "1", "hello"));
+
+        let id = client.post("hello", Some("100")).await.unwrap();
+
+        assert_eq!(id, "101");
+        let sent = server.requests("POST", MESSAGES)[0].json();
+        assert_eq!(sent["content"], "hello");
+        assert_eq!(sent["message_reference"]["message_id"], "100");
+        assert_eq!(client.cursor().as_deref(), Some("100"));
+    }
+
+    #[tokio::test]
+    async fn fetches_mentions_and_replies_to_the_bot() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        let mut mention = message("103", "2", "<@1> what is lore?");
+        mention["mentions"] = json!([{ "id": "1", "username": "lore" }]);
+        let mut reply = message("104", "3", "and you?");
+        reply["message_reference"] = json!({ "message_id": "102" });
+        reply["referenced_message"] = message("102", "1", "earlier");
+        // Newest first, like the API.
+        server.on(
+            "GET",
+            MESSAGES,
+            200,
+            json!([reply, message("105", "2", "just chatting"), mention]),
+        );
+
+        let mentions = client.fetch_mentions().await.unwrap();
+
+        assert_eq!(
+            mentions
+                .iter()
+                .map(|post| (post.id.as_str(), post.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("103", "what is lore?"), ("104", "and you?")]
+        );
+        assert!(server.requests("GET", MESSAGES)[1]
+            .query
+            .contains("after=100"));
+        assert_eq!(client.cursor().as_deref(), Some("105"));
+    }
+
+    #[tokio::test]
+    async fn surfaces_client_errors_without_retrying() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "POST",
+            MESSAGES,
+            403,
+            json!({ "message": "Missing Access" }),
+        );
+
+        let e = client.post("hello", None).await.unwrap_err();
+
+        let e = e.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(e.status, StatusCode::FORBIDDEN);
+        assert_eq!(e.tag, "DISCORD_CLIENT");
+        assert_eq!(server.requests("POST", MESSAGES).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn waits_out_rate_limits() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server
+            .rate_limit("GET", MESSAGES, 0)
+            .on("GET", MESSAGES, 200, json!([]));
+
+        let mentions = client.fetch_mentions().await.unwrap();
+
+        assert!(mentions.is_empty());
+        assert_eq!(server.requests("GET", MESSAGES).len(), 3);
+    }
+}
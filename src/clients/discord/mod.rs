@@ -0,0 +1 @@
+pub mod discord;
\ No newline at end of file
@@ -0,0 +1,171 @@
+use hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+// Local HTTP server with canned responses, to test the platform clients against.
+pub struct FakeServer {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<Recorded>,
+}
+
+struct Route {
+    method: String,
+    path: String,
+    // Served in order; the last one repeats.
+    responses: Vec<Canned>,
+    served: usize,
+}
+
+#[derive(Clone)]
+struct Canned {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub body: String,
+}
+
+impl Recorded {
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or(Value::Null)
+    }
+}
+
+impl FakeServer {
+    // Binds a free port on localhost and serves until the runtime stops.
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(&state, request).await) }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        Self { url, state }
+    }
+
+    // Answers `method path` with `status` and the JSON `body`. Unknown routes get a 404.
+    pub fn on(&self, method: &str, path: &str, status: u16, body: Value) -> &Self {
+        self.push(
+            method,
+            path,
+            Canned {
+                status,
+                headers: vec![],
+                body: body.to_string(),
+            },
+        )
+    }
+
+    // Answers `method path` with a 429 asking to retry after `retry_after` seconds.
+    pub fn rate_limit(&self, method: &str, path: &str, retry_after: u64) -> &Self {
+        self.push(
+            method,
+            path,
+            Canned {
+                status: 429,
+                headers: vec![("retry-after", retry_after.to_string())],
+                body: "{}".to_string(),
+            },
+        )
+    }
+
+    // Requests received for `method path`, oldest first.
+    pub fn requests(&self, method: &str, path: &str) -> Vec<Recorded> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter(|request| request.method == method && request.path == path)
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, method: &str, path: &str, canned: Canned) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .routes
+            .iter_mut()
+            .find(|route| route.method == method && route.path == path)
+        {
+            Some(route) => route.responses.push(canned),
+            None => state.routes.push(Route {
+                method: method.to_string(),
+                path: path.to_string(),
+                responses: vec![canned],
+                served: 0,
+            }),
+        }
+        self
+    }
+}
+
+async fn handle(state: &Mutex<State>, request: Request<Body>) -> Response<Body> {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let query = request.uri().query().unwrap_or_default().to_string();
+    let body = to_bytes(request.into_body()).await.unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    state.requests.push(Recorded {
+        method: method.clone(),
+        path: path.clone(),
+        query,
+        body: String::from_utf8_lossy(&body).to_string(),
+    });
+
+    let canned = state
+        .routes
+        .iter_mut()
+        .find(|route| route.method == method && route.path == path)
+        .map(|route| {
+            let canned = route.responses[route.served.min(route.responses.len() - 1)].clone();
+            route.served += 1;
+            canned
+        });
+    let Some(canned) = canned else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("{}"))
+            .unwrap();
+    };
+
+    let mut response = Response::builder().status(canned.status);
+    for (name, value) in canned.headers {
+        response = response.header(name, value);
+    }
+    response
+        .header("content-type", "application/json")
+        .body(Body::from(canned.body))
+        .unwrap()
+}
@@ -0,0 +1,399 @@
+use crate::clients::social::{Http, Platform, SocialPost};
+use anyhow::Result;
+use log::info;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::sync::Mutex;
+
+// Notifications fetched per poll.
+const NOTIFICATIONS_FETCH_SIZE: usize = 40;
+
+pub struct MastodonAuth {
+    pub access_token: String,
+    // Instance the account lives on, e.g. `https://mastodon.social`.
+    pub base_url: String,
+}
+
+pub struct Client {
+    http: Http,
+    base_url: String,
+    access_token: String,
+    account_id: String,
+    latest_notification_id: Mutex<Option<String>>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    id: String,
+    acct: String,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    id: String,
+    // HTML
+    content: String,
+    account: Account,
+    in_reply_to_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Notification {
+    id: String,
+    status: Option<Status>,
+}
+
+#[derive(Deserialize)]
+struct Context {
+    ancestors: Vec<Status>,
+}
+
+impl Client {
+    pub async fn new(auth: MastodonAuth) -> Result<Self> {
+        let mut client = Self {
+            http: Http::new(Platform::Mastodon),
+            base_url: auth.base_url.trim_end_matches('/').to_string(),
+            access_token: auth.access_token,
+            account_id: String::new(),
+            latest_notification_id: Mutex::new(None),
+        };
+
+        let account = client
+            .get::<Account>("/api/v1/accounts/verify_credentials", &[])
+            .await?;
+        info!("[MASTODON_CLIENT] Authenticated as @{}", account.acct);
+        client.account_id = account.id;
+
+        // Start after the latest mention so old mentions aren't answered.
+        let latest = client
+            .get::<Vec<Notification>>(
+                "/api/v1/notifications",
+                &[
+                    ("types[]", "mention".to_string()),
+                    ("limit", "1".to_string()),
+                ],
+            )
+            .await?;
+        *client.latest_notification_id.lock().unwrap() = latest
+            .into_iter()
+            .next()
+            .map(|notification| notification.id);
+
+        Ok(client)
+    }
+
+    pub fn http(&self) -> &Http {
+        &self.http
+    }
+
+    pub async fn post(&self, text: &str, reply_to: Option<&str>) -> Result<String> {
+        let mut body = json!({ "status": text });
+        if let Some(reply_to) = reply_to {
+            body["in_reply_to_id"] = json!(reply_to);
+        }
+
+        // `Http::send` only sends a post again after a 429 or a connection error, when it
+        // wasn't applied. The key guards against a proxy that applied it anyway.
+        let idempotency_key = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>();
+        let url = format!("{}/api/v1/statuses", self.base_url);
+        let status = self
+            .http
+            .send::<Status>(|http| {
+                http.post(&url)
+                    .bearer_auth(&self.access_token)
+                    .header("Idempotency-Key", &idempotency_key)
+                    .json(&body)
+            })
+            .await?;
+        info!("[MASTODON_CLIENT] Agent posted status (ID: {})", status.id);
+
+        Ok(status.id)
+    }
+
+    // Mentions newer than the cursor, oldest first.
+    pub async fn fetch_mentions(&self) -> Result<Vec<SocialPost>> {
+        let mut query = vec![
+            ("types[]", "mention".to_string()),
+            ("limit", NOTIFICATIONS_FETCH_SIZE.to_string()),
+        ];
+        if let Some(since_id) = self.latest_notification_id.lock().unwrap().clone() {
+            query.push(("since_id", since_id));
+        }
+
+        let notifications = self
+            .get::<Vec<Notification>>("/api/v1/notifications", &query)
+            .await?;
+        if let Some(max_id) = notifications
+            .iter()
+            .filter_map(|notification| notification.id.parse::<u64>().ok())
+            .max()
+        {
+            *self.latest_notification_id.lock().unwrap() = Some(max_id.to_string());
+        }
+
+        let mentions = notifications
+            .iter()
+            .rev()
+            .filter_map(|notification| notification.status.as_ref())
+            .filter(|status| status.account.id != self.account_id)
+            .map(|status| self.to_post(status))
+            .collect::<Vec<_>>();
+        info!(
+            "[MASTODON_CLIENT] Agent fetched {} mentions",
+            mentions.len()
+        );
+
+        Ok(mentions)
+    }
+
+    pub async fn fetch_thread(
+        &self,
+        mention: &SocialPost,
+        max_depth: usize,
+    ) -> Result<Vec<SocialPost>> {
+        let context = self
+            .get::<Context>(&format!("/api/v1/statuses/{}/context", mention.id), &[])
+            .await?;
+
+        // Ancestors come oldest first.
+        let skip = context.ancestors.len().saturating_sub(max_depth);
+        Ok(context
+            .ancestors
+            .iter()
+            .skip(skip)
+            .map(|status| self.to_post(status))
+            .collect())
+    }
+
+    pub fn cursor(&self) -> Option<String> {
+        self.latest_notification_id.lock().unwrap().clone()
+    }
+
+    pub fn set_cursor(&self, cursor: &str) {
+        *self.latest_notification_id.lock().unwrap() = Some(cursor.to_string());
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = format!("{}{}", self.base_url, path);
+        self.http
+            .send::<T>(|http| http.get(&url).query(query).bearer_auth(&self.access_token))
+            .await
+    }
+
+    fn to_post(&self, status: &Status) -> SocialPost {
+        SocialPost {
+            id: status.id.clone(),
+            text: strip_html(&status.content),
+            author_id: status.account.id.clone(),
+            author_name: status.account.acct.clone(),
+            in_reply_to_id: status.in_reply_to_id.clone(),
+            is_own: status.account.id == self.account_id,
+        }
+    }
+}
+
+// Mastodon only notifies people mentioned in a status, so replies start with the
+// author's `@acct` unless the text already mentions them.
+pub fn with_mention(mention: &SocialPost, text: &str) -> String {
+    let acct = format!("@{}", mention.author_name);
+    if text.contains(&acct) {
+        text.to_string()
+    } else {
+        format!("{} {}", acct, text)
+    }
+}
+
+// Statuses come as HTML: keeps the text, with paragraphs and line breaks as newlines.
+fn strip_html(content: &str) -> String {
+    let content = content
+        .replace("</p><p>", "\n\n")
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br />", "\n");
+
+    let mut text = String::with_capacity(content.len());
+    let mut in_tag = false;
+    for c in content.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+
+    text.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{
+        fake_server::FakeServer,
+        retry::{HttpError, UnknownOutcome},
+    };
+    use reqwest::StatusCode;
+
+    const NOTIFICATIONS: &str = "/api/v1/notifications";
+    const STATUSES: &str = "/api/v1/statuses";
+
+    async fn connect(server: &FakeServer) -> Client {
+        server
+            .on(
+                "GET",
+                "/api/v1/accounts/verify_credentials",
+                200,
+                json!({ "id": "1", "acct": "lore" }),
+            )
+            .on(
+                "GET",
+                NOTIFICATIONS,
+                200,
+                json!([notification("20", status("200", "2", "<p>old</p>"))]),
+            );
+        Client::new(MastodonAuth {
+            access_token: "TOKEN".to_string(),
+            base_url: server.url.clone(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn status(id: &str, account_id: &str, content: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "content": content,
+            "account": { "id": account_id, "acct": format!("user{}", account_id) },
+            "in_reply_to_id": null,
+        })
+    }
+
+    fn notification(id: &str, status: serde_json::Value) -> serde_json::Value {
+        json!({ "id": id, "status": status })
+    }
+
+    #[tokio::test]
+    async fn posts_statuses_in_reply_to_a_mention() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on("POST", STATUSES, 200, status("201", "1", "<p>hello</p>"));
+
+        let id = client.post("hello", Some("200")).await.unwrap();
+
+        assert_eq!(id, "201");
+        let sent = server.requests("POST", STATUSES)[0].json();
+        assert_eq!(sent["status"], "hello");
+        assert_eq!(sent["in_reply_to_id"], "200");
+        assert_eq!(client.cursor().as_deref(), Some("20"));
+    }
+
+    #[tokio::test]
+    async fn fetches_mentions_since_the_cursor() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        // Newest first, like the API.
+        server.on(
+            "GET",
+            NOTIFICATIONS,
+            200,
+            json!([
+                notification("23", status("203", "1", "<p>my own</p>")),
+                notification("22", status("202", "3", "<p>and you?</p>")),
+                notification(
+                    "21",
+                    status("201", "2", "<p><span>@lore</span> what&#39;s lore?</p>")
+                ),
+            ]),
+        );
+
+        let mentions = client.fetch_mentions().await.unwrap();
+
+        assert_eq!(
+            mentions
+                .iter()
+                .map(|post| (post.id.as_str(), post.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("201", "@lore what's lore?"), ("202", "and you?")]
+        );
+        assert!(server.requests("GET", NOTIFICATIONS)[1]
+            .query
+            .contains("since_id=20"));
+        assert_eq!(client.cursor().as_deref(), Some("23"));
+        assert_eq!(with_mention(&mentions[1], "hi"), "@user3 hi".to_string());
+    }
+
+    #[tokio::test]
+    async fn fetches_the_thread_above_a_mention() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "GET",
+            "/api/v1/statuses/203/context",
+            200,
+            json!({ "ancestors": [
+                status("200", "2", "<p>first</p>"),
+                status("201", "1", "<p>second</p>"),
+                status("202", "2", "<p>third</p>"),
+            ] }),
+        );
+        let mention = SocialPost {
+            id: "203".to_string(),
+            text: "fourth".to_string(),
+            author_id: "2".to_string(),
+            author_name: "user2".to_string(),
+            in_reply_to_id: Some("202".to_string()),
+            is_own: false,
+        };
+
+        let thread = client.fetch_thread(&mention, 2).await.unwrap();
+
+        assert_eq!(
+            thread
+                .iter()
+                .map(|post| (post.text.as_str(), post.is_own))
+                .collect::<Vec<_>>(),
+            vec![("second", true), ("third", false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn surfaces_client_errors() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "POST",
+            STATUSES,
+            422,
+            json!({ "error": "Text can't be blank" }),
+        );
+
+        let e = client.post("", None).await.unwrap_err();
+
+        let e = e.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(e.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(e.body.contains("blank"));
+    }
+
+    #[tokio::test]
+    async fn never_resends_a_status_after_a_server_error() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on("POST", STATUSES, 503, json!({}));
+
+        let e = client.post("hello", None).await.unwrap_err();
+
+        assert!(e.downcast_ref::<UnknownOutcome>().is_some());
+        assert_eq!(server.requests("POST", STATUSES).len(), 1);
+    }
+}
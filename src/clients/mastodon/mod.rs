@@ -0,0 +1 @@
+pub mod mastodon;
\ No newline at end of file
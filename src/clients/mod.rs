@@ -1,2 +1,10 @@
+pub mod bluesky;
+pub mod discord;
+#[cfg(test)]
+pub mod fake_server;
+pub mod mastodon;
+pub mod retry;
+pub mod social;
+pub mod telegram;
 pub mod twitter;
 
@@ -0,0 +1,128 @@
+use anyhow::{Error, Result};
+use log::warn;
+use rand::{thread_rng, Rng};
+use reqwest::{Method, Request, Response, StatusCode};
+use std::{fmt, future::Future, time::Duration};
+
+// Retries after a 429, 5xx or connection error before giving up.
+pub const MAX_RETRIES: u32 = 3;
+// Base delay of the exponential backoff for 5xx and connection errors.
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+// Longest wait for a rate limit to reset inside a single call.
+pub const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+// A non-success response, kept typed so clients can react to specific failures.
+#[derive(Debug)]
+pub struct HttpError {
+    // Log prefix of the client, e.g. `BLUESKY_CLIENT`.
+    pub tag: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] request failed with {}: {}",
+            self.tag, self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+// A request that isn't a GET timed out, broke off or got a 5xx after it was sent. It may
+// have been applied anyway, e.g. a post created, so it isn't sent again blindly.
+#[derive(Debug)]
+pub struct UnknownOutcome {
+    pub tag: String,
+    pub reason: String,
+}
+
+impl fmt::Display for UnknownOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] request outcome unknown: {}", self.tag, self.reason)
+    }
+}
+
+impl std::error::Error for UnknownOutcome {}
+
+// Sends the request built by `request` until it succeeds or fails for good. The request
+// is rebuilt for every attempt. `rate_limit_wait` sees every response and returns how long
+// a 429 has to wait for its limit to reset; waits beyond `MAX_RATE_LIMIT_WAIT` fail the
+// call. Connection errors are retried with jittered exponential backoff, since nothing
+// was sent. Timeouts and 5xx are only retried for GETs, other methods fail with
+// `UnknownOutcome` instead.
+pub async fn send<F, Fut>(
+    tag: &str,
+    http: &reqwest::Client,
+    request: F,
+    mut rate_limit_wait: impl FnMut(&Response) -> Option<Duration>,
+) -> Result<Response>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Request>>,
+{
+    let mut attempt = 0;
+    loop {
+        let request = request().await?;
+        let idempotent = request.method() == Method::GET;
+        let unknown = |reason: String| {
+            Err(Error::new(UnknownOutcome {
+                tag: tag.to_string(),
+                reason,
+            }))
+        };
+
+        let delay = match http.execute(request).await {
+            Ok(response) => {
+                let wait = rate_limit_wait(&response);
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(response);
+                }
+
+                let error = HttpError {
+                    tag: tag.to_string(),
+                    status,
+                    body: response.text().await.unwrap_or_default(),
+                };
+                match wait {
+                    Some(wait)
+                        if status == StatusCode::TOO_MANY_REQUESTS
+                            && wait <= MAX_RATE_LIMIT_WAIT
+                            && attempt < MAX_RETRIES =>
+                    {
+                        wait
+                    }
+                    _ if status.is_server_error() && !idempotent => {
+                        return unknown(format!("{} {}", status, error.body))
+                    }
+                    _ if status.is_server_error() && attempt < MAX_RETRIES => backoff(attempt),
+                    _ => return Err(Error::new(error)),
+                }
+            }
+            Err(e) if e.is_connect() && attempt < MAX_RETRIES => backoff(attempt),
+            Err(e) if !e.is_connect() && !idempotent => return unknown(e.to_string()),
+            Err(e) if e.is_timeout() && attempt < MAX_RETRIES => backoff(attempt),
+            Err(e) => return Err(e.into()),
+        };
+
+        attempt += 1;
+        warn!(
+            "[{}] Request failed, retrying in {}s ({}/{})",
+            tag,
+            delay.as_secs(),
+            attempt,
+            MAX_RETRIES
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Exponential backoff with up to one base delay of random jitter.
+pub fn backoff(attempt: u32) -> Duration {
+    let jitter = thread_rng().gen_range(0..=BACKOFF_BASE.as_millis() as u64);
+    BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt)) + Duration::from_millis(jitter)
+}
@@ -0,0 +1,417 @@
+use super::retry;
+use super::{
+    bluesky::bluesky::{BlueskyAuth, Client as BlueskyClient, DEFAULT_SERVICE_URL},
+    discord::discord::{Client as DiscordClient, DiscordAuth, DEFAULT_API_URL as DISCORD_API_URL},
+    mastodon::mastodon::{self, Client as MastodonClient, MastodonAuth},
+    telegram::telegram::{
+        Client as TelegramClient, TelegramAuth, DEFAULT_API_URL as TELEGRAM_API_URL,
+    },
+};
+use crate::core::telemetry;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    env,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    Telegram,
+    Discord,
+    Bluesky,
+    Mastodon,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Telegram => "telegram",
+            Platform::Discord => "discord",
+            Platform::Bluesky => "bluesky",
+            Platform::Mastodon => "mastodon",
+        }
+    }
+
+    // Log prefix, e.g. `[TELEGRAM]`.
+    pub fn tag(&self) -> String {
+        self.name().to_uppercase()
+    }
+
+    // Longest message the platform accepts, in characters.
+    pub fn max_chars(&self) -> usize {
+        match self {
+            Platform::Telegram => 4096,
+            Platform::Discord => 2000,
+            Platform::Bluesky => 300,
+            Platform::Mastodon => 500,
+        }
+    }
+}
+
+// A post or message on any platform other than Twitter, normalized to plain text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SocialPost {
+    // Platform id of the post: a message id, status id or `at://` uri.
+    pub id: String,
+    pub text: String,
+    pub author_id: String,
+    pub author_name: String,
+    pub in_reply_to_id: Option<String>,
+    // Written by the agent's own account.
+    pub is_own: bool,
+}
+
+// Clients of the platforms the agent posts to next to Twitter. Each one adapts posts to
+// the platform: long messages become threads, replies notify their author the way the
+// platform expects and mention markup is stripped from what the agent reads.
+pub enum SocialClient {
+    Telegram(TelegramClient),
+    Discord(DiscordClient),
+    Bluesky(BlueskyClient),
+    Mastodon(MastodonClient),
+}
+
+impl SocialClient {
+    // Connects every platform with credentials in the environment. Base urls can be
+    // overridden, e.g. to run against a local fake server.
+    pub async fn from_env() -> Result<Vec<SocialClient>> {
+        let mut clients = Vec::new();
+
+        if let Some(bot_token) = optional_var("TELEGRAM_BOT_TOKEN") {
+            let auth = TelegramAuth {
+                bot_token,
+                chat_id: required_var("TELEGRAM_CHAT_ID")?
+                    .parse::<i64>()
+                    .context("`TELEGRAM_CHAT_ID` must be a numeric chat id")?,
+                base_url: optional_var("TELEGRAM_API_URL")
+                    .unwrap_or_else(|| TELEGRAM_API_URL.to_string()),
+            };
+            let client = TelegramClient::new(auth)
+                .await
+                .context("Failed to connect to telegram")?;
+            clients.push(SocialClient::Telegram(client));
+        }
+
+        if let Some(bot_token) = optional_var("DISCORD_BOT_TOKEN") {
+            let auth = DiscordAuth {
+                bot_token,
+                channel_id: required_var("DISCORD_CHANNEL_ID")?,
+                base_url: optional_var("DISCORD_API_URL")
+                    .unwrap_or_else(|| DISCORD_API_URL.to_string()),
+            };
+            let client = DiscordClient::new(auth)
+                .await
+                .context("Failed to connect to discord")?;
+            clients.push(SocialClient::Discord(client));
+        }
+
+        if let Some(handle) = optional_var("BLUESKY_HANDLE") {
+            let auth = BlueskyAuth {
+                handle,
+                app_password: required_var("BLUESKY_APP_PASSWORD")?,
+                base_url: optional_var("BLUESKY_SERVICE_URL")
+                    .unwrap_or_else(|| DEFAULT_SERVICE_URL.to_string()),
+            };
+            let client = BlueskyClient::new(auth)
+                .await
+                .context("Failed to connect to bluesky")?;
+            clients.push(SocialClient::Bluesky(client));
+        }
+
+        if let Some(access_token) = optional_var("MASTODON_ACCESS_TOKEN") {
+            let auth = MastodonAuth {
+                access_token,
+                base_url: required_var("MASTODON_INSTANCE_URL")?,
+            };
+            let client = MastodonClient::new(auth)
+                .await
+                .context("Failed to connect to mastodon")?;
+            clients.push(SocialClient::Mastodon(client));
+        }
+
+        Ok(clients)
+    }
+
+    pub fn platform(&self) -> Platform {
+        match self {
+            SocialClient::Telegram(_) => Platform::Telegram,
+            SocialClient::Discord(_) => Platform::Discord,
+            SocialClient::Bluesky(_) => Platform::Bluesky,
+            SocialClient::Mastodon(_) => Platform::Mastodon,
+        }
+    }
+
+    // Posts `text`, as a thread if it is longer than the platform allows.
+    // Returns the id of the first post.
+    pub async fn publish(&self, text: &str) -> Result<String> {
+        self.post_thread(text, None).await
+    }
+
+    pub async fn reply(&self, mention: &SocialPost, text: &str) -> Result<String> {
+        let text = match self {
+            SocialClient::Mastodon(_) => mastodon::with_mention(mention, text),
+            _ => text.to_string(),
+        };
+        self.post_thread(&text, Some(&mention.id)).await
+    }
+
+    // Fetches mentions of and replies to the agent newer than the cursor.
+    pub async fn fetch_mentions(&self) -> Result<Vec<SocialPost>> {
+        match self {
+            SocialClient::Telegram(client) => client.fetch_mentions().await,
+            SocialClient::Discord(client) => client.fetch_mentions().await,
+            SocialClient::Bluesky(client) => client.fetch_mentions().await,
+            SocialClient::Mastodon(client) => client.fetch_mentions().await,
+        }
+    }
+
+    // Returns the posts `mention` replies to, oldest first, without `mention` itself.
+    pub async fn fetch_thread(
+        &self,
+        mention: &SocialPost,
+        max_depth: usize,
+    ) -> Result<Vec<SocialPost>> {
+        match self {
+            SocialClient::Telegram(client) => Ok(client.fetch_thread(mention, max_depth)),
+            SocialClient::Discord(client) => client.fetch_thread(mention, max_depth).await,
+            SocialClient::Bluesky(client) => client.fetch_thread(mention, max_depth).await,
+            SocialClient::Mastodon(client) => client.fetch_thread(mention, max_depth).await,
+        }
+    }
+
+    // Position of the mention polling, saved across restarts.
+    pub fn cursor(&self) -> Option<String> {
+        match self {
+            SocialClient::Telegram(client) => client.cursor(),
+            SocialClient::Discord(client) => client.cursor(),
+            SocialClient::Bluesky(client) => client.cursor(),
+            SocialClient::Mastodon(client) => client.cursor(),
+        }
+    }
+
+    pub fn set_cursor(&self, cursor: &str) {
+        match self {
+            SocialClient::Telegram(client) => client.set_cursor(cursor),
+            SocialClient::Discord(client) => client.set_cursor(cursor),
+            SocialClient::Bluesky(client) => client.set_cursor(cursor),
+            SocialClient::Mastodon(client) => client.set_cursor(cursor),
+        }
+        info!(
+            "[{}_CLIENT] Resuming mentions after {}",
+            self.platform().tag(),
+            cursor
+        );
+    }
+
+    pub fn kill(&self) -> Result<()> {
+        match self {
+            SocialClient::Telegram(client) => client.http().kill(),
+            SocialClient::Discord(client) => client.http().kill(),
+            SocialClient::Bluesky(client) => client.http().kill(),
+            SocialClient::Mastodon(client) => client.http().kill(),
+        }
+    }
+
+    // Splits `text` to the platform's length limit and posts each part as a reply to
+    // the previous one. Once the first part is out, a failing part cuts the thread short
+    // instead of failing the call, so the post isn't published a second time.
+    async fn post_thread(&self, text: &str, reply_to: Option<&str>) -> Result<String> {
+        let mut first_id: Option<String> = None;
+        let mut reply_to = reply_to.map(str::to_string);
+
+        let parts = split_message(text, self.platform().max_chars());
+        for (idx, part) in parts.iter().enumerate() {
+            let posted = match self {
+                SocialClient::Telegram(client) => client.post(part, reply_to.as_deref()).await,
+                SocialClient::Discord(client) => client.post(part, reply_to.as_deref()).await,
+                SocialClient::Bluesky(client) => client.post(part, reply_to.as_deref()).await,
+                SocialClient::Mastodon(client) => client.post(part, reply_to.as_deref()).await,
+            };
+            let id = match (posted, &first_id) {
+                (Ok(id), _) => id,
+                (Err(e), Some(first_id)) => {
+                    warn!(
+                        "[{}_CLIENT] Thread cut short after {} of {} parts: {}",
+                        self.platform().tag(),
+                        idx,
+                        parts.len(),
+                        e
+                    );
+                    return Ok(first_id.clone());
+                }
+                (Err(e), None) => return Err(e),
+            };
+            first_id.get_or_insert_with(|| id.clone());
+            reply_to = Some(id);
+        }
+
+        first_id.ok_or_else(|| anyhow!("nothing to post"))
+    }
+}
+
+// Splits `text` into parts of at most `max_chars` characters, breaking between words.
+// Words longer than a whole part are broken up.
+pub fn split_message(text: &str, max_chars: usize) -> Vec<String> {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return vec![text.to_string()];
+    }
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    for word in text.split(' ') {
+        let mut word = word.to_string();
+        while word.chars().count() > max_chars {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            let idx = word
+                .char_indices()
+                .nth(max_chars)
+                .map_or(word.len(), |(idx, _)| idx);
+            parts.push(word[..idx].to_string());
+            word = word[idx..].to_string();
+        }
+
+        if current.is_empty() {
+            current = word;
+        } else if current.chars().count() + 1 + word.chars().count() <= max_chars {
+            current.push(' ');
+            current.push_str(&word);
+        } else {
+            parts.push(std::mem::replace(&mut current, word));
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+        .into_iter()
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+// HTTP transport shared by the platform clients.
+pub struct Http {
+    platform: Platform,
+    client: reqwest::Client,
+    killed: AtomicBool,
+}
+
+impl Http {
+    pub fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            client: reqwest::Client::new(),
+            killed: AtomicBool::new(false),
+        }
+    }
+
+    // Sends the request built by `request` and parses the JSON response. Retries follow
+    // `retry::send`: 429s wait for `Retry-After`, and requests other than GETs, such as
+    // the ones creating posts, are never sent again once they may have been applied.
+    pub async fn send<T: DeserializeOwned>(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> Result<T> {
+        let tag = format!("{}_CLIENT", self.platform.tag());
+        if self.killed.load(Ordering::SeqCst) {
+            return Err(anyhow!("[{}] client has been killed", tag));
+        }
+
+        let response = retry::send(
+            &tag,
+            &self.client,
+            || async { Ok(request(&self.client).build()?) },
+            |response| {
+                if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                    telemetry::rate_limited(self.platform.name());
+                }
+                response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|val| val.to_str().ok())
+                    .and_then(|val| val.parse::<f64>().ok())
+                    .map(Duration::from_secs_f64)
+            },
+        )
+        .await?;
+
+        Ok(response.json::<T>().await?)
+    }
+
+    // Fails every later request, so nothing is posted after shutdown.
+    pub fn kill(&self) -> Result<()> {
+        if self.killed.swap(true, Ordering::SeqCst) {
+            return Err(anyhow!(
+                "[{}_CLIENT] client was already killed",
+                self.platform.tag()
+            ));
+        }
+        info!("[{}_CLIENT] Client killed", self.platform.tag());
+        Ok(())
+    }
+}
+
+fn optional_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|val| !val.is_empty())
+}
+
+fn required_var(key: &str) -> Result<String> {
+    optional_var(key).ok_or_else(|| anyhow!("`{}` is a required environment variable", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fits(parts: &[String], max_chars: usize) -> bool {
+        parts.iter().all(|part| part.chars().count() <= max_chars)
+    }
+
+    #[test]
+    fn short_message_is_kept_whole() {
+        assert_eq!(split_message("  hello world ", 20), vec!["hello world"]);
+    }
+
+    #[test]
+    fn message_is_split_between_words() {
+        let parts = split_message("the quick brown fox jumps over the lazy dog", 15);
+
+        assert_eq!(
+            parts,
+            vec!["the quick brown", "fox jumps over", "the lazy dog"]
+        );
+    }
+
+    #[test]
+    fn word_longer_than_a_part_is_broken_up() {
+        let parts = split_message("see https://example.com/a/very/long/path ok", 10);
+
+        assert!(fits(&parts, 10));
+        assert_eq!(parts[0], "see");
+        assert_eq!(parts[1], "https://ex");
+        assert_eq!(parts.last().unwrap(), "g/path ok");
+        assert_eq!(
+            parts.concat().replace(' ', ""),
+            "seehttps://example.com/a/very/long/pathok"
+        );
+    }
+
+    #[test]
+    fn multibyte_text_is_split_on_characters() {
+        let text = "日本語のテキストです ".repeat(5) + &"🦀".repeat(12);
+        let parts = split_message(&text, 8);
+
+        assert!(fits(&parts, 8));
+        assert_eq!(parts.last().unwrap(), "🦀🦀🦀🦀");
+        assert_eq!(parts.concat().replace(' ', ""), text.replace(' ', ""));
+    }
+}
@@ -0,0 +1 @@
+pub mod telegram;
\ No newline at end of file
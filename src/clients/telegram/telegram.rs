@@ -0,0 +1,401 @@
+use crate::clients::social::{Http, Platform, SocialPost};
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{collections::VecDeque, sync::Mutex};
+
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+// Messages remembered to rebuild reply chains, as the Bot API can't look up a message by id.
+const HISTORY_SIZE: usize = 500;
+
+pub struct TelegramAuth {
+    pub bot_token: String,
+    // Group or channel the agent posts to and answers in.
+    pub chat_id: i64,
+    pub base_url: String,
+}
+
+pub struct Client {
+    http: Http,
+    // Base url including the bot token.
+    bot_url: String,
+    chat_id: i64,
+    bot_id: i64,
+    bot_username: String,
+    // Id of the next update to fetch.
+    offset: Mutex<i64>,
+    history: Mutex<VecDeque<SocialPost>>,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: i64,
+    first_name: String,
+    username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    from: Option<User>,
+    text: Option<String>,
+    reply_to_message: Option<Box<Message>>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+impl Client {
+    pub async fn new(auth: TelegramAuth) -> Result<Self> {
+        let mut client = Self {
+            http: Http::new(Platform::Telegram),
+            bot_url: format!(
+                "{}/bot{}",
+                auth.base_url.trim_end_matches('/'),
+                auth.bot_token
+            ),
+            chat_id: auth.chat_id,
+            bot_id: 0,
+            bot_username: String::new(),
+            offset: Mutex::new(0),
+            history: Mutex::new(VecDeque::new()),
+        };
+
+        let bot = client.get::<User>("getMe", &[]).await?;
+        client.bot_id = bot.id;
+        client.bot_username = bot.username.unwrap_or(bot.first_name);
+        info!(
+            "[TELEGRAM_CLIENT] Authenticated as bot @{}",
+            client.bot_username
+        );
+
+        // Start after the latest update so old messages aren't answered.
+        let latest = client
+            .get::<Vec<Update>>(
+                "getUpdates",
+                &[("offset", "-1".to_string()), ("timeout", "0".to_string())],
+            )
+            .await?;
+        if let Some(update) = latest.last() {
+            *client.offset.lock().unwrap() = update.update_id + 1;
+        }
+
+        Ok(client)
+    }
+
+    pub fn http(&self) -> &Http {
+        &self.http
+    }
+
+    pub async fn post(&self, text: &str, reply_to: Option<&str>) -> Result<String> {
+        let mut body = json!({ "chat_id": self.chat_id, "text": text });
+        if let Some(reply_to) = reply_to {
+            body["reply_to_message_id"] = json!(reply_to.parse::<i64>()?);
+        }
+
+        let message = self.post_json::<Message>("sendMessage", body).await?;
+        let post = self.to_post(&message);
+        info!("[TELEGRAM_CLIENT] Agent sent message (ID: {})", post.id);
+        self.remember(post.clone());
+
+        Ok(post.id)
+    }
+
+    // Messages in the chat that mention the bot or reply to one of its messages.
+    pub async fn fetch_mentions(&self) -> Result<Vec<SocialPost>> {
+        let offset = *self.offset.lock().unwrap();
+        let updates = self
+            .get::<Vec<Update>>(
+                "getUpdates",
+                &[
+                    ("offset", offset.to_string()),
+                    ("timeout", "0".to_string()),
+                    ("allowed_updates", json!(["message"]).to_string()),
+                ],
+            )
+            .await?;
+        if let Some(update) = updates.last() {
+            *self.offset.lock().unwrap() = update.update_id + 1;
+        }
+
+        let handle = format!("@{}", self.bot_username.to_lowercase());
+        let mut mentions = Vec::new();
+        for message in updates.into_iter().filter_map(|update| update.message) {
+            if message.chat.id != self.chat_id || message.text.is_none() {
+                continue;
+            }
+            if let Some(replied_to) = &message.reply_to_message {
+                self.remember(self.to_post(replied_to));
+            }
+
+            let mut post = self.to_post(&message);
+            self.remember(post.clone());
+
+            let replies_to_bot = message
+                .reply_to_message
+                .as_ref()
+                .and_then(|replied_to| replied_to.from.as_ref())
+                .is_some_and(|user| user.id == self.bot_id);
+            if post.is_own || !(replies_to_bot || post.text.to_lowercase().contains(&handle)) {
+                continue;
+            }
+
+            post.text = strip_handle(&post.text, &handle);
+            mentions.push(post);
+        }
+        info!(
+            "[TELEGRAM_CLIENT] Agent fetched {} mentions",
+            mentions.len()
+        );
+
+        Ok(mentions)
+    }
+
+    // Follows the reply chain through the messages seen so far.
+    pub fn fetch_thread(&self, mention: &SocialPost, max_depth: usize) -> Vec<SocialPost> {
+        let history = self.history.lock().unwrap();
+        let mut thread = Vec::new();
+        let mut next_id = mention.in_reply_to_id.clone();
+
+        while let Some(id) = next_id.take() {
+            if thread.len() >= max_depth {
+                break;
+            }
+            let Some(post) = history.iter().find(|post| post.id == id) else {
+                break;
+            };
+            next_id = post.in_reply_to_id.clone();
+            thread.push(post.clone());
+        }
+
+        thread.reverse();
+        thread
+    }
+
+    pub fn cursor(&self) -> Option<String> {
+        Some(self.offset.lock().unwrap().to_string())
+    }
+
+    pub fn set_cursor(&self, cursor: &str) {
+        if let Ok(offset) = cursor.parse::<i64>() {
+            *self.offset.lock().unwrap() = offset;
+        }
+    }
+
+    // Reads go out as GETs, so they are retried after timeouts and server errors.
+    async fn get<T: DeserializeOwned>(&self, method: &str, query: &[(&str, String)]) -> Result<T> {
+        let url = format!("{}/{}", self.bot_url, method);
+        let response = self
+            .http
+            .send::<ApiResponse<T>>(|http| http.get(&url).query(query))
+            .await?;
+        unwrap_result(method, response)
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T> {
+        let url = format!("{}/{}", self.bot_url, method);
+        let response = self
+            .http
+            .send::<ApiResponse<T>>(|http| http.post(&url).json(&body))
+            .await?;
+        unwrap_result(method, response)
+    }
+
+    fn to_post(&self, message: &Message) -> SocialPost {
+        let author_id = message.from.as_ref().map_or(0, |user| user.id);
+        SocialPost {
+            id: message.message_id.to_string(),
+            text: message.text.clone().unwrap_or_default(),
+            author_id: author_id.to_string(),
+            author_name: message
+                .from
+                .as_ref()
+                .map(|user| user.username.clone().unwrap_or(user.first_name.clone()))
+                .unwrap_or_default(),
+            in_reply_to_id: message
+                .reply_to_message
+                .as_ref()
+                .map(|replied_to| replied_to.message_id.to_string()),
+            is_own: author_id == self.bot_id,
+        }
+    }
+
+    fn remember(&self, post: SocialPost) {
+        let mut history = self.history.lock().unwrap();
+        if history.iter().any(|seen| seen.id == post.id) {
+            return;
+        }
+        history.push_back(post);
+        if history.len() > HISTORY_SIZE {
+            history.pop_front();
+        }
+    }
+}
+
+// The Bot API answers errors with `ok: false` and a description.
+fn unwrap_result<T>(method: &str, response: ApiResponse<T>) -> Result<T> {
+    match response.result {
+        Some(result) if response.ok => Ok(result),
+        _ => Err(anyhow!(
+            "[TELEGRAM_CLIENT] {} failed: {}",
+            method,
+            response.description.unwrap_or_default()
+        )),
+    }
+}
+
+// Removes the bot's `@handle` (matched case-insensitively) from a message.
+fn strip_handle(text: &str, handle: &str) -> String {
+    text.split_whitespace()
+        .filter(|word| !word.to_lowercase().starts_with(handle))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{fake_server::FakeServer, retry::UnknownOutcome};
+
+    const CHAT_ID: i64 = -100;
+
+    async fn connect(server: &FakeServer) -> Client {
+        server
+            .on(
+                "GET",
+                "/botTOKEN/getMe",
+                200,
+                json!({ "ok": true, "result": { "id": 1, "first_name": "Lore", "username": "lore_bot" } }),
+            )
+            .on(
+                "GET",
+                "/botTOKEN/getUpdates",
+                200,
+                json!({ "ok": true, "result": [{ "update_id": 41 }] }),
+            );
+        Client::new(TelegramAuth {
+            bot_token: "TOKEN".to_string(),
+            chat_id: CHAT_ID,
+            base_url: server.url.clone(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn message(id: i64, from: i64, username: &str, text: &str) -> serde_json::Value {
+        json!({
+            "message_id": id,
+            "chat": { "id": CHAT_ID },
+            "from": { "id": from, "first_name": username, "username": username },
+            "text": text,
+        })
+    }
+
+    #[tokio::test]
+    async fn posts_replies_to_the_chat() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "POST",
+            "/botTOKEN/sendMessage",
+            200,
+            json!({ "ok": true, "result": message(7, 1, "lore_bot", "hello") }),
+        );
+
+        let id = client.post("hello", Some("5")).await.unwrap();
+
+        assert_eq!(id, "7");
+        let sent = server.requests("POST", "/botTOKEN/sendMessage")[0].json();
+        assert_eq!(sent["chat_id"], CHAT_ID);
+        assert_eq!(sent["reply_to_message_id"], 5);
+        assert_eq!(client.cursor().as_deref(), Some("42"));
+    }
+
+    #[tokio::test]
+    async fn fetches_mentions_and_replies_to_the_bot() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        let mut reply = message(11, 3, "bob", "and you?");
+        reply["reply_to_message"] = message(10, 1, "lore_bot", "earlier");
+        let mut other_chat = message(12, 2, "alice", "@lore_bot elsewhere");
+        other_chat["chat"]["id"] = json!(-200);
+        server.on(
+            "GET",
+            "/botTOKEN/getUpdates",
+            200,
+            json!({ "ok": true, "result": [
+                { "update_id": 42, "message": message(9, 2, "alice", "@Lore_Bot what is lore?") },
+                { "update_id": 43, "message": reply },
+                { "update_id": 44, "message": message(13, 2, "alice", "just chatting") },
+                { "update_id": 45, "message": other_chat },
+            ] }),
+        );
+
+        let mentions = client.fetch_mentions().await.unwrap();
+
+        assert_eq!(
+            mentions
+                .iter()
+                .map(|post| (post.id.as_str(), post.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("9", "what is lore?"), ("11", "and you?")]
+        );
+        assert!(server.requests("GET", "/botTOKEN/getUpdates")[1]
+            .query
+            .contains("offset=42"));
+        assert_eq!(client.cursor().as_deref(), Some("46"));
+        let thread = client.fetch_thread(&mentions[1], 8);
+        assert_eq!(thread.len(), 1);
+        assert!(thread[0].is_own);
+    }
+
+    #[tokio::test]
+    async fn surfaces_api_errors() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on(
+            "POST",
+            "/botTOKEN/sendMessage",
+            200,
+            json!({ "ok": false, "description": "Bad Request: chat not found" }),
+        );
+
+        let e = client.post("hello", None).await.unwrap_err();
+
+        assert!(e.to_string().contains("chat not found"));
+    }
+
+    #[tokio::test]
+    async fn never_resends_a_message_after_a_server_error() {
+        let server = FakeServer::start();
+        let client = connect(&server).await;
+        server.on("POST", "/botTOKEN/sendMessage", 502, json!({}));
+
+        let e = client.post("hello", None).await.unwrap_err();
+
+        assert!(e.downcast_ref::<UnknownOutcome>().is_some());
+        assert_eq!(server.requests("POST", "/botTOKEN/sendMessage").len(), 1);
+    }
+}
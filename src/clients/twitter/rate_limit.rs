@@ -0,0 +1,359 @@
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, env};
+
+// Header prefixes of the rate-limit windows Twitter reports: the per-endpoint 15 minute
+// window and the 24 hour user/app limits returned when posting.
+const LIMIT_HEADERS: [&str; 3] = ["x-rate-limit", "x-user-limit-24hour", "x-app-limit-24hour"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    PostTweet,
+    Mentions,
+    TweetLookup,
+    Timeline,
+    // The agent's own tweets.
+    UserTweets,
+    UserLookup,
+    MediaUpload,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    remaining: u32,
+    reset_at: DateTime<Utc>,
+}
+
+// Tweets posted and read in the current month (UTC). Callers persist it so monthly
+// caps survive restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthlyUsage {
+    pub month: String,
+    pub posts: u32,
+    pub reads: u32,
+}
+
+// Monthly caps of the API access tier, from `TWITTER_API_TIER` (free, basic, pro) and
+// optionally overridden by `TWITTER_MONTHLY_POST_CAP` / `TWITTER_MONTHLY_READ_CAP`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MonthlyCaps {
+    pub posts: Option<u32>,
+    pub reads: Option<u32>,
+}
+
+impl MonthlyCaps {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let (posts, reads) = match var("TWITTER_API_TIER")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "free" => (Some(500), Some(100)),
+            "basic" => (Some(3_000), Some(10_000)),
+            "pro" => (Some(300_000), Some(1_000_000)),
+            _ => (None, None),
+        };
+        let cap = |key: &str| var(key).and_then(|val| val.parse::<u32>().ok());
+
+        Self {
+            posts: cap("TWITTER_MONTHLY_POST_CAP").or(posts),
+            reads: cap("TWITTER_MONTHLY_READ_CAP").or(reads),
+        }
+    }
+}
+
+// Remaining quota per endpoint, as reported by response headers, plus monthly usage.
+pub struct RateLimits {
+    windows: HashMap<(Endpoint, &'static str), Window>,
+    caps: MonthlyCaps,
+    usage: MonthlyUsage,
+}
+
+impl RateLimits {
+    pub fn new(caps: MonthlyCaps) -> Self {
+        Self {
+            windows: HashMap::new(),
+            caps,
+            usage: MonthlyUsage {
+                month: current_month(),
+                ..MonthlyUsage::default()
+            },
+        }
+    }
+
+    // Records the windows reported in a response's headers.
+    pub fn update(&mut self, endpoint: Endpoint, headers: &HeaderMap) {
+        let header = |name: String| {
+            headers
+                .get(name)
+                .and_then(|val| val.to_str().ok())
+                .and_then(|val| val.parse::<i64>().ok())
+        };
+
+        for prefix in LIMIT_HEADERS {
+            let remaining = header(format!("{prefix}-remaining"));
+            let reset_at = header(format!("{prefix}-reset"))
+                .and_then(|reset| Utc.timestamp_opt(reset, 0).single());
+            if let (Some(remaining), Some(reset_at)) = (remaining, reset_at) {
+                self.windows.insert(
+                    (endpoint, prefix),
+                    Window {
+                        remaining: remaining.max(0) as u32,
+                        reset_at,
+                    },
+                );
+            }
+        }
+    }
+
+    // Marks the endpoint as exhausted until `reset_at`, e.g. after a 429.
+    pub fn exhaust(&mut self, endpoint: Endpoint, reset_at: DateTime<Utc>) {
+        self.windows.insert(
+            (endpoint, LIMIT_HEADERS[0]),
+            Window {
+                remaining: 0,
+                reset_at,
+            },
+        );
+    }
+
+    // When the last exhausted window of the endpoint resets, if any.
+    pub fn reset_at(&self, endpoint: Endpoint) -> Option<DateTime<Utc>> {
+        self.windows
+            .iter()
+            .filter(|((window_endpoint, _), window)| {
+                *window_endpoint == endpoint && window.remaining == 0
+            })
+            .map(|(_, window)| window.reset_at)
+            .max()
+    }
+
+    // When the endpoint can be called again, or `None` if it has quota left.
+    pub fn blocked_until(&mut self, endpoint: Endpoint) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        self.roll_month();
+
+        let monthly_cap_reached = match endpoint {
+            Endpoint::PostTweet => self.caps.posts.is_some_and(|cap| self.usage.posts >= cap),
+            Endpoint::Mentions
+            | Endpoint::TweetLookup
+            | Endpoint::Timeline
+            | Endpoint::UserTweets => self.caps.reads.is_some_and(|cap| self.usage.reads >= cap),
+            Endpoint::UserLookup | Endpoint::MediaUpload => false,
+        };
+        if monthly_cap_reached {
+            return Some(next_month_start(now));
+        }
+
+        self.reset_at(endpoint).filter(|reset_at| *reset_at > now)
+    }
+
+    pub fn record_post(&mut self) {
+        self.roll_month();
+        self.usage.posts += 1;
+    }
+
+    pub fn record_reads(&mut self, count: usize) {
+        self.roll_month();
+        self.usage.reads += count as u32;
+    }
+
+    pub fn usage(&self) -> MonthlyUsage {
+        self.usage.clone()
+    }
+
+    // Restores usage saved by a previous run; usage from an earlier month is ignored.
+    pub fn set_usage(&mut self, usage: MonthlyUsage) {
+        if usage.month == current_month() {
+            self.usage = usage;
+        }
+    }
+
+    fn roll_month(&mut self) {
+        let month = current_month();
+        if self.usage.month != month {
+            self.usage = MonthlyUsage {
+                month,
+                ..MonthlyUsage::default()
+            };
+        }
+    }
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+fn next_month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = match now.month() {
+        12 => (now.year() + 1, 1),
+        month => (now.year(), month + 1),
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|start| start.and_utc())
+        .unwrap_or_else(|| now + ChronoDuration::days(31))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn caps(vars: &[(&str, &str)]) -> MonthlyCaps {
+        MonthlyCaps::from_vars(|key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, val)| val.to_string())
+        })
+    }
+
+    #[test]
+    fn exhausted_window_blocks_until_its_reset() {
+        let reset = Utc::now().timestamp() + 600;
+        let mut limits = RateLimits::new(MonthlyCaps::default());
+
+        limits.update(
+            Endpoint::Mentions,
+            &headers(&[
+                ("x-rate-limit-remaining", "0".to_string()),
+                ("x-rate-limit-reset", reset.to_string()),
+            ]),
+        );
+
+        assert_eq!(
+            limits
+                .blocked_until(Endpoint::Mentions)
+                .map(|at| at.timestamp()),
+            Some(reset)
+        );
+        assert_eq!(limits.blocked_until(Endpoint::Timeline), None);
+    }
+
+    #[test]
+    fn daily_limit_blocks_even_with_window_quota_left() {
+        let reset = Utc::now().timestamp() + 600;
+        let daily_reset = Utc::now().timestamp() + 3600;
+        let mut limits = RateLimits::new(MonthlyCaps::default());
+
+        limits.update(
+            Endpoint::PostTweet,
+            &headers(&[
+                ("x-rate-limit-remaining", "10".to_string()),
+                ("x-rate-limit-reset", reset.to_string()),
+                ("x-user-limit-24hour-remaining", "0".to_string()),
+                ("x-user-limit-24hour-reset", daily_reset.to_string()),
+            ]),
+        );
+
+        assert_eq!(
+            limits
+                .blocked_until(Endpoint::PostTweet)
+                .map(|at| at.timestamp()),
+            Some(daily_reset)
+        );
+    }
+
+    #[test]
+    fn incomplete_or_invalid_headers_are_ignored() {
+        let mut limits = RateLimits::new(MonthlyCaps::default());
+
+        limits.update(
+            Endpoint::Mentions,
+            &headers(&[("x-rate-limit-remaining", "0".to_string())]),
+        );
+        limits.update(
+            Endpoint::Timeline,
+            &headers(&[
+                ("x-rate-limit-remaining", "none".to_string()),
+                (
+                    "x-rate-limit-reset",
+                    (Utc::now().timestamp() + 600).to_string(),
+                ),
+            ]),
+        );
+
+        assert_eq!(limits.blocked_until(Endpoint::Mentions), None);
+        assert_eq!(limits.blocked_until(Endpoint::Timeline), None);
+    }
+
+    #[test]
+    fn window_that_has_reset_does_not_block() {
+        let mut limits = RateLimits::new(MonthlyCaps::default());
+
+        limits.update(
+            Endpoint::Mentions,
+            &headers(&[
+                ("x-rate-limit-remaining", "0".to_string()),
+                (
+                    "x-rate-limit-reset",
+                    (Utc::now().timestamp() - 1).to_string(),
+                ),
+            ]),
+        );
+
+        assert_eq!(limits.blocked_until(Endpoint::Mentions), None);
+    }
+
+    #[test]
+    fn monthly_caps_follow_the_tier_and_its_overrides() {
+        let free = caps(&[("TWITTER_API_TIER", "Free")]);
+        assert_eq!((free.posts, free.reads), (Some(500), Some(100)));
+
+        let overridden = caps(&[
+            ("TWITTER_API_TIER", "basic"),
+            ("TWITTER_MONTHLY_POST_CAP", "1000"),
+            ("TWITTER_MONTHLY_READ_CAP", "lots"),
+        ]);
+        assert_eq!(
+            (overridden.posts, overridden.reads),
+            (Some(1000), Some(10_000))
+        );
+
+        let unknown = caps(&[("TWITTER_API_TIER", "enterprise")]);
+        assert_eq!((unknown.posts, unknown.reads), (None, None));
+
+        let custom = caps(&[("TWITTER_MONTHLY_READ_CAP", "50")]);
+        assert_eq!((custom.posts, custom.reads), (None, Some(50)));
+    }
+
+    #[test]
+    fn monthly_cap_blocks_until_next_month() {
+        let mut limits = RateLimits::new(MonthlyCaps {
+            posts: Some(2),
+            reads: None,
+        });
+
+        limits.record_post();
+        assert_eq!(limits.blocked_until(Endpoint::PostTweet), None);
+        limits.record_post();
+
+        let blocked_until = limits.blocked_until(Endpoint::PostTweet).unwrap();
+        assert_eq!(blocked_until, next_month_start(Utc::now()));
+        assert_eq!(blocked_until.day(), 1);
+        assert_eq!(limits.blocked_until(Endpoint::Mentions), None);
+    }
+
+    #[test]
+    fn next_month_of_december_is_january() {
+        let now = Utc.with_ymd_and_hms(2024, 12, 15, 10, 0, 0).unwrap();
+        assert_eq!(
+            next_month_start(now),
+            Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap()
+        );
+    }
+}
@@ -1,15 +1,87 @@
-use anyhow::{Error, Result};
-use log::{error, info};
+use super::rate_limit::{Endpoint, MonthlyCaps, MonthlyUsage, RateLimits};
+use crate::clients::retry::{self, backoff, HttpError, UnknownOutcome, MAX_RETRIES};
+use crate::core::telemetry;
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Duration as ChronoDuration, SecondsFormat, Utc};
+use log::{error, info, warn};
+use reqwest::{
+    header::AUTHORIZATION,
+    multipart::{Form, Part},
+    RequestBuilder, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
 use twitter_v2::{
     authorization::{BearerToken, Oauth1aToken},
+    data::ReferencedTweetKind,
     id::NumericId,
-    Tweet, TwitterApi,
+    meta::TweetsMeta,
+    ApiPayload, Authorization, Tweet, User,
 };
 
+const API_BASE_URL: &str = "https://api.twitter.com/2";
+// Media uploads are only available on the v1.1 API.
+const UPLOAD_BASE_URL: &str = "https://upload.twitter.com/1.1/media";
+
+// Assumed window when a 429 comes without rate-limit headers.
+const DEFAULT_RATE_LIMIT_WINDOW_MINUTES: i64 = 15;
+
+type UsageSink = Box<dyn Fn(&MonthlyUsage) + Send + Sync>;
+
 pub struct Client {
     auth: Oauth1aToken,
+    http: reqwest::Client,
     user_id: NumericId,
-    latest_mention_id: NumericId,
+    latest_mention_id: Mutex<NumericId>,
+    latest_timeline_id: Mutex<Option<NumericId>>,
+    rate_limits: Mutex<RateLimits>,
+    // Told about every change of the monthly usage, so it can be persisted right away.
+    usage_sink: Option<UsageSink>,
+    killed: AtomicBool,
+}
+
+// A tweet paired with its author, resolved from the `author_id` expansion.
+#[derive(Debug, Clone)]
+pub struct AuthoredTweet {
+    pub tweet: Tweet,
+    pub author: Option<User>,
+}
+
+#[derive(Deserialize)]
+struct UploadedMedia {
+    media_id_string: String,
+}
+
+// Public metrics of one of the agent's tweets.
+#[derive(Debug, Clone, Copy)]
+pub struct TweetMetrics {
+    pub id: NumericId,
+    pub likes: u64,
+    pub retweets: u64,
+    pub replies: u64,
+    pub quotes: u64,
+    pub impressions: u64,
+}
+
+// `twitter_v2` predates `impression_count`, so metrics are read into their own types.
+#[derive(Deserialize)]
+struct MetricsTweet {
+    id: NumericId,
+    public_metrics: PublicMetrics,
+}
+
+#[derive(Deserialize)]
+struct PublicMetrics {
+    retweet_count: u64,
+    reply_count: u64,
+    like_count: u64,
+    quote_count: u64,
+    #[serde(default)]
+    impression_count: u64,
 }
 
 pub struct TwitterAuth {
@@ -17,88 +89,255 @@ pub struct TwitterAuth {
     pub api_secret: String,
     pub access_token: String,
     pub access_token_secret: String,
+    // Skips the `users/me` lookup at startup when set.
+    pub user_id: Option<u64>,
 }
 
 impl Client {
-    pub async fn new(credentials: TwitterAuth) -> Self {
+    // Startup requests go through `send`, so transient failures are retried with backoff
+    // and a Twitter outage surfaces as an error instead of a panic.
+    pub async fn new(credentials: TwitterAuth) -> Result<Self> {
         let auth = Oauth1aToken::new(
             credentials.api_key,
             credentials.api_secret,
             credentials.access_token,
             credentials.access_token_secret,
         );
-        let user_id = TwitterApi::new(auth.clone())
-            .get_users_me()
-            .send()
-            .await
-            .unwrap()
-            .into_data()
-            .expect("[TWITTER_CLIENT] fatal error occured while fetching user_id")
-            .id;
+        let mut client = Self {
+            auth,
+            http: reqwest::Client::new(),
+            user_id: NumericId::new(credentials.user_id.unwrap_or_default()),
+            latest_mention_id: Mutex::new(NumericId::new(0)),
+            latest_timeline_id: Mutex::new(None),
+            rate_limits: Mutex::new(RateLimits::new(MonthlyCaps::from_env())),
+            usage_sink: None,
+            killed: AtomicBool::new(false),
+        };
+
+        if credentials.user_id.is_none() {
+            client.user_id = client
+                .send::<User, ()>(
+                    Endpoint::UserLookup,
+                    client.http.get(format!("{API_BASE_URL}/users/me")),
+                )
+                .await
+                .map_err(|e| e.context("[TWITTER_CLIENT] failed to fetch user_id"))?
+                .into_data()
+                .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get user data"))?
+                .id;
+        }
+        info!("[TWITTER_CLIENT] Authenticated as user {}", client.user_id);
 
         // Fetch the latest mention ID
         // @todo: Make this the last replied to mention ID
-        let latest_mention_id = TwitterApi::new(auth.clone())
-            .get_user_mentions(user_id)
-            .send()
+        let latest_mention_id = client
+            .send::<Vec<Tweet>, TweetsMeta>(
+                Endpoint::Mentions,
+                client
+                    .http
+                    .get(format!("{API_BASE_URL}/users/{}/mentions", client.user_id)),
+            )
             .await
             .ok()
             .and_then(|response| response.into_data())
-            .and_then(|mentions| mentions.into_iter().map(|mention| mention.id).max())
-            .unwrap_or_else(|| NumericId::new(0));
-
-        Self {
-            auth,
-            user_id,
-            latest_mention_id,
+            .and_then(|mentions| mentions.into_iter().map(|mention| mention.id).max());
+        if let Some(latest_mention_id) = latest_mention_id {
+            *client.latest_mention_id.lock().unwrap() = latest_mention_id;
         }
+
+        Ok(client)
+    }
+
+    pub async fn publish(&self, response: &str) -> Result<NumericId> {
+        self.post_tweet(json!({ "text": response })).await
+    }
+
+    pub async fn publish_with_media(
+        &self,
+        response: &str,
+        media_ids: &[String],
+    ) -> Result<NumericId> {
+        self.post_tweet(json!({
+            "text": response,
+            "media": { "media_ids": media_ids },
+        }))
+        .await
     }
 
-    pub async fn publish(&self, response: &str) -> Result<()> {
-        let tweet = TwitterApi::new(self.auth.clone())
-            .post_tweet()
-            .text(response.to_string())
-            .send()
+    // Uploads an image and sets its alt text, returning the media id to attach to a post.
+    pub async fn upload_media(
+        &self,
+        image: &[u8],
+        mime_type: &str,
+        alt_text: &str,
+    ) -> Result<String> {
+        let media = self
+            .execute(Endpoint::MediaUpload, || {
+                let part = Part::bytes(image.to_vec())
+                    .file_name("media")
+                    .mime_str(mime_type)?;
+                Ok(self
+                    .http
+                    .post(format!("{UPLOAD_BASE_URL}/upload.json"))
+                    .multipart(Form::new().part("media", part)))
+            })
             .await?
-            .into_data()
-            .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get tweet data"))?;
+            .json::<UploadedMedia>()
+            .await?;
 
-        info!("[TWITTER_CLIENT] Agent posted tweet (ID: {})", tweet.id);
+        if !alt_text.is_empty() {
+            // Alt text is limited to 1000 characters.
+            let alt_text = alt_text.chars().take(1000).collect::<String>();
+            let body = json!({
+                "media_id": media.media_id_string,
+                "alt_text": { "text": alt_text },
+            });
+            self.execute(Endpoint::MediaUpload, || {
+                Ok(self
+                    .http
+                    .post(format!("{UPLOAD_BASE_URL}/metadata/create.json"))
+                    .json(&body))
+            })
+            .await?;
+        }
+        info!(
+            "[TWITTER_CLIENT] Uploaded media (ID: {})",
+            media.media_id_string
+        );
 
-        Ok(())
+        Ok(media.media_id_string)
     }
 
-    pub async fn reply(&mut self, id: NumericId, response: &str) -> Result<()> {
-        let tweet = TwitterApi::new(self.auth.clone())
-            .post_tweet()
-            .in_reply_to_tweet_id(id)
-            .text(response.to_string())
-            .send()
-            .await?
-            .into_data()
-            .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get tweet data"))?;
+    pub async fn reply(&self, id: NumericId, response: &str) -> Result<NumericId> {
+        self.post_tweet(json!({
+            "text": response,
+            "reply": { "in_reply_to_tweet_id": id.to_string() },
+        }))
+        .await
+    }
 
-        info!("[TWITTER_CLIENT] Agent posted tweet (ID: {})", tweet.id);
+    // Returns the id of the new tweet. When a create times out or gets a 5xx it may still
+    // have gone through, so it is only sent again if the tweet isn't on the agent's own
+    // timeline, and not at all if the timeline can't be checked.
+    async fn post_tweet(&self, body: serde_json::Value) -> Result<NumericId> {
+        let text = body["text"].as_str().unwrap_or_default();
+        let mut attempt = 0;
+        loop {
+            let sent_at = Utc::now();
+            let error = match self
+                .send::<Tweet, ()>(
+                    Endpoint::PostTweet,
+                    self.http.post(format!("{API_BASE_URL}/tweets")).json(&body),
+                )
+                .await
+            {
+                Ok(response) => {
+                    let tweet = response
+                        .into_data()
+                        .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get tweet data"))?;
+                    self.record_usage(|limits| limits.record_post());
+                    info!("[TWITTER_CLIENT] Agent posted tweet (ID: {})", tweet.id);
+                    return Ok(tweet.id);
+                }
+                Err(e) if e.is::<UnknownOutcome>() && attempt < MAX_RETRIES => e,
+                Err(e) => return Err(e),
+            };
 
-        Ok(())
+            warn!("{}. Checking the timeline before posting again...", error);
+            tokio::time::sleep(backoff(attempt)).await;
+            match self.find_own_tweet(text, sent_at).await {
+                Ok(Some(id)) => {
+                    self.record_usage(|limits| limits.record_post());
+                    info!("[TWITTER_CLIENT] Tweet went through after all (ID: {})", id);
+                    return Ok(id);
+                }
+                Ok(None) => attempt += 1,
+                Err(e) => {
+                    error!(
+                        "[TWITTER_CLIENT] Failed to check the timeline: {}. Not posting again...",
+                        e
+                    );
+                    return Err(error);
+                }
+            }
+        }
     }
 
-    //@todo: find most efficient way to reply to all mentions without replying to the same one multiple times.
-    pub async fn fetch_mentions(&mut self, count: usize) -> Result<Vec<Tweet>> {
-        let mentions = TwitterApi::new(self.auth.clone())
-            .get_user_mentions(self.user_id)
-            .since_id(self.latest_mention_id)
-            .max_results(count)
-            .send()
-            .await?
+    // Looks for a tweet with `text` the agent posted since `since`.
+    async fn find_own_tweet(&self, text: &str, since: DateTime<Utc>) -> Result<Option<NumericId>> {
+        // Allows for clock skew between the agent and Twitter.
+        let start_time = since - ChronoDuration::minutes(1);
+        let response = self
+            .send::<Vec<Tweet>, TweetsMeta>(
+                Endpoint::UserTweets,
+                self.http
+                    .get(format!("{API_BASE_URL}/users/{}/tweets", self.user_id))
+                    .query(&[
+                        (
+                            "start_time",
+                            start_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        ),
+                        ("max_results", "5".to_string()),
+                    ]),
+            )
+            .await?;
+
+        let tweets = response.into_data().unwrap_or_default();
+        self.record_usage(|limits| limits.record_reads(tweets.len()));
+        Ok(tweets
+            .into_iter()
+            .find(|tweet| same_text(&tweet.text, text))
+            .map(|tweet| tweet.id))
+    }
+
+    // Fetches mentions newer than the cursor along with their authors' public metrics.
+    // Mentions are queued by the caller, so advancing the cursor here doesn't drop them.
+    pub async fn fetch_mentions(&self, count: usize) -> Result<Vec<AuthoredTweet>> {
+        let since_id = *self.latest_mention_id.lock().unwrap();
+        let response = self
+            .send::<Vec<Tweet>, TweetsMeta>(
+                Endpoint::Mentions,
+                self.http
+                    .get(format!("{API_BASE_URL}/users/{}/mentions", self.user_id))
+                    .query(&[
+                        ("since_id", since_id.to_string()),
+                        ("max_results", count.clamp(5, 100).to_string()),
+                        ("expansions", "author_id".to_string()),
+                        (
+                            "tweet.fields",
+                            "author_id,conversation_id,created_at,entities,referenced_tweets"
+                                .to_string(),
+                        ),
+                        ("user.fields", "username,public_metrics".to_string()),
+                    ]),
+            )
+            .await?;
+
+        let users = response
+            .includes()
+            .and_then(|includes| includes.users.clone())
+            .unwrap_or_default();
+        // No `data` means there are no mentions newer than the cursor.
+        let mentions = response
             .into_data()
-            .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get mentions from tweet"))?;
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tweet| AuthoredTweet {
+                author: users
+                    .iter()
+                    .find(|user| Some(user.id) == tweet.author_id)
+                    .cloned(),
+                tweet,
+            })
+            .collect::<Vec<_>>();
+        self.record_usage(|limits| limits.record_reads(mentions.len()));
 
-        if let Some(max_id) = mentions.iter().map(|mention| mention.id).max() {
-            self.latest_mention_id = max_id;
+        if let Some(max_id) = mentions.iter().map(|mention| mention.tweet.id).max() {
+            let mut latest_mention_id = self.latest_mention_id.lock().unwrap();
+            *latest_mention_id = max_id.max(*latest_mention_id);
             info!(
                 "[TWITTER_CLIENT] Updated latest_mention_id to {}",
-                self.latest_mention_id
+                latest_mention_id
             );
         }
         info!("[TWITTER_CLIENT] Agent fetched all mentions");
@@ -106,22 +345,331 @@ impl Client {
         Ok(mentions)
     }
 
-    pub async fn fetch_timeline(&mut self, count: usize) -> Result<Vec<Tweet>> {
-        let timeline = TwitterApi::new(self.auth.clone())
-            .get_user_tweets(self.user_id)
-            .since_id(self.latest_mention_id)
-            .max_results(count)
-            .send()
-            .await?
+    // Walks up the reply chain starting at `in_reply_to_id` and returns the thread oldest first.
+    // The conversation root is always included when it is beyond `max_depth`.
+    // Ancestors that can't be fetched (deleted, protected) end the walk early.
+    pub async fn fetch_thread(
+        &self,
+        in_reply_to_id: NumericId,
+        conversation_id: Option<NumericId>,
+        max_depth: usize,
+    ) -> Result<Vec<AuthoredTweet>> {
+        let mut thread = Vec::new();
+        let mut next_id = Some(in_reply_to_id);
+
+        while let Some(id) = next_id.take() {
+            if thread.len() >= max_depth {
+                break;
+            }
+
+            match self.fetch_tweet(id).await {
+                Ok(tweet) => {
+                    next_id = tweet
+                        .tweet
+                        .referenced_tweets
+                        .iter()
+                        .flatten()
+                        .find(|referenced| referenced.kind == ReferencedTweetKind::RepliedTo)
+                        .map(|referenced| referenced.id);
+                    thread.push(tweet);
+                }
+                Err(e) if thread.is_empty() => return Err(e),
+                Err(e) => {
+                    error!(
+                        "[TWITTER_CLIENT] Failed to fetch thread tweet {}: {}. Stopping walk...",
+                        id, e
+                    );
+                    break;
+                }
+            }
+        }
+
+        if let Some(conversation_id) = conversation_id {
+            if !thread.iter().any(|entry| entry.tweet.id == conversation_id) {
+                match self.fetch_tweet(conversation_id).await {
+                    Ok(root) => thread.push(root),
+                    Err(e) => error!(
+                        "[TWITTER_CLIENT] Failed to fetch conversation root {}: {}",
+                        conversation_id, e
+                    ),
+                }
+            }
+        }
+
+        thread.reverse();
+        info!(
+            "[TWITTER_CLIENT] Agent fetched thread of {} tweets",
+            thread.len()
+        );
+
+        Ok(thread)
+    }
+
+    // Fetches the public metrics of up to 100 tweets in one lookup. Deleted tweets are
+    // left out.
+    pub async fn fetch_tweet_metrics(&self, ids: &[NumericId]) -> Result<Vec<TweetMetrics>> {
+        let ids = ids
+            .iter()
+            .take(100)
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let response = self
+            .send::<Vec<MetricsTweet>, ()>(
+                Endpoint::TweetLookup,
+                self.http
+                    .get(format!("{API_BASE_URL}/tweets"))
+                    .query(&[("ids", ids.as_str()), ("tweet.fields", "public_metrics")]),
+            )
+            .await?;
+
+        let metrics = response
+            .into_data()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tweet| TweetMetrics {
+                id: tweet.id,
+                likes: tweet.public_metrics.like_count,
+                retweets: tweet.public_metrics.retweet_count,
+                replies: tweet.public_metrics.reply_count,
+                quotes: tweet.public_metrics.quote_count,
+                impressions: tweet.public_metrics.impression_count,
+            })
+            .collect::<Vec<_>>();
+        self.record_usage(|limits| limits.record_reads(metrics.len()));
+        info!(
+            "[TWITTER_CLIENT] Agent fetched metrics of {} tweets",
+            metrics.len()
+        );
+
+        Ok(metrics)
+    }
+
+    async fn fetch_tweet(&self, id: NumericId) -> Result<AuthoredTweet> {
+        let response = self
+            .send::<Tweet, ()>(
+                Endpoint::TweetLookup,
+                self.http
+                    .get(format!("{API_BASE_URL}/tweets/{id}"))
+                    .query(&[
+                        ("expansions", "author_id"),
+                        (
+                            "tweet.fields",
+                            "author_id,conversation_id,referenced_tweets",
+                        ),
+                        ("user.fields", "username"),
+                    ]),
+            )
+            .await?;
+        self.record_usage(|limits| limits.record_reads(1));
+
+        let author = response
+            .includes()
+            .and_then(|includes| includes.users.as_ref())
+            .and_then(|users| users.first().cloned());
+        let tweet = response
+            .into_data()
+            .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to get tweet data"))?;
+
+        Ok(AuthoredTweet { tweet, author })
+    }
+
+    pub fn user_id(&self) -> NumericId {
+        self.user_id
+    }
+
+    pub fn latest_mention_id(&self) -> NumericId {
+        *self.latest_mention_id.lock().unwrap()
+    }
+
+    // Resumes mention polling from a cursor saved by a previous run.
+    pub fn set_latest_mention_id(&self, id: NumericId) {
+        *self.latest_mention_id.lock().unwrap() = id;
+        info!("[TWITTER_CLIENT] Resuming mentions after {}", id);
+    }
+
+    // Fetches the home (reverse-chronological) timeline of accounts the agent follows.
+    pub async fn fetch_timeline(&self, count: usize) -> Result<Vec<AuthoredTweet>> {
+        let mut query = vec![
+            ("max_results", count.clamp(1, 100).to_string()),
+            ("exclude", "replies,retweets".to_string()),
+            ("expansions", "author_id".to_string()),
+            ("user.fields", "username".to_string()),
+        ];
+        if let Some(since_id) = *self.latest_timeline_id.lock().unwrap() {
+            query.push(("since_id", since_id.to_string()));
+        }
+
+        let payload = self
+            .send::<Vec<Tweet>, TweetsMeta>(
+                Endpoint::Timeline,
+                self.http
+                    .get(format!(
+                        "{API_BASE_URL}/users/{}/timelines/reverse_chronological",
+                        self.user_id
+                    ))
+                    .query(&query),
+            )
+            .await?;
+
+        let users = payload
+            .includes()
+            .and_then(|includes| includes.users.clone())
+            .unwrap_or_default();
+        let timeline = payload
             .into_data()
-            .ok_or_else(|| Error::msg("[TWITTER_CLIENT] failed to fetch timeline"))?;
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tweet| AuthoredTweet {
+                author: users
+                    .iter()
+                    .find(|user| Some(user.id) == tweet.author_id)
+                    .cloned(),
+                tweet,
+            })
+            .collect::<Vec<_>>();
+        self.record_usage(|limits| limits.record_reads(timeline.len()));
+
+        if let Some(max_id) = timeline.iter().map(|entry| entry.tweet.id).max() {
+            *self.latest_timeline_id.lock().unwrap() = Some(max_id);
+            info!("[TWITTER_CLIENT] Updated latest_timeline_id to {}", max_id);
+        }
         info!("[TWITTER_CLIENT] Agent fetched timeline");
 
         Ok(timeline)
     }
 
-    //@note: for later concurrent purposes.
+    // When the endpoint can be called again if its quota is exhausted, so callers can
+    // hold off instead of failing repeatedly.
+    pub fn blocked_until(&self, endpoint: Endpoint) -> Option<DateTime<Utc>> {
+        self.rate_limits.lock().unwrap().blocked_until(endpoint)
+    }
+
+    pub fn monthly_usage(&self) -> MonthlyUsage {
+        self.rate_limits.lock().unwrap().usage()
+    }
+
+    // Restores monthly usage saved by a previous run.
+    pub fn set_monthly_usage(&self, usage: MonthlyUsage) {
+        self.rate_limits.lock().unwrap().set_usage(usage);
+    }
+
+    // Calls `sink` with the monthly usage every time it changes.
+    pub fn on_usage_change(&mut self, sink: impl Fn(&MonthlyUsage) + Send + Sync + 'static) {
+        self.usage_sink = Some(Box::new(sink));
+    }
+
+    fn record_usage(&self, record: impl FnOnce(&mut RateLimits)) {
+        let usage = {
+            let mut rate_limits = self.rate_limits.lock().unwrap();
+            record(&mut rate_limits);
+            rate_limits.usage()
+        };
+        if let Some(sink) = &self.usage_sink {
+            sink(&usage);
+        }
+    }
+
+    async fn send<T: DeserializeOwned, M: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request: RequestBuilder,
+    ) -> Result<ApiPayload<T, M>> {
+        let response = self
+            .execute(endpoint, || {
+                request
+                    .try_clone()
+                    .ok_or_else(|| Error::msg("[TWITTER_CLIENT] request can't be retried"))
+            })
+            .await?;
+
+        Ok(response.json::<ApiPayload<T, M>>().await?)
+    }
+
+    // Signs and sends the request built by `request`, recording the rate-limit headers
+    // of every response. Retries are left to `retry::send`: 429s wait for the window to
+    // reset, and a 429 it gives up on blocks the endpoint until the reset.
+    async fn execute(
+        &self,
+        endpoint: Endpoint,
+        request: impl Fn() -> Result<RequestBuilder>,
+    ) -> Result<Response> {
+        self.ensure_alive()?;
+        if let Some(until) = self.blocked_until(endpoint) {
+            return Err(anyhow!(
+                "[TWITTER_CLIENT] {:?} quota exhausted until {}",
+                endpoint,
+                until
+            ));
+        }
+
+        let mut reset_at = None;
+        let result = retry::send(
+            "TWITTER_CLIENT",
+            &self.http,
+            || async {
+                let mut signed = request()?.build()?;
+                let authorization = self.auth.header(&signed).await?;
+                signed.headers_mut().insert(AUTHORIZATION, authorization);
+                Ok(signed)
+            },
+            |response| {
+                let mut rate_limits = self.rate_limits.lock().unwrap();
+                rate_limits.update(endpoint, response.headers());
+                if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                    return None;
+                }
+
+                telemetry::rate_limited("twitter");
+                let reset = rate_limits.reset_at(endpoint).unwrap_or_else(|| {
+                    Utc::now() + ChronoDuration::minutes(DEFAULT_RATE_LIMIT_WINDOW_MINUTES)
+                });
+                reset_at = Some(reset);
+                Some((reset - Utc::now()).to_std().unwrap_or_default())
+            },
+        )
+        .await;
+
+        match (result, reset_at) {
+            (Err(e), Some(reset_at))
+                if e.downcast_ref::<HttpError>()
+                    .is_some_and(|e| e.status == StatusCode::TOO_MANY_REQUESTS) =>
+            {
+                self.rate_limits.lock().unwrap().exhaust(endpoint, reset_at);
+                Err(anyhow!(
+                    "[TWITTER_CLIENT] {:?} rate limited until {}",
+                    endpoint,
+                    reset_at
+                ))
+            }
+            (result, _) => result,
+        }
+    }
+
+    // Stops the client once the agent is shutting down: any request made after this
+    // fails instead of reaching Twitter, so late tasks can't post after shutdown.
     pub fn kill(&self) -> Result<()> {
+        if self.killed.swap(true, Ordering::SeqCst) {
+            return Err(Error::msg("[TWITTER_CLIENT] client was already killed"));
+        }
+        info!("[TWITTER_CLIENT] Client killed");
         Ok(())
     }
+
+    fn ensure_alive(&self) -> Result<()> {
+        if self.killed.load(Ordering::SeqCst) {
+            return Err(Error::msg("[TWITTER_CLIENT] client has been killed"));
+        }
+        Ok(())
+    }
+}
+
+// Whether `posted`, as returned by the API, is the tweet sent as `text`. The API escapes
+// `&`, `<` and `>`, and replies may start with the handles they reply to.
+fn same_text(posted: &str, text: &str) -> bool {
+    let posted = posted
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    !text.trim().is_empty() && posted.trim().ends_with(text.trim())
 }
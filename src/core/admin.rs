@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::info;
+use serde_json::{json, Value};
+use std::{convert::Infallible, env, fmt::Display, future::Future, net::SocketAddr};
+use tokio::sync::{mpsc::Sender, oneshot};
+
+// Events returned by `/events` when no `limit` is given.
+const DEFAULT_EVENTS_LIMIT: usize = 50;
+
+pub struct AdminConfig {
+    pub addr: SocketAddr,
+    // Bearer token required on every request when set.
+    pub token: Option<String>,
+    // Generated replies wait in `/replies` until approved instead of being published.
+    pub approve_replies: bool,
+}
+
+impl AdminConfig {
+    // Reads `ADMIN_API_ADDR`, `ADMIN_API_TOKEN` and `ADMIN_APPROVE_REPLIES`.
+    // `None` when no address is set, which disables the API. Addresses reachable from
+    // other hosts need a token.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(addr) = env::var("ADMIN_API_ADDR")
+            .ok()
+            .filter(|val| !val.is_empty())
+        else {
+            return Ok(None);
+        };
+
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|e| anyhow!("invalid `ADMIN_API_ADDR` {}: {}", addr, e))?;
+        let token = env::var("ADMIN_API_TOKEN")
+            .ok()
+            .filter(|val| !val.is_empty());
+        if token.is_none() && !addr.ip().is_loopback() {
+            return Err(anyhow!(
+                "`ADMIN_API_ADDR` {} is reachable from other hosts, set `ADMIN_API_TOKEN` or bind to a loopback address",
+                addr
+            ));
+        }
+
+        Ok(Some(Self {
+            addr,
+            token,
+            approve_replies: env::var("ADMIN_APPROVE_REPLIES").is_ok_and(|val| val == "true"),
+        }))
+    }
+}
+
+// What an operator asked for. Requests are answered by the agent, which owns the state.
+#[derive(Debug)]
+pub enum AdminRequest {
+    Health,
+    Character,
+    Queue,
+    Events { limit: usize },
+    Stats,
+    Pause,
+    Resume,
+    Post,
+    Branch,
+    Replies,
+    Approve(u64),
+    Reject(u64),
+}
+
+pub struct AdminResponse {
+    pub status: StatusCode,
+    pub body: Value,
+}
+
+impl AdminResponse {
+    pub fn ok(body: Value) -> Self {
+        Self {
+            status: StatusCode::OK,
+            body,
+        }
+    }
+
+    // The request was taken on and is carried out by the pipeline.
+    pub fn accepted(message: impl Display) -> Self {
+        Self {
+            status: StatusCode::ACCEPTED,
+            body: json!({ "status": message.to_string() }),
+        }
+    }
+
+    pub fn error(status: StatusCode, message: impl Display) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+}
+
+pub struct AdminCall {
+    pub request: AdminRequest,
+    pub respond: oneshot::Sender<AdminResponse>,
+}
+
+// Serves the admin API until `shutdown` resolves. Each request is handed to the agent
+// over `calls` and its response is sent back as JSON.
+pub async fn serve(
+    config: &AdminConfig,
+    calls: Sender<AdminCall>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let token = config.token.clone();
+    let make_service = make_service_fn(move |_| {
+        let calls = calls.clone();
+        let token = token.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(request, calls.clone(), token.clone())
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&config.addr)?.serve(make_service);
+    info!("[ADMIN] Listening on http://{}", config.addr);
+    server.with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+async fn handle(
+    request: Request<Body>,
+    calls: Sender<AdminCall>,
+    token: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let response = match route(&request, token.as_deref()) {
+        Ok(admin_request) => call(&calls, admin_request).await,
+        Err(response) => response,
+    };
+
+    Ok(Response::builder()
+        .status(response.status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(response.body.to_string()))
+        .expect("status and content type are valid"))
+}
+
+fn route(request: &Request<Body>, token: Option<&str>) -> Result<AdminRequest, AdminResponse> {
+    if let Some(token) = token {
+        let authorized = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|val| val.to_str().ok())
+            .and_then(|val| val.strip_prefix("Bearer "))
+            == Some(token);
+        if !authorized {
+            return Err(AdminResponse::error(
+                StatusCode::UNAUTHORIZED,
+                "missing or invalid token",
+            ));
+        }
+    }
+
+    let path = request.uri().path().trim_matches('/');
+    let segments = path.split('/').collect::<Vec<&str>>();
+    let reply_id = |id: &str| {
+        id.parse::<u64>()
+            .map_err(|_| AdminResponse::error(StatusCode::BAD_REQUEST, "invalid reply id"))
+    };
+
+    match (request.method(), segments.as_slice()) {
+        (&Method::GET, ["health"]) => Ok(AdminRequest::Health),
+        (&Method::GET, ["character"]) => Ok(AdminRequest::Character),
+        (&Method::GET, ["queue"]) => Ok(AdminRequest::Queue),
+        (&Method::GET, ["events"]) => Ok(AdminRequest::Events {
+            limit: query_param(request, "limit")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(DEFAULT_EVENTS_LIMIT),
+        }),
+        (&Method::GET, ["stats"]) => Ok(AdminRequest::Stats),
+        (&Method::POST, ["pause"]) => Ok(AdminRequest::Pause),
+        (&Method::POST, ["resume"]) => Ok(AdminRequest::Resume),
+        (&Method::POST, ["post"]) => Ok(AdminRequest::Post),
+        (&Method::POST, ["branch"]) => Ok(AdminRequest::Branch),
+        (&Method::GET, ["replies"]) => Ok(AdminRequest::Replies),
+        (&Method::POST, ["replies", id, "approve"]) => reply_id(id).map(AdminRequest::Approve),
+        (&Method::POST, ["replies", id, "reject"]) => reply_id(id).map(AdminRequest::Reject),
+        _ => Err(AdminResponse::error(
+            StatusCode::NOT_FOUND,
+            format!("no route for {} /{}", request.method(), path),
+        )),
+    }
+}
+
+async fn call(calls: &Sender<AdminCall>, request: AdminRequest) -> AdminResponse {
+    let (respond, response) = oneshot::channel();
+    if calls.send(AdminCall { request, respond }).await.is_err() {
+        return AdminResponse::error(StatusCode::SERVICE_UNAVAILABLE, "agent is shutting down");
+    }
+
+    response.await.unwrap_or_else(|_| {
+        AdminResponse::error(StatusCode::SERVICE_UNAVAILABLE, "agent is shutting down")
+    })
+}
+
+fn query_param<'a>(request: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    request.uri().query()?.split('&').find_map(|pair| {
+        pair.split_once('=')
+            .filter(|(key, _)| *key == name)
+            .map(|(_, val)| val)
+    })
+}
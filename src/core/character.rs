@@ -1,8 +1,12 @@
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use lazy_static::lazy_static;
+use rand::{
+    seq::{index, SliceRandom},
+    Rng,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
     env,
     fs::{self, File, OpenOptions},
     io,
@@ -19,6 +23,13 @@ pub struct Character {
     pub lore: Vec<String>,
     pub styles: Vec<String>,
     pub topics: Vec<String>,
+    // Look of generated post images, e.g. "grainy black and white film photography".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visual_style: Option<String>,
+    // Per-platform sections merged over the base persona, keyed by platform name
+    // ("twitter", "telegram", "discord", "bluesky" or "mastodon").
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub platforms: BTreeMap<String, PlatformPersona>,
 
     // Character metadata
     #[serde(skip)]
@@ -31,6 +42,81 @@ pub struct Character {
     pub previous_posts: VecDeque<String>,
 }
 
+// Overrides of the base persona for one platform. Anything left out falls back to the
+// base character.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformPersona {
+    // Handle on the platform, without the `@`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_name: Option<String>,
+    // Replace the base styles and adjectives when not empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub styles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjectives: Vec<String>,
+    // Longest post or reply asked for, in characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    // Emoji the agent may use. None at all when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emoji: Vec<String>,
+    // Minimum minutes between posts on the platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_interval_minutes: Option<u64>,
+}
+
+// The persona prompts are built with on one platform: the base character merged with
+// the platform's section.
+#[derive(Debug, Clone)]
+pub struct Persona {
+    pub platform: String,
+    // Whether the character file has a section for the platform.
+    pub overridden: bool,
+    pub user_name: String,
+    pub styles: Vec<String>,
+    pub adjectives: Vec<String>,
+    pub max_length: usize,
+    pub emoji: Vec<String>,
+    pub post_interval_minutes: Option<u64>,
+}
+
+impl Persona {
+    // Emoji rule of the prompt.
+    pub fn emoji_rule(&self) -> String {
+        if self.emoji.is_empty() {
+            "No emojis.".to_string()
+        } else {
+            format!("No emojis other than {}.", self.emoji.join(" "))
+        }
+    }
+}
+
+// Platform name of Twitter in `platforms`.
+pub const TWITTER: &str = "twitter";
+
+// Post length asked for when the platform section doesn't set one.
+const DEFAULT_MAX_LENGTH: usize = 280;
+
+// Character entries picked for a single prompt.
+#[derive(Debug, Clone, Default)]
+pub struct PromptInputs {
+    pub lore: Vec<String>,
+    // Positions of `lore` in the character's lore.
+    pub lore_indices: Vec<usize>,
+    pub topics: Vec<String>,
+    pub adjective: String,
+    pub style: String,
+}
+
+// Values forced into prompts instead of random picks, e.g. while authoring in the CLI.
+#[derive(Debug, Clone, Default)]
+pub struct PromptOverrides {
+    pub topic: Option<String>,
+    pub style: Option<String>,
+    pub adjective: Option<String>,
+}
+
 lazy_static! {
     pub static ref POSTS_BEFORE_BRANCH: u8 = {
         env::var("POSTS_BEFORE_BRANCH")
@@ -70,6 +156,84 @@ impl Character {
         Ok(character)
     }
 
+    // Merges the platform's section, if any, over the base persona.
+    pub fn persona(&self, platform: &str) -> Persona {
+        let section = self.platforms.get(platform);
+        let overrides = section.cloned().unwrap_or_default();
+        let or_base = |list: Vec<String>, base: &Vec<String>| {
+            if list.is_empty() {
+                base.clone()
+            } else {
+                list
+            }
+        };
+
+        Persona {
+            platform: platform.to_string(),
+            overridden: section.is_some(),
+            user_name: overrides
+                .user_name
+                .unwrap_or_else(|| self.twitter_user_name.clone()),
+            styles: or_base(overrides.styles, &self.styles),
+            adjectives: or_base(overrides.adjectives, &self.adjectives),
+            max_length: overrides.max_length.unwrap_or(DEFAULT_MAX_LENGTH),
+            emoji: overrides.emoji,
+            post_interval_minutes: overrides.post_interval_minutes,
+        }
+    }
+
+    // Picks 3 lore entries, 3 topics, 1 adjective and 1 style at random, unless overridden.
+    // The adjective and style come from the persona.
+    pub fn choose_prompt_inputs(
+        &self,
+        persona: &Persona,
+        rng: &mut impl Rng,
+        overrides: &PromptOverrides,
+    ) -> PromptInputs {
+        let lore_indices = index::sample(rng, self.lore.len(), self.lore.len().min(3)).into_vec();
+        PromptInputs {
+            lore: lore_indices
+                .iter()
+                .map(|idx| self.lore[*idx].clone())
+                .collect(),
+            lore_indices,
+            topics: match &overrides.topic {
+                Some(topic) => vec![topic.clone()],
+                None => self.topics.choose_multiple(rng, 3).cloned().collect(),
+            },
+            adjective: overrides
+                .adjective
+                .clone()
+                .or_else(|| persona.adjectives.choose(rng).cloned())
+                .unwrap_or_default(),
+            style: overrides
+                .style
+                .clone()
+                .or_else(|| persona.styles.choose(rng).cloned())
+                .unwrap_or_default(),
+        }
+    }
+
+    // Lists the versions saved under `characters/` for this character, in ascending order.
+    pub fn available_versions(&self) -> Result<Vec<u8>> {
+        let mut versions = fs::read_dir("characters")?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().into_string().ok()?;
+                let stem = file_name.strip_suffix(".json")?;
+                if stem == self.character_name {
+                    return Some(1);
+                }
+                stem.strip_prefix(&format!("{}.v", self.character_name))?
+                    .parse::<u8>()
+                    .ok()
+            })
+            .collect::<Vec<u8>>();
+        versions.sort_unstable();
+        versions.dedup();
+        Ok(versions)
+    }
+
     pub fn stringify(&self) -> Result<String> {
         serde_json::to_string_pretty(self).map_err(Error::new)
     }
@@ -93,15 +257,25 @@ impl Character {
     }
 
     pub fn save(&mut self, json: &str) -> Result<Self> {
-        // Increment version
-        self.version += 1;
+        // Branch past the newest saved version, which is ahead of this one after `/version`
+        let latest = self.available_versions()?.last().copied().unwrap_or(1);
+        let version = latest
+            .max(self.version)
+            .checked_add(1)
+            .ok_or_else(|| anyhow!("no character versions left after v{}", latest))?;
 
         // Generate new file name using character name and version
         let path =
-            Path::new("characters").join(format!("{}.v{}.json", self.character_name, self.version));
+            Path::new("characters").join(format!("{}.v{}.json", self.character_name, version));
 
         // Create new character struct from input JSON
         let mut updated_character = serde_json::from_str::<Character>(json.trim())?;
+        if updated_character.visual_style.is_none() {
+            updated_character.visual_style = self.visual_style.clone();
+        }
+        if updated_character.platforms.is_empty() {
+            updated_character.platforms = self.platforms.clone();
+        }
 
         // Save file
         let temp_path = path.with_extension("tmp");
@@ -113,10 +287,16 @@ impl Character {
                 .open(&temp_path)?;
             serde_json::to_writer_pretty(&mut character_file, &updated_character)?;
         }
+        // Never replace a saved version, e.g. one written by another agent meanwhile
+        if path.exists() {
+            fs::remove_file(&temp_path)?;
+            return Err(anyhow!("{} already exists", path.display()));
+        }
         fs::rename(temp_path, path)?;
 
         // Set the previous character metadata to new one
-        updated_character.version = self.version;
+        self.version = version;
+        updated_character.version = version;
         updated_character.character_name = self.character_name.clone();
         Ok(updated_character)
     }
@@ -1,19 +1,31 @@
-use super::character::Character;
-use anyhow::{Error, Result};
+use super::character::{Character, PromptInputs, PromptOverrides, TWITTER};
+use anyhow::{anyhow, Error, Result};
 use rand::rngs::ThreadRng;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::thread_rng;
 use rig::{
     agent::Agent,
     completion::{Chat, Message as CompletionMessage},
     providers::anthropic::{
-        completion::CompletionModel as AnthropicCompletionModel, ClientBuilder,
+        completion::CompletionModel as AnthropicCompletionModel, Client as AnthropicClient,
+        ClientBuilder,
     },
 };
-use std::io::{self, Write};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// Number of messages kept in the rolling conversation history.
+const HISTORY_SIZE: usize = 40;
 
 pub struct Instance {
+    anthropic: AnthropicClient,
     agent: Agent<AnthropicCompletionModel>,
     character: Character,
+    history: Vec<CompletionMessage>,
+    chat_mode: bool,
+    overrides: PromptOverrides,
 }
 
 impl Instance {
@@ -21,13 +33,12 @@ impl Instance {
         let anthropic = ClientBuilder::new(anthropic_api_key).build();
 
         Ok(Self {
-            agent: anthropic
-                .agent("claude-3-5-sonnet-20241022")
-                .max_tokens(4096)
-                .preamble(&character.bio)
-                .temperature(1.0)
-                .build(),
+            agent: build_agent(&anthropic, &character),
+            anthropic,
             character,
+            history: Vec::new(),
+            chat_mode: false,
+            overrides: PromptOverrides::default(),
         })
     }
 
@@ -38,24 +49,47 @@ impl Instance {
         let mut rng = thread_rng();
 
         loop {
-            print!("(1) TWITTER post | (2) Gen new LORE branch | Or type a custom prompt for an example TWITTER reply: ");
+            if self.chat_mode {
+                print!("[CHAT] You (/help for commands): ");
+            } else {
+                print!("(1) TWITTER post | (2) Gen new LORE branch | (/help) Commands | Or type a custom prompt for an example TWITTER reply: ");
+            }
             io::stdout().flush()?;
 
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
             let input = input.trim();
 
+            if input.is_empty() {
+                continue;
+            }
+
+            if let Some(command) = input.strip_prefix('/') {
+                let (command, args) = command
+                    .split_once(char::is_whitespace)
+                    .map_or((command, ""), |(command, args)| (command, args.trim()));
+                if let Err(e) = self.handle_command(command, args, &mut rng).await {
+                    eprintln!("[CLI] Command /{} failed: {}", command, e);
+                }
+                continue;
+            }
+
             match input {
-                "1" => {
+                "1" if !self.chat_mode => {
                     println!("[CLI] Generating a new Twitter post...");
-                    let prompt = self.gen_twitter_post_prompt(&mut rng);
+                    let inputs = self.character.choose_prompt_inputs(
+                        &self.character.persona(TWITTER),
+                        &mut rng,
+                        &self.overrides,
+                    );
+                    let prompt = self.gen_twitter_post_prompt(&inputs);
                     let generated_tweet = self.handle_generate(&prompt, vec![]).await?;
                     self.character.add_previous_post(&generated_tweet);
 
                     println!("[CLI] Generated post:\n{}", generated_tweet);
                     println!();
                 }
-                "2" => {
+                "2" if !self.chat_mode => {
                     println!("[CLI] Generating a new lore branch...");
                     match self.gen_lore_branch().await {
                         Ok(_) => println!(
@@ -65,11 +99,28 @@ impl Instance {
                         Err(e) => eprintln!("[CLI] Failed to generate new lore branch: {}", e),
                     };
                 }
+                message if self.chat_mode => {
+                    match self.handle_generate(message, self.history.clone()).await {
+                        Ok(response) => {
+                            self.push_history(message, &response);
+                            println!("[CHAT] {}: {}", self.character.alias, response);
+                            println!();
+                        }
+                        Err(e) => eprintln!("[CLI] Failed to generate chat response: {}", e),
+                    }
+                }
                 custom => {
                     println!("[CLI] Generating a new Twitter reply...");
-                    let prompt = self.gen_twitter_reply_prompt(custom.to_string(), &mut rng);
-                    let generated_tweet = self.handle_generate(&prompt, vec![]).await?;
+                    let inputs = self.character.choose_prompt_inputs(
+                        &self.character.persona(TWITTER),
+                        &mut rng,
+                        &self.overrides,
+                    );
+                    let prompt = self.gen_twitter_reply_prompt(custom.to_string(), &inputs);
+                    let generated_tweet =
+                        self.handle_generate(&prompt, self.history.clone()).await?;
                     self.character.add_previous_post(&generated_tweet);
+                    self.push_history(custom, &generated_tweet);
 
                     println!("[CLI] Generated reply:\n{}", generated_tweet);
                 }
@@ -77,7 +128,264 @@ impl Instance {
         }
     }
 
-    fn gen_twitter_post_prompt(&self, rng: &mut ThreadRng) -> String {
+    async fn handle_command(
+        &mut self,
+        command: &str,
+        args: &str,
+        rng: &mut ThreadRng,
+    ) -> Result<()> {
+        match command {
+            "help" => {
+                println!("[CLI] Commands:");
+                println!("  /character         Show the current character and version");
+                println!("  /previous          Show the previous posts used to avoid repetition");
+                println!(
+                    "  /prompt post       Preview a rendered post prompt without calling the model"
+                );
+                println!("  /prompt reply <t>  Preview a rendered reply prompt for tweet <t>");
+                println!(
+                    "  /posts [n]         Generate n candidate posts side by side (default 3)"
+                );
+                println!(
+                    "  /force <field> <v> Force the topic, style or adjective of every prompt"
+                );
+                println!("  /force clear       Go back to random topics, styles and adjectives");
+                println!("  /version [n]       List versions or switch to version n");
+                println!("  /chat              Toggle chat mode (talk to the character directly)");
+                println!("  /history           Show the conversation history");
+                println!("  /reset             Clear the conversation history");
+                println!("  /save [name]       Save the conversation history");
+                println!("  /load [name]       Load a saved conversation history");
+                println!("  /export [name]     Export the conversation as a markdown transcript");
+            }
+            "chat" => {
+                self.chat_mode = !self.chat_mode;
+                println!(
+                    "[CLI] Chat mode {}",
+                    if self.chat_mode {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            "history" => {
+                if self.history.is_empty() {
+                    println!("[CLI] History is empty");
+                }
+                for message in &self.history {
+                    println!("[{}] {}", message.role, message.content);
+                }
+            }
+            "reset" => {
+                self.history.clear();
+                println!("[CLI] History cleared");
+            }
+            "save" => {
+                let path = self.transcript_path(args, "json");
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&path, serde_json::to_string_pretty(&self.history)?)?;
+                println!(
+                    "[CLI] Saved {} messages to {}",
+                    self.history.len(),
+                    path.display()
+                );
+            }
+            "load" => {
+                let path = self.transcript_path(args, "json");
+                self.history =
+                    serde_json::from_str::<Vec<CompletionMessage>>(&fs::read_to_string(&path)?)?;
+                println!(
+                    "[CLI] Loaded {} messages from {}",
+                    self.history.len(),
+                    path.display()
+                );
+            }
+            "export" => {
+                let path = self.transcript_path(args, "md");
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let transcript = self
+                    .history
+                    .iter()
+                    .map(|message| {
+                        let speaker = match message.role.as_str() {
+                            "assistant" => self.character.alias.as_str(),
+                            _ => "You",
+                        };
+                        format!("**{}**: {}", speaker, message.content)
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n\n");
+                fs::write(
+                    &path,
+                    format!(
+                        "# {} (v{}) transcript\n\n{}\n",
+                        self.character.alias, self.character.version, transcript
+                    ),
+                )?;
+                println!("[CLI] Exported transcript to {}", path.display());
+            }
+            "character" => {
+                println!(
+                    "[CLI] {} (@{}) | {} v{} | {} posts since branch",
+                    self.character.alias,
+                    self.character.twitter_user_name,
+                    self.character.character_name,
+                    self.character.version,
+                    self.character.posts_since_branch
+                );
+                println!("{}", self.character.stringify()?);
+            }
+            "previous" => {
+                if self.character.previous_posts.is_empty() {
+                    println!("[CLI] No previous posts");
+                }
+                for (idx, post) in self.character.previous_posts.iter().enumerate() {
+                    println!("[{}] {}", idx + 1, post);
+                }
+            }
+            "prompt" => {
+                let inputs = self.character.choose_prompt_inputs(
+                    &self.character.persona(TWITTER),
+                    rng,
+                    &self.overrides,
+                );
+                let prompt = match args.split_once(char::is_whitespace) {
+                    Some(("reply", tweet)) => {
+                        self.gen_twitter_reply_prompt(tweet.trim().to_string(), &inputs)
+                    }
+                    None if args == "post" || args.is_empty() => {
+                        self.gen_twitter_post_prompt(&inputs)
+                    }
+                    _ => return Err(anyhow!("usage: /prompt post | /prompt reply <tweet>")),
+                };
+                println!("{}", prompt);
+            }
+            "posts" => {
+                let count = if args.is_empty() {
+                    3
+                } else {
+                    args.parse::<usize>()
+                        .map_err(|_| anyhow!("usage: /posts [n]"))?
+                };
+                println!("[CLI] Generating {} candidate posts...", count);
+                for idx in 1..=count {
+                    let inputs = self.character.choose_prompt_inputs(
+                        &self.character.persona(TWITTER),
+                        rng,
+                        &self.overrides,
+                    );
+                    let prompt = self.gen_twitter_post_prompt(&inputs);
+                    match self.handle_generate(&prompt, vec![]).await {
+                        Ok(post) => println!(
+                            "[{}] ({} | {} | {})\n{}\n",
+                            idx,
+                            inputs.adjective,
+                            inputs.style,
+                            inputs.topics.join(" / "),
+                            post
+                        ),
+                        Err(e) => eprintln!("[{}] Failed to generate post: {}", idx, e),
+                    }
+                }
+            }
+            "force" => {
+                let (field, value) = args
+                    .split_once(char::is_whitespace)
+                    .map_or((args, ""), |(field, value)| (field, value.trim()));
+                let value = (!value.is_empty()).then(|| value.to_string());
+                match field {
+                    "topic" => self.overrides.topic = value,
+                    "style" => self.overrides.style = value,
+                    "adjective" => self.overrides.adjective = value,
+                    "clear" => self.overrides = PromptOverrides::default(),
+                    "" => (),
+                    _ => {
+                        return Err(anyhow!(
+                            "usage: /force topic|style|adjective <value> | /force clear"
+                        ))
+                    }
+                }
+                println!("[CLI] Forced prompt inputs: {:?}", self.overrides);
+            }
+            "version" => {
+                let versions = self.character.available_versions()?;
+                if args.is_empty() {
+                    println!(
+                        "[CLI] Current version: v{} | Available: {}",
+                        self.character.version,
+                        versions
+                            .iter()
+                            .map(|version| format!("v{}", version))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    );
+                    return Ok(());
+                }
+
+                let version = args
+                    .trim_start_matches('v')
+                    .parse::<u8>()
+                    .map_err(|_| anyhow!("usage: /version [n]"))?;
+                if !versions.contains(&version) {
+                    return Err(anyhow!("version v{} does not exist", version));
+                }
+
+                let file_name = if version == 1 {
+                    self.character.character_name.clone()
+                } else {
+                    format!("{}.v{}", self.character.character_name, version)
+                };
+                let mut character = Character::load(&file_name)?;
+                character.previous_posts = self.character.previous_posts.clone();
+                self.agent = build_agent(&self.anthropic, &character);
+                self.character = character;
+                println!(
+                    "[CLI] Switched to {} v{}",
+                    self.character.character_name, self.character.version
+                );
+            }
+            unknown => println!(
+                "[CLI] Unknown command /{}. Type /help for commands.",
+                unknown
+            ),
+        }
+
+        Ok(())
+    }
+
+    // Appends a user/assistant exchange, dropping the oldest exchange once the
+    // history exceeds `HISTORY_SIZE` messages.
+    fn push_history(&mut self, input: &str, response: &str) {
+        self.history.push(CompletionMessage {
+            role: "user".to_string(),
+            content: input.to_string(),
+        });
+        self.history.push(CompletionMessage {
+            role: "assistant".to_string(),
+            content: response.to_string(),
+        });
+
+        if self.history.len() > HISTORY_SIZE {
+            self.history.drain(..self.history.len() - HISTORY_SIZE);
+        }
+    }
+
+    // Saved histories and exported transcripts live in `transcripts/<character>.<name>.<ext>`.
+    fn transcript_path(&self, name: &str, extension: &str) -> PathBuf {
+        let name = if name.is_empty() { "session" } else { name };
+        Path::new("transcripts").join(format!(
+            "{}.{}.{}",
+            self.character.character_name, name, extension
+        ))
+    }
+
+    fn gen_twitter_post_prompt(&self, inputs: &PromptInputs) -> String {
+        let persona = self.character.persona(TWITTER);
         let prompt = format!(
             r"
             <instructions>
@@ -102,41 +410,19 @@ impl Instance {
             <rules>
             - NEVER use any of the words in <bannedWords> in your response.
             - Given your <instructions>, your response should not contain any questions. 
-            - Less than 280 characters. 
-            - No emojis. 
+            - Less than {max_length} characters. 
+            - {emoji_rule} 
             - Use \\n\\n (double spaces) between statements.
             - Make content have a different purpose than all the entries in <previousMessages>. You are allowed to make things up.
             </rules>",
             alias = self.character.alias,
-            twitter_user_name = self.character.twitter_user_name,
-            lore = self
-                .character
-                .lore
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            topic = self
-                .character
-                .topics
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            adjectives = self
-                .character
-                .adjectives
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join(","),
-            style = self
-                .character
-                .styles
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
+            twitter_user_name = persona.user_name,
+            max_length = persona.max_length,
+            emoji_rule = persona.emoji_rule(),
+            lore = inputs.lore.join("\n"),
+            topic = inputs.topics.join("\n"),
+            adjectives = inputs.adjective,
+            style = inputs.style,
             previous_messages = self
                 .character
                 .previous_posts
@@ -150,7 +436,8 @@ impl Instance {
         return prompt;
     }
 
-    fn gen_twitter_reply_prompt(&self, tweet: String, rng: &mut ThreadRng) -> String {
+    fn gen_twitter_reply_prompt(&self, tweet: String, inputs: &PromptInputs) -> String {
+        let persona = self.character.persona(TWITTER);
         let prompt = format!(
             r"<instructions>
             Generate a reply in the voice and style of {alias}, aka @{twitter_user_name}. Your reply to <tweet> must follow ALL the <rules>.
@@ -182,35 +469,19 @@ impl Instance {
             <rules>
             - NEVER use any of the words in <bannedWords> in your response.
             - Directly answer the question, dont make it a quote.
-            - Less than 280 characters. 
-            - No emojis. 
+            - Less than {max_length} characters. 
+            - {emoji_rule} 
             - Use \\n\\n (double spaces) between statements.
             - Make content have a different purpose than all the entries in <previousMessages>. You are allowed to make things up.
             </rules>",
             alias = self.character.alias,
-            twitter_user_name = self.character.twitter_user_name,
+            twitter_user_name = persona.user_name,
+            max_length = persona.max_length,
+            emoji_rule = persona.emoji_rule(),
             tweet = tweet,
-            lore = self
-                .character
-                .lore
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            adjectives = self
-                .character
-                .adjectives
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            style = self
-                .character
-                .styles
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
+            lore = inputs.lore.join("\n"),
+            adjectives = inputs.adjective,
+            style = inputs.style,
             previous_messages = self
                 .character
                 .previous_posts
@@ -299,3 +570,15 @@ impl Instance {
         self.agent.chat(prompt, history).await.map_err(Error::new)
     }
 }
+
+fn build_agent(
+    anthropic: &AnthropicClient,
+    character: &Character,
+) -> Agent<AnthropicCompletionModel> {
+    anthropic
+        .agent("claude-3-5-sonnet-20241022")
+        .max_tokens(4096)
+        .preamble(&character.bio)
+        .temperature(1.0)
+        .build()
+}
@@ -0,0 +1,205 @@
+use super::character::PromptInputs;
+use super::llm::Generation;
+use super::telemetry;
+use crate::db::store::{Storage, Store};
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+// Events kept in memory for the admin API.
+const RECENT_EVENTS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Post,
+    Reply,
+    Branch,
+    Skip,
+    Error,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Post => "post",
+            Action::Reply => "reply",
+            Action::Branch => "branch",
+            Action::Skip => "skip",
+            Action::Error => "error",
+        }
+    }
+}
+
+// Character entries a prompt was built from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventInputs {
+    pub topics: Vec<String>,
+    pub style: String,
+    pub adjective: String,
+    pub lore_indices: Vec<usize>,
+}
+
+impl From<&PromptInputs> for EventInputs {
+    fn from(inputs: &PromptInputs) -> Self {
+        Self {
+            topics: inputs.topics.clone(),
+            style: inputs.style.clone(),
+            adjective: inputs.adjective.clone(),
+            lore_indices: inputs.lore_indices.clone(),
+        }
+    }
+}
+
+// One entry of the append-only event log. Every post, reply, lore branch, skipped task
+// and error is recorded, so the agent's behavior can be audited after the fact.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub timestamp_unix: i64,
+    pub action: Action,
+    // Character version, `None` for scheduler events that don't involve one.
+    pub version: Option<u8>,
+    // `None` when the event isn't tied to one platform.
+    pub platform: Option<String>,
+    // Id of the published tweet, or of the post on the other platforms.
+    pub tweet_id: Option<String>,
+    pub in_reply_to_id: Option<String>,
+    pub text: Option<String>,
+    // Why a task was skipped or failed, or what triggered a branch.
+    pub reason: Option<String>,
+    pub inputs: Option<EventInputs>,
+    pub model: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    // In USD, zero for models without a price.
+    pub cost_usd: Option<f64>,
+}
+
+impl Event {
+    pub fn new(action: Action, version: Option<u8>, platform: Option<&str>) -> Self {
+        Self {
+            timestamp_unix: Utc::now().timestamp(),
+            action,
+            version,
+            platform: platform.map(str::to_string),
+            tweet_id: None,
+            in_reply_to_id: None,
+            text: None,
+            reason: None,
+            inputs: None,
+            model: None,
+            latency_ms: None,
+            input_tokens: None,
+            output_tokens: None,
+            cost_usd: None,
+        }
+    }
+
+    pub fn skip(version: Option<u8>, platform: Option<&str>, reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+            ..Self::new(Action::Skip, version, platform)
+        }
+    }
+
+    pub fn error(version: Option<u8>, platform: Option<&str>, reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+            ..Self::new(Action::Error, version, platform)
+        }
+    }
+
+    // The same event recorded as an error, e.g. a generated post that failed to publish.
+    pub fn failed(self, reason: impl Into<String>) -> Self {
+        Self {
+            action: Action::Error,
+            reason: Some(reason.into()),
+            ..self
+        }
+    }
+
+    // An event for generated text, with the model, latency and token usage of the call.
+    pub fn generated(
+        action: Action,
+        version: u8,
+        platform: &str,
+        inputs: Option<EventInputs>,
+        generation: &Generation,
+    ) -> Self {
+        Self {
+            text: Some(generation.text.clone()),
+            inputs,
+            model: Some(generation.model.clone()),
+            latency_ms: Some(generation.latency.as_millis() as u64),
+            input_tokens: Some(generation.usage.input_tokens),
+            output_tokens: Some(generation.usage.output_tokens),
+            cost_usd: Some(generation.cost_usd),
+            ..Self::new(action, Some(version), Some(platform))
+        }
+    }
+}
+
+// Records events to the store's event log when stats are enabled, and keeps the latest
+// ones in memory so they can be inspected while the agent runs.
+pub struct EventLog {
+    store: Arc<Store>,
+    persist: bool,
+    recent: Mutex<VecDeque<Event>>,
+}
+
+impl EventLog {
+    pub fn new(store: Arc<Store>, persist: bool) -> Self {
+        Self {
+            store,
+            persist,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub async fn record(&self, event: Event) {
+        telemetry::event(event.action);
+        info!(
+            action = event.action.as_str(),
+            version = event.version,
+            platform = event.platform.as_deref(),
+            tweet_id = event.tweet_id.as_deref(),
+            reason = event.reason.as_deref();
+            "[EVENTS] Recorded {} event",
+            event.action.as_str()
+        );
+        {
+            let mut recent = self.recent.lock().unwrap();
+            recent.push_back(event.clone());
+            if recent.len() > RECENT_EVENTS {
+                recent.pop_front();
+            }
+        }
+
+        if !self.persist {
+            return;
+        }
+        if let Err(e) = self.store.append_event(&event).await {
+            error!(
+                "[STATS_DB] Failed to record {:?} event: {}",
+                event.action, e
+            );
+        }
+    }
+
+    // The latest `limit` events, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<Event> {
+        self.recent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
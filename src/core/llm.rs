@@ -0,0 +1,737 @@
+use super::telemetry;
+use anyhow::{anyhow, Error, Result};
+use log::{error, info, warn};
+use rand::{thread_rng, Rng};
+use rig::{
+    agent::{Agent, AgentBuilder},
+    completion::{
+        Completion, CompletionError, CompletionModel, CompletionResponse,
+        Message as CompletionMessage, ModelChoice, ToolDefinition,
+    },
+    embeddings::{Embedding, EmbeddingError},
+    providers::{anthropic, openai},
+    tool::Tool,
+};
+use schemars::{schema_for, JsonSchema};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    env, fmt,
+    future::Future,
+    marker::PhantomData,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::{sleep, timeout};
+
+// Model used when `LLM_MODEL` is not set.
+pub const DEFAULT_MODEL: &str = "claude-3-5-sonnet-20241022";
+
+const OPENAI_EMBEDDINGS_URL: &str = "https://api.openai.com/v1/embeddings";
+
+// Longest backoff between two retries, however many retries are configured.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Providers report failed responses as text without their status code. Failures that
+// mention one of these are worth retrying, any other one (bad request, auth) is final.
+const TRANSIENT_ERRORS: [&str; 9] = [
+    "overloaded",
+    "rate_limit",
+    "rate limit",
+    "api_error",
+    "server_error",
+    "server had an error",
+    "timeout",
+    "timed out",
+    "unavailable",
+];
+
+// Prepended to the preamble of extractors, as rig's own extractor does.
+const EXTRACTOR_PREAMBLE: &str = "You are an AI assistant whose purpose is to extract structured data from the provided text.
+You will have access to a `submit` function that defines the structure of the data to extract from the provided text.
+Use the `submit` function to submit the structured data. ALWAYS call the `submit` function.";
+
+// USD per million input and output tokens, by model name prefix. `MODEL_PRICES` adds to
+// or overrides these.
+const DEFAULT_PRICES: [(&str, f64, f64); 9] = [
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("text-embedding-ada-002", 0.1, 0.0),
+    ("text-embedding-3-small", 0.02, 0.0),
+];
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    // Retries per model after the first attempt.
+    pub max_retries: u32,
+    pub timeout: Duration,
+    // Base delay of the exponential backoff between retries.
+    pub backoff: Duration,
+}
+
+impl RetryConfig {
+    // Reads `<prefix>_MAX_RETRIES`, `<prefix>_TIMEOUT_SECS` and `<prefix>_BACKOFF_MS`.
+    pub fn from_env(prefix: &str) -> Self {
+        let number = |key: &str| {
+            env::var(format!("{prefix}_{key}"))
+                .ok()
+                .and_then(|val| val.parse::<u64>().ok())
+        };
+
+        Self {
+            max_retries: number("MAX_RETRIES").unwrap_or(2) as u32,
+            timeout: Duration::from_secs(number("TIMEOUT_SECS").unwrap_or(60).max(1)),
+            backoff: Duration::from_millis(number("BACKOFF_MS").unwrap_or(1000)),
+        }
+    }
+
+    // Exponential backoff up to `MAX_BACKOFF`, with up to one base delay of random jitter.
+    fn delay(&self, attempt: u32) -> Duration {
+        let jitter = thread_rng().gen_range(0..=self.backoff.as_millis() as u64);
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF)
+            + Duration::from_millis(jitter)
+    }
+}
+
+// Failures of our own that are worth another attempt.
+#[derive(Debug)]
+enum Retryable {
+    TimedOut(Duration),
+    // The model answered in text instead of calling `submit`.
+    NoSubmit,
+}
+
+impl fmt::Display for Retryable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Retryable::TimedOut(after) => write!(f, "timed out after {}s", after.as_secs()),
+            Retryable::NoSubmit => write!(f, "model answered without calling `submit`"),
+        }
+    }
+}
+
+impl std::error::Error for Retryable {}
+
+// Whether a failed call may succeed when retried: timeouts, connection errors, rate
+// limits, overloaded providers and server errors.
+fn is_transient(error: &Error) -> bool {
+    let http = |e: &reqwest::Error| e.is_timeout() || e.is_connect() || e.is_request();
+    let provider = |message: &str| {
+        let message = message.to_lowercase();
+        TRANSIENT_ERRORS
+            .iter()
+            .any(|transient| message.contains(transient))
+    };
+
+    if error.is::<Retryable>() {
+        return true;
+    }
+    match error.downcast_ref::<CompletionError>() {
+        Some(CompletionError::HttpError(e)) => http(e),
+        Some(CompletionError::ProviderError(message)) => provider(message),
+        Some(_) => false,
+        None => match error.downcast_ref::<EmbeddingError>() {
+            Some(EmbeddingError::HttpError(e)) => http(e),
+            Some(EmbeddingError::ProviderError(message)) => provider(message),
+            _ => false,
+        },
+    }
+}
+
+// Runs `call` until it succeeds, with a timeout per attempt and backoff in between.
+// Only transient failures are retried. Every failed attempt is logged under `label`.
+pub async fn with_retries<T, F, Fut>(label: &str, config: &RetryConfig, mut call: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let started = Instant::now();
+        let error = match timeout(config.timeout, call()).await {
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(e)) => e,
+            Err(_) => Error::new(Retryable::TimedOut(config.timeout)),
+        };
+
+        warn!(
+            "[LLM] {} attempt {}/{} failed after {}ms: {}",
+            label,
+            attempt + 1,
+            config.max_retries + 1,
+            started.elapsed().as_millis(),
+            error
+        );
+        if attempt >= config.max_retries || !is_transient(&error) {
+            return Err(error);
+        }
+
+        sleep(config.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+enum ChatAgent {
+    Anthropic(Agent<anthropic::completion::CompletionModel>),
+    OpenAI(Agent<openai::CompletionModel>),
+}
+
+// Tokens billed for a call, as reported by the provider.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    fn add(&mut self, other: TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+// A chat or extraction that gave no usable answer, with what its attempts were billed.
+#[derive(Debug)]
+pub struct GenerationFailed {
+    pub reason: String,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+}
+
+impl fmt::Display for GenerationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for GenerationFailed {}
+
+// Tokens and cost billed for a call, including the attempts of a failed one.
+pub fn billed(result: Result<&Generation, &Error>) -> (TokenUsage, f64) {
+    match result {
+        Ok(generation) => (generation.usage, generation.cost_usd),
+        Err(e) => e
+            .downcast_ref::<GenerationFailed>()
+            .map(|failed| (failed.usage, failed.cost_usd))
+            .unwrap_or_default(),
+    }
+}
+
+impl ChatAgent {
+    // Sends the completion request directly instead of going through `Chat`, which
+    // drops the provider response along with its token usage.
+    async fn complete(
+        &self,
+        prompt: &str,
+        history: Vec<CompletionMessage>,
+    ) -> Result<(ModelChoice, TokenUsage)> {
+        Ok(match self {
+            ChatAgent::Anthropic(agent) => {
+                let CompletionResponse {
+                    choice,
+                    raw_response,
+                } = agent.completion(prompt, history).await?.send().await?;
+                let usage = TokenUsage {
+                    input_tokens: raw_response.usage.input_tokens,
+                    output_tokens: raw_response.usage.output_tokens,
+                };
+                (choice, usage)
+            }
+            ChatAgent::OpenAI(agent) => {
+                let CompletionResponse {
+                    choice,
+                    raw_response,
+                } = agent.completion(prompt, history).await?.send().await?;
+                let usage = raw_response
+                    .usage
+                    .map(|usage| TokenUsage {
+                        input_tokens: usage.prompt_tokens as u64,
+                        output_tokens: usage.total_tokens.saturating_sub(usage.prompt_tokens)
+                            as u64,
+                    })
+                    .unwrap_or_default();
+                (choice, usage)
+            }
+        })
+    }
+}
+
+// The `submit` tool of extractors, taking the JSON schema of the extracted type.
+#[derive(Clone)]
+struct SubmitTool {
+    parameters: serde_json::Value,
+}
+
+impl Tool for SubmitTool {
+    const NAME: &'static str = "submit";
+    type Error = Infallible;
+    type Args = serde_json::Value;
+    type Output = serde_json::Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Submit the structured data you extracted from the provided text."
+                .to_string(),
+            parameters: self.parameters.clone(),
+        }
+    }
+
+    async fn call(&self, data: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok(data)
+    }
+}
+
+// Model prices in USD per million tokens.
+#[derive(Debug, Clone)]
+pub struct Prices(Vec<(String, f64, f64)>);
+
+impl Prices {
+    // Reads `MODEL_PRICES`, written as `model=input/output` and comma separated, e.g.
+    // `gpt-4o=2.5/10,claude-3-5-sonnet=3/15`, on top of the built-in prices.
+    pub fn from_env() -> Result<Self> {
+        Self::parse(&env::var("MODEL_PRICES").unwrap_or_default())
+    }
+
+    fn parse(specs: &str) -> Result<Self> {
+        let mut prices = DEFAULT_PRICES
+            .iter()
+            .map(|(model, input, output)| (model.to_string(), *input, *output))
+            .collect::<Vec<_>>();
+
+        for spec in specs.split(',') {
+            let spec = spec.trim();
+            if spec.is_empty() {
+                continue;
+            }
+            let invalid = || anyhow!("invalid price `{}` in MODEL_PRICES", spec);
+            let (model, price) = spec.split_once('=').ok_or_else(invalid)?;
+            let (input, output) = price.split_once('/').ok_or_else(invalid)?;
+            let (input, output) = (
+                input.trim().parse::<f64>().map_err(|_| invalid())?,
+                output.trim().parse::<f64>().map_err(|_| invalid())?,
+            );
+            let model = model.trim().to_string();
+            prices.retain(|(known, _, _)| *known != model);
+            prices.push((model, input, output));
+        }
+
+        Ok(Self(prices))
+    }
+
+    // Cost of a call in USD, by the longest price prefix of the model name. The provider
+    // is ignored, so `openai:gpt-4o` is priced as `gpt-4o`. `None` for unknown models.
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        let name = model.split_once(':').map_or(model, |(_, name)| name);
+        self.0
+            .iter()
+            .filter(|(prefix, _, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _, _)| prefix.len())
+            .map(|(_, input, output)| {
+                (usage.input_tokens as f64 * input + usage.output_tokens as f64 * output)
+                    / 1_000_000.0
+            })
+    }
+}
+
+// A chat response along with the model that produced it.
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub text: String,
+    pub model: String,
+    pub attempts: u32,
+    pub latency: Duration,
+    // Every answered attempt, including the ones that were turned down and retried.
+    pub usage: TokenUsage,
+    // Zero for models without a price.
+    pub cost_usd: f64,
+}
+
+// Chat agents tried in order: the primary model (`LLM_MODEL`) followed by the
+// `LLM_FALLBACK_MODELS` chain, each with retries as configured by `LLM_*`.
+// Models are written as `provider:model`, e.g. `openai:gpt-4o`; anthropic is the default.
+pub struct Llm {
+    anthropic: anthropic::Client,
+    openai: openai::Client,
+    max_tokens: u64,
+    agents: Vec<(String, ChatAgent)>,
+    retry: RetryConfig,
+    prices: Prices,
+}
+
+impl Llm {
+    pub fn new(
+        anthropic_api_key: &str,
+        openai_api_key: &str,
+        preamble: &str,
+        max_tokens: u64,
+        temperature: f64,
+    ) -> Result<Self> {
+        let mut llm = Self {
+            anthropic: anthropic::ClientBuilder::new(anthropic_api_key).build(),
+            openai: openai::Client::new(openai_api_key),
+            max_tokens,
+            agents: Vec::new(),
+            retry: RetryConfig::from_env("LLM"),
+            prices: Prices::from_env()?,
+        };
+        llm.agents = llm.build_agents(preamble, Some(temperature), None)?;
+
+        for (model, _) in &llm.agents {
+            if llm.prices.cost(model, &TokenUsage::default()).is_none() {
+                warn!(
+                    "[LLM] No price for {}, its cost is counted as zero. Set it in MODEL_PRICES",
+                    model
+                );
+            }
+        }
+
+        Ok(llm)
+    }
+
+    pub fn prices(&self) -> &Prices {
+        &self.prices
+    }
+
+    // An extractor of `T` on the same model chain, with `preamble` as extra instructions.
+    pub fn extractor<T: JsonSchema>(&self, preamble: &str) -> Result<Extractor<T>> {
+        let tool = SubmitTool {
+            parameters: json!(schema_for!(T)),
+        };
+        let preamble = format!(
+            "{}\n=============== ADDITIONAL INSTRUCTIONS ===============\n{}",
+            EXTRACTOR_PREAMBLE, preamble
+        );
+
+        Ok(Extractor {
+            agents: self.build_agents(&preamble, None, Some(tool))?,
+            retry: self.retry.clone(),
+            prices: self.prices.clone(),
+            _t: PhantomData,
+        })
+    }
+
+    // Tries each model of the chain in turn until one answers.
+    pub async fn chat(&self, prompt: &str, history: Vec<CompletionMessage>) -> Result<Generation> {
+        complete(
+            &self.agents,
+            &self.retry,
+            &self.prices,
+            prompt,
+            history,
+            |choice| match choice {
+                ModelChoice::Message(text) => Ok(text),
+                ModelChoice::ToolCall(name, _) => Err(anyhow!(
+                    "model called tool `{}` but the agent has no tools",
+                    name
+                )),
+            },
+        )
+        .await
+    }
+
+    // Builds an agent for every model of the chain.
+    fn build_agents(
+        &self,
+        preamble: &str,
+        temperature: Option<f64>,
+        tool: Option<SubmitTool>,
+    ) -> Result<Vec<(String, ChatAgent)>> {
+        let primary = env::var("LLM_MODEL").unwrap_or_else(|_| DEFAULT_MODEL.to_string());
+        let fallbacks = env::var("LLM_FALLBACK_MODELS").unwrap_or_default();
+
+        std::iter::once(primary.as_str())
+            .chain(fallbacks.split(','))
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+            .map(|spec| {
+                let (provider, model) = spec.split_once(':').unwrap_or(("anthropic", spec));
+                let agent = match provider {
+                    "anthropic" => ChatAgent::Anthropic(configure_agent(
+                        self.anthropic.agent(model),
+                        self.max_tokens,
+                        preamble,
+                        temperature,
+                        tool.clone(),
+                    )),
+                    "openai" => ChatAgent::OpenAI(configure_agent(
+                        self.openai.agent(model),
+                        self.max_tokens,
+                        preamble,
+                        temperature,
+                        tool.clone(),
+                    )),
+                    _ => return Err(anyhow!("unknown LLM provider `{}` in `{}`", provider, spec)),
+                };
+                Ok((spec.to_string(), agent))
+            })
+            .collect()
+    }
+}
+
+fn configure_agent<M: CompletionModel>(
+    builder: AgentBuilder<M>,
+    max_tokens: u64,
+    preamble: &str,
+    temperature: Option<f64>,
+    tool: Option<SubmitTool>,
+) -> Agent<M> {
+    let mut builder = builder.max_tokens(max_tokens).preamble(preamble);
+    if let Some(temperature) = temperature {
+        builder = builder.temperature(temperature);
+    }
+    if let Some(tool) = tool {
+        builder = builder.tool(tool);
+    }
+    builder.build()
+}
+
+// Structured data of type `T`, submitted by the model through a `submit` tool call.
+// Uses the model chain and retries of the `Llm` it was built from.
+pub struct Extractor<T> {
+    agents: Vec<(String, ChatAgent)>,
+    retry: RetryConfig,
+    prices: Prices,
+    _t: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Extractor<T> {
+    // Returns the data along with the generation, whose text is the submitted JSON.
+    pub async fn extract(&self, prompt: &str) -> Result<(T, Generation)> {
+        let generation = complete(
+            &self.agents,
+            &self.retry,
+            &self.prices,
+            prompt,
+            vec![],
+            |choice| match choice {
+                ModelChoice::ToolCall(name, args) if name == SubmitTool::NAME => {
+                    Ok(args.to_string())
+                }
+                ModelChoice::ToolCall(name, _) => {
+                    Err(anyhow!("model called unknown tool `{}`", name))
+                }
+                ModelChoice::Message(_) => Err(Error::new(Retryable::NoSubmit)),
+            },
+        )
+        .await?;
+        let data = serde_json::from_str(&generation.text).map_err(|e| GenerationFailed {
+            reason: format!("invalid submission: {}", e),
+            usage: generation.usage,
+            cost_usd: generation.cost_usd,
+        })?;
+        Ok((data, generation))
+    }
+}
+
+// OpenAI embeddings, called directly since rig drops the token usage of the response.
+#[derive(Clone)]
+pub struct Embedder {
+    http: reqwest::Client,
+    api_key: String,
+    model: String,
+}
+
+impl Embedder {
+    pub fn new(api_key: &str, model: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    // Embeds `documents` in order, along with the tokens billed for them. Failures are
+    // reported as rig's `EmbeddingError`, so `with_retries` tells transient ones apart.
+    pub async fn embed(&self, documents: Vec<String>) -> Result<(Vec<Embedding>, TokenUsage)> {
+        let response = self
+            .http
+            .post(OPENAI_EMBEDDINGS_URL)
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "model": self.model,
+                "input": documents,
+            }))
+            .send()
+            .await
+            .map_err(EmbeddingError::HttpError)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.map_err(EmbeddingError::HttpError)?;
+            return Err(EmbeddingError::ProviderError(format!("{} {}", status, body)).into());
+        }
+
+        let response = response
+            .json::<openai::EmbeddingResponse>()
+            .await
+            .map_err(EmbeddingError::HttpError)?;
+        if response.data.len() != documents.len() {
+            return Err(EmbeddingError::ResponseError(
+                "response data length does not match input length".to_string(),
+            )
+            .into());
+        }
+
+        let usage = TokenUsage {
+            input_tokens: response.usage.prompt_tokens as u64,
+            output_tokens: 0,
+        };
+        let embeddings = response
+            .data
+            .into_iter()
+            .zip(documents)
+            .map(|(data, document)| Embedding {
+                document,
+                vec: data.embedding,
+            })
+            .collect();
+        Ok((embeddings, usage))
+    }
+}
+
+// Tries each model of the chain in turn until one gives an answer `accept` takes.
+async fn complete(
+    agents: &[(String, ChatAgent)],
+    retry: &RetryConfig,
+    prices: &Prices,
+    prompt: &str,
+    history: Vec<CompletionMessage>,
+    accept: impl Fn(ModelChoice) -> Result<String>,
+) -> Result<Generation> {
+    let started = Instant::now();
+    let mut attempts = 0;
+    // Every answer is billed, including the ones `accept` turns down.
+    let spent = Mutex::new((TokenUsage::default(), 0.0));
+
+    for (model, agent) in agents {
+        let result = with_retries(model, retry, || {
+            attempts += 1;
+            let history = history.clone();
+            let accept = &accept;
+            let spent = &spent;
+            async move {
+                let (choice, usage) = agent.complete(prompt, history).await?;
+                let cost_usd = prices.cost(model, &usage).unwrap_or_default();
+                telemetry::spent(model, &usage, cost_usd);
+                {
+                    let mut spent = spent.lock().unwrap();
+                    spent.0.add(usage);
+                    spent.1 += cost_usd;
+                }
+                accept(choice)
+            }
+        })
+        .await;
+
+        let (usage, cost_usd) = *spent.lock().unwrap();
+        match result {
+            Ok(text) => {
+                info!(
+                    "[LLM] {} answered in {}ms after {} attempt(s) ({} input, {} output tokens)",
+                    model,
+                    started.elapsed().as_millis(),
+                    attempts,
+                    usage.input_tokens,
+                    usage.output_tokens
+                );
+                telemetry::generated(model, started.elapsed());
+                return Ok(Generation {
+                    text,
+                    model: model.clone(),
+                    attempts,
+                    latency: started.elapsed(),
+                    usage,
+                    cost_usd,
+                });
+            }
+            Err(e) => {
+                telemetry::generation_failed(model);
+                warn!("[LLM] {} failed, trying next model: {}", model, e)
+            }
+        }
+    }
+
+    error!(
+        "[LLM] All models failed after {} attempt(s) in {}ms",
+        attempts,
+        started.elapsed().as_millis()
+    );
+    let (usage, cost_usd) = *spent.lock().unwrap();
+    Err(Error::new(GenerationFailed {
+        reason: format!("all models failed after {} attempts", attempts),
+        usage,
+        cost_usd,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(input_tokens: u64, output_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            input_tokens,
+            output_tokens,
+        }
+    }
+
+    #[test]
+    fn longest_price_prefix_wins() {
+        let prices = Prices::parse("").unwrap();
+
+        assert_eq!(
+            prices.cost("gpt-4o-mini-2024-07-18", &usage(1_000_000, 0)),
+            Some(0.15)
+        );
+        assert_eq!(
+            prices.cost("gpt-4o-2024-08-06", &usage(1_000_000, 0)),
+            Some(2.5)
+        );
+    }
+
+    #[test]
+    fn provider_is_ignored_and_unknown_models_have_no_price() {
+        let prices = Prices::parse("").unwrap();
+
+        assert_eq!(
+            prices.cost("anthropic:claude-3-5-sonnet-latest", &usage(1_000, 2_000)),
+            Some(0.033)
+        );
+        assert_eq!(prices.cost("mistral-large", &usage(1_000, 1_000)), None);
+    }
+
+    #[test]
+    fn model_prices_override_and_extend_the_defaults() {
+        let prices = Prices::parse(" gpt-4o = 5/20 , llama-3=0.5/1,").unwrap();
+
+        assert_eq!(
+            prices.cost("gpt-4o", &usage(1_000_000, 1_000_000)),
+            Some(25.0)
+        );
+        assert_eq!(
+            prices.cost("gpt-4o-mini", &usage(1_000_000, 1_000_000)),
+            Some(0.75)
+        );
+        assert_eq!(prices.cost("llama-3-70b", &usage(0, 2_000_000)), Some(2.0));
+    }
+
+    #[test]
+    fn invalid_model_prices_are_rejected() {
+        assert!(Prices::parse("gpt-4o").is_err());
+        assert!(Prices::parse("gpt-4o=2.5").is_err());
+        assert!(Prices::parse("gpt-4o=cheap/10").is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::{anyhow, Result};
+use chrono::{SecondsFormat, Utc};
+use fern::colors::{Color, ColoredLevelConfig};
+use lazy_static::lazy_static;
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    LevelFilter, Record,
+};
+use serde_json::{json, Map, Value as JsonValue};
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::RwLock,
+};
+
+lazy_static! {
+    // Added to every JSON log line, set once the character is known.
+    static ref CONTEXT: RwLock<Context> = RwLock::new(Context::default());
+}
+
+#[derive(Default)]
+struct Context {
+    character: Option<String>,
+    environment: Option<String>,
+    version: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // Colored text for a terminal.
+    Pretty,
+    // One JSON object per line, for log shippers.
+    Json,
+}
+
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    // Also written to this file, rotated once it reaches `file_max_bytes`.
+    pub file: Option<PathBuf>,
+    pub file_max_bytes: u64,
+    // Rotated files kept next to the log file, as `<file>.1` (newest) to `<file>.<n>`.
+    pub file_keep: usize,
+}
+
+impl LogConfig {
+    // Reads `LOG_LEVEL`, `LOG_FORMAT`, `LOG_FILE`, `LOG_FILE_MAX_MB` and `LOG_FILE_KEEP`.
+    pub fn from_env() -> Result<Self> {
+        let var = |key: &str| env::var(key).ok().filter(|val| !val.is_empty());
+        let number = |key: &str, default: u64| {
+            var(key).map_or(Ok(default), |val| {
+                val.parse::<u64>()
+                    .map_err(|_| anyhow!("`{}` must be a number, got {}", key, val))
+            })
+        };
+
+        Ok(Self {
+            level: match var("LOG_LEVEL") {
+                Some(level) => level
+                    .parse()
+                    .map_err(|_| anyhow!("unknown `LOG_LEVEL` {}", level))?,
+                None => LevelFilter::Info,
+            },
+            format: match var("LOG_FORMAT").as_deref() {
+                Some("pretty") | None => LogFormat::Pretty,
+                Some("json") => LogFormat::Json,
+                Some(format) => {
+                    return Err(anyhow!(
+                        "unknown `LOG_FORMAT` {}: expected pretty or json",
+                        format
+                    ))
+                }
+            },
+            file: var("LOG_FILE").map(PathBuf::from),
+            file_max_bytes: number("LOG_FILE_MAX_MB", 10)?.max(1) * 1024 * 1024,
+            file_keep: number("LOG_FILE_KEEP", 5)? as usize,
+        })
+    }
+}
+
+// Installs the logger: stdout, plus the log file when one is configured. Dependencies
+// log at `Info` at most, so `debug` and `trace` only apply to the agent itself.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let format = config.format;
+    let mut dispatch = fern::Dispatch::new()
+        .level(config.level.min(LevelFilter::Info))
+        .level_for(env!("CARGO_PKG_NAME"), config.level)
+        .chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    out.finish(format_args!("{}", render(format, message, record, true)))
+                })
+                .chain(io::stdout()),
+        );
+
+    if let Some(path) = &config.file {
+        let file = RotatingFile::open(path.clone(), config.file_max_bytes, config.file_keep)?;
+        dispatch = dispatch.chain(
+            fern::Dispatch::new()
+                .format(move |out, message, record| {
+                    out.finish(format_args!("{}", render(format, message, record, false)))
+                })
+                .chain(Box::new(file) as Box<dyn Write + Send>),
+        );
+    }
+
+    dispatch.apply()?;
+    Ok(())
+}
+
+pub fn set_character(character: &str, environment: &str) {
+    let mut context = CONTEXT.write().unwrap();
+    context.character = Some(character.to_string());
+    context.environment = Some(environment.to_string());
+}
+
+// Called whenever the running character version changes, e.g. after a branch.
+pub fn set_version(version: u8) {
+    CONTEXT.write().unwrap().version = Some(version);
+}
+
+fn render(format: LogFormat, message: &fmt::Arguments, record: &Record, colored: bool) -> String {
+    let mut fields = Fields::default();
+    // Fields are plain values, visiting them can't fail.
+    let _ = record.key_values().visit(&mut fields);
+
+    match format {
+        LogFormat::Pretty => {
+            let level = if colored {
+                ColoredLevelConfig::new()
+                    .info(Color::BrightGreen)
+                    .error(Color::BrightRed)
+                    .warn(Color::BrightYellow)
+                    .color(record.level())
+                    .to_string()
+            } else {
+                record.level().to_string()
+            };
+            let fields = fields
+                .0
+                .iter()
+                .filter(|(_, val)| !val.is_null())
+                .map(|(key, val)| match val {
+                    JsonValue::String(val) => format!(" {}={}", key, val),
+                    val => format!(" {}={}", key, val),
+                })
+                .collect::<String>();
+            format!(
+                "[{} | {} | loreweaver] {}{}",
+                Utc::now().format("%H:%M:%S.%3f"),
+                level,
+                message,
+                fields
+            )
+        }
+        LogFormat::Json => {
+            // `[TAG] message` is split into the component and the message.
+            let message = message.to_string();
+            let (component, message) = message
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once("] "))
+                .map_or((None, message.as_str()), |(tag, rest)| (Some(tag), rest));
+
+            let mut line = Map::new();
+            line.insert(
+                "timestamp".to_string(),
+                json!(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+            );
+            line.insert("level".to_string(), json!(record.level().as_str()));
+            line.insert("target".to_string(), json!(record.target()));
+            line.insert("component".to_string(), json!(component));
+            line.insert("message".to_string(), json!(message));
+            {
+                let context = CONTEXT.read().unwrap();
+                line.insert("character".to_string(), json!(context.character));
+                line.insert("environment".to_string(), json!(context.environment));
+                line.insert("version".to_string(), json!(context.version));
+            }
+            line.extend(fields.0);
+            JsonValue::Object(line).to_string()
+        }
+    }
+}
+
+// Key-values of a log record, e.g. `info!(action = "post"; "...")`.
+#[derive(Default)]
+struct Fields(Vec<(String, JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(val) = value.to_u64() {
+            json!(val)
+        } else if let Some(val) = value.to_i64() {
+            json!(val)
+        } else if let Some(val) = value.to_bool() {
+            json!(val)
+        } else if let Some(val) = value.to_borrowed_str() {
+            json!(val)
+        } else {
+            // `None` fields are the only ones that aren't a number, bool or string.
+            match value.to_string().as_str() {
+                "None" => JsonValue::Null,
+                val => json!(val),
+            }
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+// Log file that is moved to `<path>.1` once it grows past `max_bytes`, shifting the
+// older ones up and dropping those beyond `keep`. Rotates on flush, which the logger
+// does after every line, so lines aren't split across files.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn rotated(&self, idx: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), idx))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for idx in (1..self.keep).rev() {
+                let from = self.rotated(idx);
+                if from.exists() {
+                    fs::rename(from, self.rotated(idx + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.size >= self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use super::character::Character;
+use anyhow::{anyhow, Result};
+use log::info;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const OPENAI_IMAGES_URL: &str = "https://api.openai.com/v1/images/generations";
+
+// An image to attach to a post, with a description the post prompt can work with.
+#[derive(Debug, Clone)]
+pub struct MediaImage {
+    pub bytes: Vec<u8>,
+    pub mime_type: String,
+    pub description: String,
+}
+
+// An image along with the alt text written for it.
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub image: MediaImage,
+    pub alt_text: String,
+}
+
+#[derive(Deserialize)]
+struct GeneratedImages {
+    data: Vec<GeneratedImage>,
+}
+
+#[derive(Deserialize)]
+struct GeneratedImage {
+    url: String,
+    revised_prompt: Option<String>,
+}
+
+// Where post images come from, set by `MEDIA_SOURCE`.
+pub enum MediaSource {
+    None,
+    // Images under `MEDIA_DIR`, tagged by the name of the directory they are in.
+    // `<image>.txt` next to an image describes it, otherwise its file name does.
+    Directory(PathBuf),
+    // Images generated from the post topic in the character's visual style.
+    OpenAI {
+        http: reqwest::Client,
+        api_key: String,
+        model: String,
+    },
+}
+
+pub struct Media {
+    source: MediaSource,
+    // Chance that a post gets an image.
+    probability: f64,
+}
+
+impl Media {
+    pub fn from_env(openai_api_key: &str) -> Result<Self> {
+        let source = match env::var("MEDIA_SOURCE").unwrap_or_default().as_str() {
+            "" | "none" => MediaSource::None,
+            "directory" => MediaSource::Directory(PathBuf::from(
+                env::var("MEDIA_DIR").unwrap_or_else(|_| "media".to_string()),
+            )),
+            "openai" => MediaSource::OpenAI {
+                http: reqwest::Client::new(),
+                api_key: openai_api_key.to_string(),
+                model: env::var("IMAGE_MODEL").unwrap_or_else(|_| "dall-e-3".to_string()),
+            },
+            other => return Err(anyhow!("unknown MEDIA_SOURCE `{}`", other)),
+        };
+
+        Ok(Self {
+            source,
+            probability: env::var("MEDIA_PROBABILITY")
+                .ok()
+                .and_then(|val| val.parse::<f64>().ok())
+                .unwrap_or(0.25)
+                .clamp(0.0, 1.0),
+        })
+    }
+
+    // Picks or generates an image for a post about `topics`, or `None` when this post
+    // goes without one.
+    pub async fn choose(
+        &self,
+        topics: &[String],
+        character: &Character,
+    ) -> Result<Option<MediaImage>> {
+        if matches!(self.source, MediaSource::None) || !thread_rng().gen_bool(self.probability) {
+            return Ok(None);
+        }
+
+        match &self.source {
+            MediaSource::None => Ok(None),
+            MediaSource::Directory(dir) => choose_from_dir(dir, topics),
+            MediaSource::OpenAI {
+                http,
+                api_key,
+                model,
+            } => {
+                let prompt = format!(
+                    "{style}An image evoking {topics}, in the world of {alias}. No text in the image.",
+                    style = character
+                        .visual_style
+                        .as_ref()
+                        .map(|style| format!("{}. ", style.trim_end_matches('.')))
+                        .unwrap_or_default(),
+                    topics = topics.join(", "),
+                    alias = character.alias,
+                );
+                generate_image(http, api_key, model, &prompt)
+                    .await
+                    .map(Some)
+            }
+        }
+    }
+}
+
+fn choose_from_dir(dir: &Path, topics: &[String]) -> Result<Option<MediaImage>> {
+    let topics = topics
+        .iter()
+        .map(|topic| topic.to_lowercase())
+        .collect::<Vec<String>>();
+
+    // Images in directories whose tag appears in a topic, or untagged ones otherwise.
+    let mut tagged = Vec::new();
+    let mut untagged = Vec::new();
+    for entry in fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            let tag = entry
+                .file_name()
+                .to_string_lossy()
+                .to_lowercase()
+                .replace(['-', '_'], " ");
+            if topics.iter().any(|topic| topic.contains(&tag)) {
+                tagged.extend(images_in(&path)?);
+            }
+        } else if mime_type(&path).is_some() {
+            untagged.push(path);
+        }
+    }
+
+    let candidates = if tagged.is_empty() { untagged } else { tagged };
+    let Some(path) = candidates.choose(&mut thread_rng()) else {
+        info!("[MEDIA] No image found for topics {:?}", topics);
+        return Ok(None);
+    };
+
+    let description = fs::read_to_string(path.with_extension("txt"))
+        .ok()
+        .map(|description| description.trim().to_string())
+        .filter(|description| !description.is_empty())
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().replace(['-', '_'], " "))
+                .unwrap_or_default()
+        });
+    info!("[MEDIA] Picked image {}", path.display());
+
+    Ok(Some(MediaImage {
+        bytes: fs::read(path)?,
+        mime_type: mime_type(path).unwrap_or("image/png").to_string(),
+        description,
+    }))
+}
+
+fn images_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && mime_type(path).is_some())
+        .collect())
+}
+
+fn mime_type(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+async fn generate_image(
+    http: &reqwest::Client,
+    api_key: &str,
+    model: &str,
+    prompt: &str,
+) -> Result<MediaImage> {
+    let generated = http
+        .post(OPENAI_IMAGES_URL)
+        .bearer_auth(api_key)
+        .json(&json!({
+            "model": model,
+            "prompt": prompt,
+            "n": 1,
+            "size": "1024x1024",
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<GeneratedImages>()
+        .await?
+        .data
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("image generation returned no image"))?;
+
+    let bytes = http
+        .get(&generated.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    info!("[MEDIA] Generated image with {}", model);
+
+    Ok(MediaImage {
+        bytes: bytes.to_vec(),
+        mime_type: "image/png".to_string(),
+        description: generated
+            .revised_prompt
+            .unwrap_or_else(|| prompt.to_string()),
+    })
+}
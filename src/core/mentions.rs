@@ -0,0 +1,597 @@
+use super::character::Character;
+use crate::clients::{social::SocialPost, twitter::twitter::AuthoredTweet};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+};
+use twitter_v2::data::ReferencedTweetKind;
+
+lazy_static! {
+    pub static ref REPLIES_PER_CYCLE: usize = {
+        env::var("REPLIES_PER_CYCLE")
+            .ok()
+            .and_then(|val| val.parse::<usize>().ok())
+            .unwrap_or(1)
+    };
+    pub static ref MENTION_MAX_AGE_HOURS: i64 = {
+        env::var("MENTION_MAX_AGE_HOURS")
+            .ok()
+            .and_then(|val| val.parse::<i64>().ok())
+            .unwrap_or(24)
+    };
+    static ref MENTION_BLOCKLIST: Vec<String> = {
+        env::var("MENTION_BLOCKLIST")
+            .map(|val| {
+                val.split(',')
+                    .map(|word| word.trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect()
+            })
+            .unwrap_or_else(|_| {
+                DEFAULT_BLOCKLIST
+                    .iter()
+                    .map(|word| word.to_string())
+                    .collect()
+            })
+    };
+}
+
+// Mentions kept in the queue at most; the lowest ranked are dropped first.
+const MAX_QUEUE_SIZE: usize = 50;
+
+// Failed reply generations after which a mention is dropped from the queue.
+const MAX_REPLY_ATTEMPTS: u32 = 3;
+
+// Phrases that mark a mention as spam or abuse unless `MENTION_BLOCKLIST` overrides them.
+const DEFAULT_BLOCKLIST: &[&str] = &[
+    "airdrop",
+    "giveaway",
+    "dm me",
+    "follow back",
+    "promo",
+    "kys",
+    "kill yourself",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedMention {
+    pub id: u64,
+    pub text: String,
+    pub author_id: Option<u64>,
+    pub author_user_name: Option<String>,
+    pub author_followers: usize,
+    #[serde(default)]
+    pub in_reply_to_id: Option<u64>,
+    #[serde(default)]
+    pub conversation_id: Option<u64>,
+    pub queued_at_unix: i64,
+    pub score: f32,
+    #[serde(default)]
+    pub failed_attempts: u32,
+}
+
+// A mention from one of the other platforms, keyed by the platform name so it still
+// finds its client after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedSocialMention {
+    pub platform: String,
+    pub post: SocialPost,
+    pub queued_at_unix: i64,
+}
+
+// Structured response of the reply selection step, submitted by the model as a tool call.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplySelection {
+    /// ID of the tweet to reply to, copied exactly from the list, or null if none of the tweets deserve a reply.
+    pub selected_id: Option<String>,
+    /// One short sentence explaining the choice.
+    pub reason: String,
+}
+
+impl ReplySelection {
+    // Resolves the selected id against the candidates the model was shown.
+    // `Ok(None)` means the model chose not to reply to any of them.
+    pub fn validate(&self, candidates: &[QueuedMention]) -> Result<Option<u64>> {
+        let Some(selected_id) = self.selected_id.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        if selected_id.is_empty() || selected_id.eq_ignore_ascii_case("none") {
+            return Ok(None);
+        }
+
+        let id = selected_id
+            .parse::<u64>()
+            .map_err(|_| anyhow!("selected id `{}` is not a tweet id", selected_id))?;
+        if !candidates.iter().any(|mention| mention.id == id) {
+            return Err(anyhow!("selected id {} is not one of the candidates", id));
+        }
+
+        Ok(Some(id))
+    }
+}
+
+// Mentions waiting for a reply, persisted to `state/<character>.mentions.json` so
+// mentions that were not answered in one cycle are considered again in the next.
+pub struct MentionQueue {
+    path: PathBuf,
+    mentions: Vec<QueuedMention>,
+}
+
+impl MentionQueue {
+    pub fn load(character_name: &str) -> Result<Self> {
+        let path = Path::new("state").join(format!("{}.mentions.json", character_name));
+
+        let mentions = if path.exists() {
+            serde_json::from_str::<Vec<QueuedMention>>(&fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { path, mentions })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save_json(&self.path, &self.mentions)
+    }
+
+    pub fn len(&self) -> usize {
+        self.mentions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mentions.is_empty()
+    }
+
+    // Triages and queues new mentions, returning how many were accepted.
+    // Spam, abusive and duplicate mentions are dropped here.
+    pub fn enqueue(&mut self, mentions: Vec<AuthoredTweet>, character: &Character) -> usize {
+        let keywords = character_keywords(character);
+        let now = Utc::now().timestamp();
+        let mut accepted = 0;
+
+        for mention in mentions {
+            let id = mention.tweet.id.as_u64();
+            if self.mentions.iter().any(|queued| {
+                queued.id == id
+                    || (queued.author_id == mention.tweet.author_id.map(|id| id.as_u64())
+                        && queued.text == mention.tweet.text)
+            }) {
+                continue;
+            }
+
+            let Some(score) = score_mention(&mention, &keywords) else {
+                continue;
+            };
+
+            self.mentions.push(QueuedMention {
+                id,
+                text: mention.tweet.text.clone(),
+                author_id: mention.tweet.author_id.map(|id| id.as_u64()),
+                author_user_name: mention.author.as_ref().map(|user| user.username.clone()),
+                author_followers: mention
+                    .author
+                    .as_ref()
+                    .and_then(|user| user.public_metrics.as_ref())
+                    .map_or(0, |metrics| metrics.followers_count),
+                in_reply_to_id: mention
+                    .tweet
+                    .referenced_tweets
+                    .iter()
+                    .flatten()
+                    .find(|referenced| referenced.kind == ReferencedTweetKind::RepliedTo)
+                    .map(|referenced| referenced.id.as_u64()),
+                conversation_id: mention.tweet.conversation_id.map(|id| id.as_u64()),
+                queued_at_unix: now,
+                score,
+                failed_attempts: 0,
+            });
+            accepted += 1;
+        }
+
+        self.prune();
+        accepted
+    }
+
+    // Returns up to `count` queued mentions, best ranked first.
+    pub fn top(&self, count: usize) -> Vec<QueuedMention> {
+        self.mentions.iter().take(count).cloned().collect()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<QueuedMention> {
+        let idx = self.mentions.iter().position(|mention| mention.id == id)?;
+        Some(self.mentions.remove(idx))
+    }
+
+    // Drops mentions the model chose not to reply to, so they aren't offered again.
+    pub fn decline(&mut self, ids: &[u64]) {
+        self.mentions.retain(|mention| !ids.contains(&mention.id));
+    }
+
+    // Counts a failed reply generation for the mention and drops it after
+    // `MAX_REPLY_ATTEMPTS`, so it can't stay at the top of the queue. Returns whether it was dropped.
+    pub fn record_failure(&mut self, id: u64) -> bool {
+        let Some(mention) = self.mentions.iter_mut().find(|mention| mention.id == id) else {
+            return false;
+        };
+        mention.failed_attempts += 1;
+        if mention.failed_attempts < MAX_REPLY_ATTEMPTS {
+            return false;
+        }
+        self.remove(id);
+        true
+    }
+
+    // Puts back a mention that was taken for a reply that didn't get posted.
+    pub fn requeue(&mut self, mention: QueuedMention) {
+        if !self.mentions.iter().any(|queued| queued.id == mention.id) {
+            self.mentions.push(mention);
+        }
+        self.prune();
+    }
+
+    // Drops expired mentions, re-ranks the rest by score decayed with age and
+    // caps the queue at `MAX_QUEUE_SIZE`.
+    pub fn prune(&mut self) {
+        let now = Utc::now().timestamp();
+        let max_age_secs = *MENTION_MAX_AGE_HOURS * 60 * 60;
+
+        self.mentions
+            .retain(|mention| now - mention.queued_at_unix < max_age_secs);
+
+        let rank = |mention: &QueuedMention| {
+            let age_hours = (now - mention.queued_at_unix) as f32 / 3600.0;
+            mention.score / (1.0 + age_hours / 6.0)
+        };
+        self.mentions.sort_by(|a, b| rank(b).total_cmp(&rank(a)));
+        self.mentions.truncate(MAX_QUEUE_SIZE);
+    }
+}
+
+// Mentions from the other platforms waiting for a reply, persisted to
+// `state/<character>.social_mentions.json`. They are answered oldest first; mentions over
+// the reply budget, or whose reply failed, wait for a later cycle.
+pub struct SocialMentionQueue {
+    path: PathBuf,
+    mentions: Vec<QueuedSocialMention>,
+}
+
+impl SocialMentionQueue {
+    pub fn load(character_name: &str) -> Result<Self> {
+        let path = Path::new("state").join(format!("{}.social_mentions.json", character_name));
+
+        let mentions = if path.exists() {
+            serde_json::from_str::<Vec<QueuedSocialMention>>(&fs::read_to_string(&path)?)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { path, mentions })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save_json(&self.path, &self.mentions)
+    }
+
+    pub fn len(&self) -> usize {
+        self.mentions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mentions.is_empty()
+    }
+
+    // Queues new mentions from `platform`, returning how many were accepted.
+    // Mentions already queued are dropped here.
+    pub fn enqueue(&mut self, platform: &str, posts: Vec<SocialPost>) -> usize {
+        let now = Utc::now().timestamp();
+        let mut accepted = 0;
+
+        for post in posts {
+            if self.position(platform, &post.id).is_some() {
+                continue;
+            }
+            self.mentions.push(QueuedSocialMention {
+                platform: platform.to_string(),
+                post,
+                queued_at_unix: now,
+            });
+            accepted += 1;
+        }
+
+        self.prune();
+        accepted
+    }
+
+    // Returns up to `count` queued mentions from the given platforms, oldest first.
+    pub fn oldest(&self, platforms: &[&str], count: usize) -> Vec<QueuedSocialMention> {
+        self.mentions
+            .iter()
+            .filter(|mention| platforms.contains(&mention.platform.as_str()))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    pub fn remove(&mut self, platform: &str, id: &str) -> Option<QueuedSocialMention> {
+        let idx = self.position(platform, id)?;
+        Some(self.mentions.remove(idx))
+    }
+
+    // Puts back a mention that was taken for a reply that didn't get posted.
+    pub fn requeue(&mut self, mention: QueuedSocialMention) {
+        if self.position(&mention.platform, &mention.post.id).is_none() {
+            self.mentions.push(mention);
+        }
+        self.prune();
+    }
+
+    // Drops expired mentions, restores the oldest-first order and caps the queue at
+    // `MAX_QUEUE_SIZE`, dropping the oldest first.
+    pub fn prune(&mut self) {
+        let now = Utc::now().timestamp();
+        let max_age_secs = *MENTION_MAX_AGE_HOURS * 60 * 60;
+
+        self.mentions
+            .retain(|mention| now - mention.queued_at_unix < max_age_secs);
+        self.mentions.sort_by_key(|mention| mention.queued_at_unix);
+        let excess = self.mentions.len().saturating_sub(MAX_QUEUE_SIZE);
+        self.mentions.drain(..excess);
+    }
+
+    fn position(&self, platform: &str, id: &str) -> Option<usize> {
+        self.mentions
+            .iter()
+            .position(|mention| mention.platform == platform && mention.post.id == id)
+    }
+}
+
+// Writes `value` to a temp file next to `path`, then moves it over `path`.
+fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    {
+        let mut queue_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)?;
+        serde_json::to_writer_pretty(&mut queue_file, value)?;
+    }
+    fs::rename(temp_path, path)?;
+
+    Ok(())
+}
+
+// Lowercased words (longer than four characters) from the character's topics and lore,
+// used to judge whether a mention is relevant to the character.
+fn character_keywords(character: &Character) -> HashSet<String> {
+    character
+        .topics
+        .iter()
+        .chain(character.lore.iter())
+        .flat_map(|entry| normalized_words(entry))
+        .filter(|word| word.len() > 4)
+        .collect()
+}
+
+fn normalized_words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// Whether a mention contains one of the spam / abuse phrases of the blocklist.
+// Phrases match whole words only, so "kys" doesn't block "skys" or "keys".
+pub fn is_blocked(text: &str) -> bool {
+    let words = normalized_words(text);
+    MENTION_BLOCKLIST.iter().any(|phrase| {
+        let phrase = normalized_words(phrase);
+        !phrase.is_empty() && words.windows(phrase.len()).any(|window| window == phrase)
+    })
+}
+
+// Scores a mention for reply priority, or `None` when it should not be answered at all.
+fn score_mention(mention: &AuthoredTweet, keywords: &HashSet<String>) -> Option<f32> {
+    let text = mention.tweet.text.to_lowercase();
+
+    // Toxicity / spam phrases
+    if is_blocked(&text) {
+        return None;
+    }
+
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let tags = words
+        .iter()
+        .filter(|word| word.starts_with('@') || word.starts_with('#'))
+        .count();
+    let links = words
+        .iter()
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .count();
+    let content = normalized_words(
+        &words
+            .iter()
+            .filter(|word| {
+                !word.starts_with('@') && !word.starts_with('#') && !word.starts_with("http")
+            })
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join(" "),
+    );
+
+    // Spam: tag walls, link drops and mentions with nothing to reply to
+    if tags > 5 || content.len() < 2 || (links > 0 && content.len() < 5) {
+        return None;
+    }
+
+    let relevance = content
+        .iter()
+        .filter(|word| keywords.contains(word.as_str()))
+        .count() as f32;
+    let followers = mention
+        .author
+        .as_ref()
+        .and_then(|user| user.public_metrics.as_ref())
+        .map_or(0, |metrics| metrics.followers_count);
+
+    let mut score = 1.0 + relevance.min(5.0) + ((followers + 1) as f32).log10();
+    if text.contains('?') {
+        score += 1.0;
+    }
+    if tags > 2 || links > 0 {
+        score *= 0.5;
+    }
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tweet(text: &str, followers: usize) -> AuthoredTweet {
+        AuthoredTweet {
+            tweet: serde_json::from_value(json!({ "id": "1", "text": text })).unwrap(),
+            author: Some(
+                serde_json::from_value(json!({
+                    "id": "2",
+                    "name": "Fan",
+                    "username": "fan",
+                    "public_metrics": {
+                        "followers_count": followers,
+                        "following_count": 0,
+                        "tweet_count": 0,
+                        "listed_count": 0,
+                    },
+                }))
+                .unwrap(),
+            ),
+        }
+    }
+
+    fn keywords() -> HashSet<String> {
+        ["dragons", "castle"]
+            .iter()
+            .map(|word| word.to_string())
+            .collect()
+    }
+
+    fn score(text: &str) -> Option<f32> {
+        score_mention(&tweet(text, 0), &keywords())
+    }
+
+    fn queued(id: u64) -> QueuedMention {
+        QueuedMention {
+            id,
+            text: format!("mention {}", id),
+            author_id: None,
+            author_user_name: None,
+            author_followers: 0,
+            in_reply_to_id: None,
+            conversation_id: None,
+            queued_at_unix: Utc::now().timestamp(),
+            score: 1.0,
+            failed_attempts: 0,
+        }
+    }
+
+    fn queue(mentions: Vec<QueuedMention>) -> MentionQueue {
+        MentionQueue {
+            path: PathBuf::from("unused.json"),
+            mentions,
+        }
+    }
+
+    #[test]
+    fn blocklist_matches_whole_words_only() {
+        assert!(is_blocked("Huge AIRDROP today!"));
+        assert!(is_blocked("please dm me for details"));
+        assert!(is_blocked("kys."));
+        assert!(!is_blocked("the skys are clear"));
+        assert!(!is_blocked("promotion season is here"));
+        assert!(!is_blocked("dm someone, me included"));
+    }
+
+    #[test]
+    fn spam_mentions_are_not_scored() {
+        assert_eq!(score("@lore free AIRDROP for everyone"), None);
+        assert_eq!(score("@lore @a @b @c @d @e #tags everywhere"), None);
+        assert_eq!(score("@lore wow https://spam.example"), None);
+        assert_eq!(score("@lore hi"), None);
+    }
+
+    #[test]
+    fn relevant_questions_rank_higher() {
+        let plain = score("@lore nice weather today").unwrap();
+        let relevant = score("@lore the dragons took the castle").unwrap();
+        let question = score("@lore where did the dragons go?").unwrap();
+
+        assert_eq!(plain, 1.0);
+        assert_eq!(relevant, 3.0);
+        assert_eq!(question, 3.0);
+        assert!(relevant > plain);
+    }
+
+    #[test]
+    fn links_and_tags_halve_the_score() {
+        let linked = score("@lore look at these dragons over here https://example.com").unwrap();
+        assert_eq!(linked, 1.0);
+    }
+
+    #[test]
+    fn followers_add_to_the_score() {
+        let text = "@lore nice weather today";
+        let unknown = score_mention(&tweet(text, 0), &keywords()).unwrap();
+        let known = score_mention(&tweet(text, 9_999), &keywords()).unwrap();
+
+        assert!((known - unknown - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mention_is_dropped_after_repeated_failures() {
+        let mut queue = queue(vec![queued(1), queued(2)]);
+
+        for _ in 1..MAX_REPLY_ATTEMPTS {
+            assert!(!queue.record_failure(1));
+        }
+        assert_eq!(queue.top(1)[0].id, 1);
+
+        assert!(queue.record_failure(1));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.top(1)[0].id, 2);
+    }
+
+    #[test]
+    fn declined_mentions_are_not_offered_again() {
+        let mut queue = queue(vec![queued(1), queued(2), queued(3)]);
+        let candidates = queue.top(2);
+
+        queue.decline(
+            &candidates
+                .iter()
+                .map(|mention| mention.id)
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.top(2)[0].id, 3);
+    }
+}
@@ -1,5 +1,15 @@
+pub mod admin;
 pub mod character;
 pub mod cli;
+pub mod events;
+pub mod llm;
+pub mod logging;
+pub mod media;
+pub mod mentions;
+pub mod report;
+pub mod scheduler;
+pub mod state;
+pub mod telemetry;
 pub mod twitter;
 
 use rig::Embed;
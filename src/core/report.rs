@@ -0,0 +1,271 @@
+use super::events::Action;
+use crate::db::store::{Storage, Store, Usage};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+const USAGE: &str =
+    "usage: stats | stats compare <version> <version> | stats export <csv|json> [path]";
+
+// What the `stats` command reports for one character version.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionSummary {
+    pub version: u32,
+    pub created_at_unix: i64,
+    // Until the next version was created, or until now for the latest one.
+    pub lifetime_hours: f64,
+    pub tweets_sent: u64,
+    pub replies_sent: u64,
+    pub messages_read: u64,
+    // Tweets with collected engagement metrics.
+    pub tweets_measured: usize,
+    pub likes: u64,
+    pub retweets: u64,
+    pub replies: u64,
+    pub quotes: u64,
+    pub impressions: u64,
+    // Likes, retweets, replies and quotes per measured tweet.
+    pub engagement_per_tweet: f64,
+    // Model usage summed over all actions, then by action.
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+    pub usage: BTreeMap<String, Usage>,
+    // What triggered the branch that created the version, `None` for the first one.
+    pub branch_reason: Option<String>,
+}
+
+// Runs `stats` with the arguments that follow it.
+pub async fn run(store: &Store, args: &[String]) -> Result<()> {
+    let summaries = summarize(store).await?;
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args.as_slice() {
+        [] => print_summaries(&summaries),
+        ["compare", a, b] => print_comparison(find(&summaries, a)?, find(&summaries, b)?),
+        ["export", format] => println!("{}", export(&summaries, format)?),
+        ["export", format, path] => {
+            fs::write(Path::new(path), export(&summaries, format)?)?;
+            println!("[STATS] Exported {} versions to {}", summaries.len(), path);
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+    Ok(())
+}
+
+// Summaries of every version of the scope, oldest first.
+pub async fn summarize(store: &Store) -> Result<Vec<VersionSummary>> {
+    let versions = store.stats_versions().await?;
+    let branches = store.events(Action::Branch).await?;
+    let now = Utc::now();
+
+    let summaries = versions
+        .iter()
+        .enumerate()
+        .map(|(idx, doc)| {
+            let created_at =
+                DateTime::from_timestamp(doc.creation_date_unix as i64, 0).unwrap_or_default();
+            let ended_at = versions
+                .get(idx + 1)
+                .and_then(|next| DateTime::from_timestamp(next.creation_date_unix as i64, 0))
+                .unwrap_or(now);
+            let engagement = &doc.engagement;
+            let mut total = Usage::default();
+            for usage in doc.usage.values() {
+                total.add(usage);
+            }
+            let interactions =
+                engagement.likes + engagement.retweets + engagement.replies + engagement.quotes;
+
+            VersionSummary {
+                version: doc.version,
+                created_at_unix: created_at.timestamp(),
+                lifetime_hours: (ended_at - created_at).num_minutes().max(0) as f64 / 60.0,
+                tweets_sent: doc.tweets_sent,
+                replies_sent: doc.replies_sent,
+                messages_read: doc.messages_read,
+                tweets_measured: doc.tweets.len(),
+                likes: engagement.likes,
+                retweets: engagement.retweets,
+                replies: engagement.replies,
+                quotes: engagement.quotes,
+                impressions: engagement.impressions,
+                engagement_per_tweet: if doc.tweets.is_empty() {
+                    0.0
+                } else {
+                    interactions as f64 / doc.tweets.len() as f64
+                },
+                input_tokens: total.input_tokens,
+                output_tokens: total.output_tokens,
+                cost_usd: total.cost_usd,
+                usage: doc.usage.clone(),
+                branch_reason: branches
+                    .iter()
+                    .rev()
+                    .find(|event| event.version.map(u32::from) == Some(doc.version))
+                    .and_then(|event| event.reason.clone()),
+            }
+        })
+        .collect();
+
+    Ok(summaries)
+}
+
+fn find<'a>(summaries: &'a [VersionSummary], version: &str) -> Result<&'a VersionSummary> {
+    let version = version
+        .trim_start_matches('v')
+        .parse::<u32>()
+        .map_err(|_| anyhow!(USAGE))?;
+    summaries
+        .iter()
+        .find(|summary| summary.version == version)
+        .ok_or_else(|| anyhow!("no stats for version {}", version))
+}
+
+fn print_summaries(summaries: &[VersionSummary]) {
+    if summaries.is_empty() {
+        println!("[STATS] No stats recorded yet");
+        return;
+    }
+
+    println!(
+        "{:>7}  {:<16}  {:>9}  {:>6}  {:>7}  {:>6}  {:>6}  {:>8}  {:>7}  {:>6}  {:>11}  {:>9}  {:>9}  {:>8}  branch",
+        "version",
+        "created",
+        "lifetime",
+        "tweets",
+        "replies",
+        "read",
+        "likes",
+        "retweets",
+        "replied",
+        "quotes",
+        "impressions",
+        "eng/tweet",
+        "tokens",
+        "cost"
+    );
+    for summary in summaries {
+        println!(
+            "{:>7}  {:<16}  {:>8.1}h  {:>6}  {:>7}  {:>6}  {:>6}  {:>8}  {:>7}  {:>6}  {:>11}  {:>9.2}  {:>9}  {:>8}  {}",
+            summary.version,
+            format_unix(summary.created_at_unix, "%Y-%m-%d %H:%M"),
+            summary.lifetime_hours,
+            summary.tweets_sent,
+            summary.replies_sent,
+            summary.messages_read,
+            summary.likes,
+            summary.retweets,
+            summary.replies,
+            summary.quotes,
+            summary.impressions,
+            summary.engagement_per_tweet,
+            summary.input_tokens + summary.output_tokens,
+            format!("${:.4}", summary.cost_usd),
+            summary.branch_reason.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+// Prints the metrics of two versions side by side, with the change from `a` to `b`.
+fn print_comparison(a: &VersionSummary, b: &VersionSummary) {
+    let rows = [
+        ("lifetime (h)", a.lifetime_hours, b.lifetime_hours),
+        ("tweets sent", a.tweets_sent as f64, b.tweets_sent as f64),
+        ("replies sent", a.replies_sent as f64, b.replies_sent as f64),
+        (
+            "messages read",
+            a.messages_read as f64,
+            b.messages_read as f64,
+        ),
+        ("likes", a.likes as f64, b.likes as f64),
+        ("retweets", a.retweets as f64, b.retweets as f64),
+        ("replies", a.replies as f64, b.replies as f64),
+        ("quotes", a.quotes as f64, b.quotes as f64),
+        ("impressions", a.impressions as f64, b.impressions as f64),
+        (
+            "engagement/tweet",
+            a.engagement_per_tweet,
+            b.engagement_per_tweet,
+        ),
+        (
+            "tokens",
+            (a.input_tokens + a.output_tokens) as f64,
+            (b.input_tokens + b.output_tokens) as f64,
+        ),
+        ("cost (USD)", a.cost_usd, b.cost_usd),
+    ];
+
+    let (version_a, version_b) = (format!("v{}", a.version), format!("v{}", b.version));
+    println!(
+        "{:<16}  {:>12}  {:>12}  {:>12}  {:>8}",
+        "", version_a, version_b, "change", "%"
+    );
+    for (name, a, b) in rows {
+        let percent = if a == 0.0 {
+            "-".to_string()
+        } else {
+            format!("{:+.1}", (b - a) / a * 100.0)
+        };
+        println!(
+            "{:<16}  {:>12.2}  {:>12.2}  {:>+12.2}  {:>8}",
+            name,
+            a,
+            b,
+            b - a,
+            percent
+        );
+    }
+}
+
+fn export(summaries: &[VersionSummary], format: &str) -> Result<String> {
+    match format {
+        "json" => Ok(serde_json::to_string_pretty(summaries)?),
+        "csv" => {
+            let mut csv = String::from(
+                "version,created_at_unix,lifetime_hours,tweets_sent,replies_sent,messages_read,tweets_measured,likes,retweets,replies,quotes,impressions,engagement_per_tweet,input_tokens,output_tokens,cost_usd,branch_reason\n",
+            );
+            for summary in summaries {
+                csv.push_str(&format!(
+                    "{},{},{:.2},{},{},{},{},{},{},{},{},{},{:.4},{},{},{:.6},{}\n",
+                    summary.version,
+                    summary.created_at_unix,
+                    summary.lifetime_hours,
+                    summary.tweets_sent,
+                    summary.replies_sent,
+                    summary.messages_read,
+                    summary.tweets_measured,
+                    summary.likes,
+                    summary.retweets,
+                    summary.replies,
+                    summary.quotes,
+                    summary.impressions,
+                    summary.engagement_per_tweet,
+                    summary.input_tokens,
+                    summary.output_tokens,
+                    summary.cost_usd,
+                    csv_field(summary.branch_reason.as_deref().unwrap_or_default())
+                ));
+            }
+            Ok(csv)
+        }
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+fn format_unix(unix: i64, format: &str) -> String {
+    DateTime::<Utc>::from_timestamp(unix, 0)
+        .unwrap_or_default()
+        .format(format)
+        .to_string()
+}
+
+// Quotes a field that contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,589 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use log::info;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Longest look-ahead when searching for the next allowed posting minute.
+const MAX_LOOKAHEAD_MINUTES: i64 = 7 * 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Post,
+    PollMentions,
+    Branch,
+    CollectMetrics,
+}
+
+// A recurring time-of-day window, optionally restricted to some weekdays.
+// Parsed from `[days ]HH:MM-HH:MM`, e.g. `Mon-Fri 08:00-12:00` or `Sat,Sun 10:00-02:00`.
+// Windows ending before they start wrap past midnight; equal start and end, e.g.
+// `00:00-00:00`, cover the whole day.
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (days, range) = match spec.rsplit_once(char::is_whitespace) {
+            Some((days, range)) => (parse_days(days.trim())?, range),
+            None => ([true; 7], spec),
+        };
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow!("invalid time window `{}`, expected HH:MM-HH:MM", spec))?;
+
+        Ok(Self {
+            days,
+            start: NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+            end: NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+        })
+    }
+
+    pub fn contains(&self, time: &DateTime<Tz>) -> bool {
+        let weekday = time.weekday().num_days_from_monday() as usize;
+        let time_of_day = time.time();
+
+        if self.start == self.end {
+            self.days[weekday]
+        } else if self.start < self.end {
+            self.days[weekday] && time_of_day >= self.start && time_of_day < self.end
+        } else {
+            // Wrapping window: the part after midnight belongs to the previous day's window.
+            (self.days[weekday] && time_of_day >= self.start)
+                || (self.days[(weekday + 6) % 7] && time_of_day < self.end)
+        }
+    }
+}
+
+fn parse_days(spec: &str) -> Result<[bool; 7]> {
+    const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+    let day_idx = |day: &str| {
+        DAYS.iter()
+            .position(|name| day.trim().to_lowercase().starts_with(name))
+            .ok_or_else(|| anyhow!("invalid weekday `{}`", day))
+    };
+
+    let mut days = [false; 7];
+    for part in spec.split(',') {
+        if part.trim() == "*" {
+            return Ok([true; 7]);
+        }
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day_idx(from)?, day_idx(to)?);
+                let mut day = from;
+                loop {
+                    days[day] = true;
+                    if day == to {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days[day_idx(part)?] = true,
+        }
+    }
+
+    Ok(days)
+}
+
+// Posts, replies, model tokens and cost counted on `day`, in the schedule's timezone.
+// Callers persist it so the daily caps and budget survive restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub day: String,
+    #[serde(default)]
+    pub posts: u32,
+    #[serde(default)]
+    pub replies: u32,
+    pub tokens: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    pub timezone: Tz,
+    // Posting is only allowed inside these windows; empty means any time.
+    pub post_windows: Vec<TimeWindow>,
+    // No posting or replying inside these windows.
+    pub quiet_hours: Vec<TimeWindow>,
+    pub post_interval_minutes: i64,
+    pub post_jitter_minutes: i64,
+    pub mention_poll_interval_minutes: i64,
+    pub mention_poll_jitter_minutes: i64,
+    // Time-based branching on top of `POSTS_BEFORE_BRANCH`; disabled when `None`.
+    pub branch_interval_hours: Option<i64>,
+    // Engagement metrics collection of recent tweets; disabled when `None`.
+    pub metrics_interval_minutes: Option<i64>,
+    // Daily caps, reset at midnight in `timezone`; disabled when `None`.
+    pub max_posts_per_day: Option<u32>,
+    pub max_replies_per_day: Option<u32>,
+    // Daily model budgets: nothing new is generated once one is spent.
+    pub max_tokens_per_day: Option<u64>,
+    pub max_cost_per_day: Option<f64>,
+}
+
+impl SchedulerConfig {
+    pub fn from_env() -> Result<Self> {
+        let windows = |key: &str| -> Result<Vec<TimeWindow>> {
+            env::var(key)
+                .unwrap_or_default()
+                .split(';')
+                .filter(|spec| !spec.trim().is_empty())
+                .map(TimeWindow::parse)
+                .collect()
+        };
+        let number = |key: &str| env::var(key).ok().and_then(|val| val.parse::<i64>().ok());
+        let cap = |key: &str| {
+            env::var(key)
+                .ok()
+                .and_then(|val| val.parse::<u32>().ok())
+                .filter(|cap| *cap > 0)
+        };
+
+        Ok(Self {
+            timezone: env::var("SCHEDULE_TIMEZONE")
+                .unwrap_or_else(|_| "UTC".to_string())
+                .parse::<Tz>()
+                .map_err(|e| anyhow!("invalid SCHEDULE_TIMEZONE: {}", e))?,
+            post_windows: windows("POST_WINDOWS")?,
+            quiet_hours: windows("QUIET_HOURS")?,
+            post_interval_minutes: number("POST_INTERVAL_MINUTES").unwrap_or(60).max(1),
+            post_jitter_minutes: number("POST_JITTER_MINUTES").unwrap_or(15).max(0),
+            mention_poll_interval_minutes: number("MENTION_POLL_INTERVAL_MINUTES")
+                .unwrap_or(15)
+                .max(1),
+            mention_poll_jitter_minutes: number("MENTION_POLL_JITTER_MINUTES").unwrap_or(3).max(0),
+            branch_interval_hours: number("BRANCH_INTERVAL_HOURS").filter(|hours| *hours > 0),
+            metrics_interval_minutes: number("METRICS_INTERVAL_MINUTES")
+                .filter(|minutes| *minutes > 0),
+            max_posts_per_day: cap("MAX_POSTS_PER_DAY"),
+            max_replies_per_day: cap("MAX_REPLIES_PER_DAY"),
+            max_tokens_per_day: env::var("MAX_TOKENS_PER_DAY")
+                .ok()
+                .and_then(|val| val.parse::<u64>().ok())
+                .filter(|cap| *cap > 0),
+            max_cost_per_day: env::var("MAX_COST_PER_DAY_USD")
+                .ok()
+                .and_then(|val| val.parse::<f64>().ok())
+                .filter(|cap| *cap > 0.0),
+        })
+    }
+}
+
+// Decides when the agent posts, polls mentions and branches. Each task runs on its own
+// cadence with random jitter, and posting/replying respects windows, quiet hours and daily caps.
+pub struct Scheduler {
+    config: SchedulerConfig,
+    next_post: DateTime<Utc>,
+    next_poll: DateTime<Utc>,
+    next_branch: Option<DateTime<Utc>>,
+    next_metrics: Option<DateTime<Utc>>,
+    day: NaiveDate,
+    posts_today: u32,
+    replies_today: u32,
+    tokens_today: u64,
+    cost_today: f64,
+    // Set by an operator: scheduled posts are skipped until resumed.
+    paused: bool,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        let now = Utc::now();
+        let mut scheduler = Self {
+            day: now.with_timezone(&config.timezone).date_naive(),
+            next_post: now,
+            next_poll: now,
+            next_branch: None,
+            next_metrics: None,
+            posts_today: 0,
+            replies_today: 0,
+            tokens_today: 0,
+            cost_today: 0.0,
+            paused: false,
+            config,
+        };
+
+        scheduler.next_post = scheduler.next_allowed_post(scheduler.jittered(
+            now,
+            scheduler.config.post_interval_minutes,
+            scheduler.config.post_jitter_minutes,
+        ));
+        scheduler.next_poll = scheduler.jittered(
+            now,
+            scheduler.config.mention_poll_interval_minutes,
+            scheduler.config.mention_poll_jitter_minutes,
+        );
+        scheduler.next_branch = scheduler
+            .config
+            .branch_interval_hours
+            .map(|hours| now + ChronoDuration::hours(hours));
+        scheduler.next_metrics = scheduler
+            .config
+            .metrics_interval_minutes
+            .map(|minutes| now + ChronoDuration::minutes(minutes));
+
+        scheduler
+    }
+
+    // The earliest task that is due and when it is due. The scheduler is shared with
+    // the pipeline workers, so callers sleep without holding it and then call `advance`.
+    pub fn next_due(&self) -> (Task, DateTime<Utc>) {
+        let (task, due) = [
+            Some((Task::Post, self.next_post)),
+            Some((Task::PollMentions, self.next_poll)),
+            self.next_branch.map(|due| (Task::Branch, due)),
+            self.next_metrics.map(|due| (Task::CollectMetrics, due)),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(_, due)| *due)
+        .expect("post and mention tasks are always scheduled");
+
+        info!(
+            "[SCHEDULER] Next task {:?} at {}",
+            task,
+            due.with_timezone(&self.config.timezone)
+                .format("%Y-%m-%d %H:%M:%S %Z")
+        );
+
+        (task, due)
+    }
+
+    // Schedules the next run of `task` after it fired.
+    pub fn advance(&mut self, task: Task) {
+        let now = Utc::now();
+        self.roll_day(now);
+        match task {
+            Task::Post => {
+                self.next_post = self.next_allowed_post(self.jittered(
+                    now,
+                    self.config.post_interval_minutes,
+                    self.config.post_jitter_minutes,
+                ))
+            }
+            Task::PollMentions => {
+                self.next_poll = self.jittered(
+                    now,
+                    self.config.mention_poll_interval_minutes,
+                    self.config.mention_poll_jitter_minutes,
+                )
+            }
+            Task::Branch => {
+                self.next_branch = self
+                    .config
+                    .branch_interval_hours
+                    .map(|hours| now + ChronoDuration::hours(hours))
+            }
+            Task::CollectMetrics => {
+                self.next_metrics = self
+                    .config
+                    .metrics_interval_minutes
+                    .map(|minutes| now + ChronoDuration::minutes(minutes))
+            }
+        }
+    }
+
+    // Holds `task` off until `until`, e.g. while the Twitter quota it needs is exhausted.
+    pub fn defer(&mut self, task: Task, until: DateTime<Utc>) {
+        match task {
+            Task::Post => self.next_post = self.next_allowed_post(self.next_post.max(until)),
+            Task::PollMentions => self.next_poll = self.next_poll.max(until),
+            Task::Branch => self.next_branch = self.next_branch.map(|due| due.max(until)),
+            Task::CollectMetrics => self.next_metrics = self.next_metrics.map(|due| due.max(until)),
+        }
+    }
+
+    // Whether a post may be published right now.
+    pub fn can_post(&mut self) -> bool {
+        let now = Utc::now();
+        self.roll_day(now);
+        self.post_allowed_at(now) && !self.post_cap_reached()
+    }
+
+    // Whether today's posts used up `MAX_POSTS_PER_DAY`.
+    pub fn post_cap_reached(&mut self) -> bool {
+        self.roll_day(Utc::now());
+        self.config
+            .max_posts_per_day
+            .is_some_and(|cap| self.posts_today >= cap)
+    }
+
+    // How many replies may still be sent right now, capped at `budget`. The `held`
+    // replies waiting for approval count toward the daily cap.
+    pub fn reply_budget(&mut self, budget: usize, held: usize) -> usize {
+        let now = Utc::now();
+        self.roll_day(now);
+        if self.in_quiet_hours(now) {
+            return 0;
+        }
+
+        match self.config.max_replies_per_day {
+            Some(cap) => {
+                budget.min((cap.saturating_sub(self.replies_today) as usize).saturating_sub(held))
+            }
+            None => budget,
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn next_post(&self) -> DateTime<Utc> {
+        self.next_post
+    }
+
+    pub fn record_post(&mut self) {
+        self.roll_day(Utc::now());
+        self.posts_today += 1;
+    }
+
+    pub fn record_replies(&mut self, count: usize) {
+        self.roll_day(Utc::now());
+        self.replies_today += count as u32;
+    }
+
+    pub fn record_usage(&mut self, tokens: u64, cost_usd: f64) {
+        self.roll_day(Utc::now());
+        self.tokens_today += tokens;
+        self.cost_today += cost_usd;
+    }
+
+    // Tokens and cost spent today.
+    pub fn usage_today(&mut self) -> (u64, f64) {
+        self.roll_day(Utc::now());
+        (self.tokens_today, self.cost_today)
+    }
+
+    pub fn daily_usage(&mut self) -> DailyUsage {
+        self.roll_day(Utc::now());
+        DailyUsage {
+            day: self.day.to_string(),
+            posts: self.posts_today,
+            replies: self.replies_today,
+            tokens: self.tokens_today,
+            cost_usd: self.cost_today,
+        }
+    }
+
+    // Picks up the usage saved by a previous run, unless it was counted on another day.
+    pub fn restore_usage(&mut self, usage: &DailyUsage) {
+        self.roll_day(Utc::now());
+        if usage.day == self.day.to_string() {
+            self.posts_today = usage.posts;
+            self.replies_today = usage.replies;
+            self.tokens_today = usage.tokens;
+            self.cost_today = usage.cost_usd;
+        }
+    }
+
+    // Whether today's token or cost budget is spent.
+    pub fn budget_exceeded(&mut self) -> bool {
+        self.roll_day(Utc::now());
+        self.config
+            .max_tokens_per_day
+            .is_some_and(|cap| self.tokens_today >= cap)
+            || self
+                .config
+                .max_cost_per_day
+                .is_some_and(|cap| self.cost_today >= cap)
+    }
+
+    fn roll_day(&mut self, now: DateTime<Utc>) {
+        let today = now.with_timezone(&self.config.timezone).date_naive();
+        if today != self.day {
+            info!(
+                "[SCHEDULER] New day, resetting caps ({} posts, {} replies, {} tokens, ${:.4} yesterday)",
+                self.posts_today, self.replies_today, self.tokens_today, self.cost_today
+            );
+            self.day = today;
+            self.posts_today = 0;
+            self.replies_today = 0;
+            self.tokens_today = 0;
+            self.cost_today = 0.0;
+        }
+    }
+
+    fn in_quiet_hours(&self, time: DateTime<Utc>) -> bool {
+        let local = time.with_timezone(&self.config.timezone);
+        self.config
+            .quiet_hours
+            .iter()
+            .any(|window| window.contains(&local))
+    }
+
+    fn post_allowed_at(&self, time: DateTime<Utc>) -> bool {
+        let local = time.with_timezone(&self.config.timezone);
+        !self.in_quiet_hours(time)
+            && (self.config.post_windows.is_empty()
+                || self
+                    .config
+                    .post_windows
+                    .iter()
+                    .any(|window| window.contains(&local)))
+    }
+
+    // Moves `time` forward to the first minute that is inside a posting window and
+    // outside quiet hours, plus jitter so posts don't all land on the window start.
+    fn next_allowed_post(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        if self.post_allowed_at(time) {
+            return time;
+        }
+
+        (1..=MAX_LOOKAHEAD_MINUTES)
+            .map(|minutes| time + ChronoDuration::minutes(minutes))
+            .find(|candidate| self.post_allowed_at(*candidate))
+            .map(|start| {
+                let jitter = thread_rng().gen_range(0..=self.config.post_jitter_minutes * 60);
+                let jittered = start + ChronoDuration::seconds(jitter);
+                if self.post_allowed_at(jittered) {
+                    jittered
+                } else {
+                    start
+                }
+            })
+            .unwrap_or(time)
+    }
+
+    // `from + interval ± jitter`, never earlier than one minute from `from`.
+    fn jittered(
+        &self,
+        from: DateTime<Utc>,
+        interval_minutes: i64,
+        jitter_minutes: i64,
+    ) -> DateTime<Utc> {
+        let jitter_secs = jitter_minutes * 60;
+        let offset = interval_minutes * 60 + thread_rng().gen_range(-jitter_secs..=jitter_secs);
+        from + ChronoDuration::seconds(offset.max(60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn config() -> SchedulerConfig {
+        SchedulerConfig {
+            timezone: Tz::UTC,
+            post_windows: vec![],
+            quiet_hours: vec![],
+            post_interval_minutes: 60,
+            post_jitter_minutes: 0,
+            mention_poll_interval_minutes: 15,
+            mention_poll_jitter_minutes: 0,
+            branch_interval_hours: None,
+            metrics_interval_minutes: None,
+            max_posts_per_day: Some(2),
+            max_replies_per_day: Some(3),
+            max_tokens_per_day: None,
+            max_cost_per_day: None,
+        }
+    }
+
+    #[test]
+    fn daily_caps_survive_a_restart() {
+        let mut scheduler = Scheduler::new(config());
+        scheduler.record_post();
+        scheduler.record_post();
+        scheduler.record_replies(2);
+        scheduler.record_usage(100, 0.5);
+        let saved = scheduler.daily_usage();
+
+        let mut restarted = Scheduler::new(config());
+        restarted.restore_usage(&saved);
+
+        assert!(restarted.post_cap_reached());
+        assert_eq!(restarted.reply_budget(5, 0), 1);
+        assert_eq!(restarted.usage_today(), (100, 0.5));
+    }
+
+    #[test]
+    fn usage_of_another_day_is_not_restored() {
+        let mut scheduler = Scheduler::new(config());
+        scheduler.record_post();
+        scheduler.record_post();
+        let saved = DailyUsage {
+            day: "2000-01-01".to_string(),
+            ..scheduler.daily_usage()
+        };
+
+        let mut restarted = Scheduler::new(config());
+        restarted.restore_usage(&saved);
+
+        assert!(!restarted.post_cap_reached());
+        assert_eq!(restarted.reply_budget(5, 0), 3);
+    }
+
+    #[test]
+    fn held_replies_count_toward_the_reply_cap() {
+        let mut scheduler = Scheduler::new(config());
+        scheduler.record_replies(1);
+
+        assert_eq!(scheduler.reply_budget(5, 1), 1);
+        assert_eq!(scheduler.reply_budget(5, 4), 0);
+    }
+
+    // 2024-01-01 is a Monday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        Tz::UTC
+            .with_ymd_and_hms(2024, 1, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn window_past_midnight_belongs_to_the_day_it_starts() {
+        let window = TimeWindow::parse("Fri 22:00-02:00").unwrap();
+
+        assert!(window.contains(&at(5, 23, 0)));
+        assert!(window.contains(&at(6, 1, 59)));
+        assert!(!window.contains(&at(6, 2, 0)));
+        assert!(!window.contains(&at(5, 1, 0)));
+        assert!(!window.contains(&at(6, 23, 0)));
+    }
+
+    #[test]
+    fn day_range_wraps_over_the_weekend() {
+        let window = TimeWindow::parse("Fri-Mon 08:00-12:00").unwrap();
+
+        for day in [5, 6, 7, 8] {
+            assert!(window.contains(&at(day, 9, 0)), "day {}", day);
+        }
+        for day in [2, 3, 4] {
+            assert!(!window.contains(&at(day, 9, 0)), "day {}", day);
+        }
+        assert!(!window.contains(&at(5, 12, 0)));
+    }
+
+    #[test]
+    fn window_with_equal_start_and_end_covers_the_whole_day() {
+        let window = TimeWindow::parse("00:00-00:00").unwrap();
+        assert!(window.contains(&at(1, 0, 0)));
+        assert!(window.contains(&at(3, 23, 59)));
+
+        let window = TimeWindow::parse("Sat,Sun 00:00-00:00").unwrap();
+        assert!(window.contains(&at(6, 12, 0)));
+        assert!(!window.contains(&at(5, 12, 0)));
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        assert!(TimeWindow::parse("08:00").is_err());
+        assert!(TimeWindow::parse("Funday 08:00-12:00").is_err());
+        assert!(TimeWindow::parse("25:00-26:00").is_err());
+    }
+}
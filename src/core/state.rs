@@ -0,0 +1,101 @@
+use super::character::Character;
+use super::scheduler::DailyUsage;
+use crate::clients::twitter::rate_limit::MonthlyUsage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+// Runtime state carried across restarts, persisted to `state/<character>.state.json`
+// on shutdown so the agent resumes where it stopped instead of starting fresh.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentState {
+    #[serde(skip)]
+    path: PathBuf,
+    // Newest mention already fetched; older mentions were queued or answered.
+    pub latest_mention_id: Option<u64>,
+    pub previous_posts: Vec<String>,
+    pub posts_since_branch: u8,
+    #[serde(default)]
+    pub twitter_usage: MonthlyUsage,
+    // Mention cursors of the other platforms, by platform name.
+    #[serde(default)]
+    pub social_cursors: HashMap<String, String>,
+    // Recent tweets whose engagement metrics are still collected.
+    #[serde(default)]
+    pub posted_tweets: Vec<PostedTweet>,
+    // Unix time of the latest post, by platform name.
+    #[serde(default)]
+    pub last_posts: HashMap<String, i64>,
+    #[serde(default)]
+    pub daily_usage: DailyUsage,
+}
+
+// A tweet the agent posted, with the character version that wrote it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostedTweet {
+    pub id: u64,
+    pub version: u8,
+    pub is_reply: bool,
+    pub posted_at_unix: i64,
+}
+
+impl AgentState {
+    pub fn load(character_name: &str) -> Result<Self> {
+        let path = Path::new("state").join(format!("{}.state.json", character_name));
+
+        let state = if path.exists() {
+            serde_json::from_str::<AgentState>(&fs::read_to_string(&path)?)?
+        } else {
+            AgentState::default()
+        };
+
+        Ok(Self { path, ..state })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        {
+            let mut state_file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp_path)?;
+            serde_json::to_writer_pretty(&mut state_file, self)?;
+        }
+        fs::rename(temp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    // Restores previous posts and the branching counter saved by the last run.
+    pub fn restore(&self, character: &mut Character) {
+        character.previous_posts = self.previous_posts.iter().cloned().collect();
+        character.posts_since_branch = self.posts_since_branch;
+    }
+
+    // Tracks a new tweet, forgetting the ones posted before `keep_since`.
+    pub fn record_tweet(&mut self, tweet: PostedTweet, keep_since: i64) {
+        self.posted_tweets
+            .retain(|posted| posted.posted_at_unix >= keep_since);
+        self.posted_tweets.push(tweet);
+    }
+
+    pub fn record_post(&mut self, platform: &str, posted_at_unix: i64) {
+        self.last_posts.insert(platform.to_string(), posted_at_unix);
+    }
+
+    pub fn record(&mut self, character: &Character) {
+        self.previous_posts = character.previous_posts.iter().cloned().collect();
+        self.posts_since_branch = character.posts_since_branch;
+    }
+}
@@ -0,0 +1,199 @@
+use super::events::Action;
+use super::llm::TokenUsage;
+use anyhow::{anyhow, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use log::{error, info};
+use prometheus::{
+    register_counter_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    CounterVec, Encoder, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::{convert::Infallible, env, future::Future, net::SocketAddr, time::Duration};
+
+// Buckets of the LLM and embedding latency histograms, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0];
+
+lazy_static! {
+    static ref GENERATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "loreweaver_generation_duration_seconds",
+        "Time to get an answer from a model, retries included.",
+        &["model"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap();
+    static ref GENERATIONS: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_generations_total",
+        "Chat completions by model and outcome, after retries.",
+        &["model", "outcome"]
+    )
+    .unwrap();
+    static ref TOKENS: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_tokens_total",
+        "Tokens billed for model calls, by model and input or output.",
+        &["model", "kind"]
+    )
+    .unwrap();
+    static ref COST: CounterVec = register_counter_vec!(
+        "loreweaver_cost_usd_total",
+        "Cost of model calls in USD, by model.",
+        &["model"]
+    )
+    .unwrap();
+    static ref PUBLISHED: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_published_total",
+        "Posts and replies published or failed to publish, by platform.",
+        &["platform", "kind", "outcome"]
+    )
+    .unwrap();
+    static ref LAST_POST: IntGaugeVec = register_int_gauge_vec!(
+        "loreweaver_last_post_timestamp_seconds",
+        "Unix time of the latest published post, by platform.",
+        &["platform"]
+    )
+    .unwrap();
+    static ref RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_rate_limited_total",
+        "Requests answered with a 429, by platform.",
+        &["platform"]
+    )
+    .unwrap();
+    static ref EMBEDDING_SECONDS: HistogramVec = register_histogram_vec!(
+        "loreweaver_embedding_duration_seconds",
+        "Time of a single embedding call, by outcome.",
+        &["outcome"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap();
+    static ref BRANCHES: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_branches_total",
+        "Lore branches by trigger and outcome.",
+        &["trigger", "outcome"]
+    )
+    .unwrap();
+    static ref EVENTS: IntCounterVec = register_int_counter_vec!(
+        "loreweaver_events_total",
+        "Events recorded to the event log, by action.",
+        &["action"]
+    )
+    .unwrap();
+}
+
+fn outcome(ok: bool) -> &'static str {
+    if ok {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+pub fn generated(model: &str, latency: Duration) {
+    GENERATION_SECONDS
+        .with_label_values(&[model])
+        .observe(latency.as_secs_f64());
+    GENERATIONS.with_label_values(&[model, outcome(true)]).inc();
+}
+
+// Tokens and cost of a chat completion or embedding call.
+pub fn spent(model: &str, usage: &TokenUsage, cost_usd: f64) {
+    TOKENS
+        .with_label_values(&[model, "input"])
+        .inc_by(usage.input_tokens);
+    TOKENS
+        .with_label_values(&[model, "output"])
+        .inc_by(usage.output_tokens);
+    COST.with_label_values(&[model]).inc_by(cost_usd);
+}
+
+pub fn generation_failed(model: &str) {
+    GENERATIONS
+        .with_label_values(&[model, outcome(false)])
+        .inc();
+}
+
+// `kind` is `post` or `reply`.
+pub fn published(platform: &str, kind: &str, ok: bool) {
+    PUBLISHED
+        .with_label_values(&[platform, kind, outcome(ok)])
+        .inc();
+    if ok && kind == "post" {
+        LAST_POST
+            .with_label_values(&[platform])
+            .set(chrono::Utc::now().timestamp());
+    }
+}
+
+// Sets the last post time saved by a previous run, so it survives restarts.
+pub fn restore_last_post(platform: &str, posted_at_unix: i64) {
+    LAST_POST.with_label_values(&[platform]).set(posted_at_unix);
+}
+
+pub fn rate_limited(platform: &str) {
+    RATE_LIMITED.with_label_values(&[platform]).inc();
+}
+
+pub fn embedded(latency: Duration, ok: bool) {
+    EMBEDDING_SECONDS
+        .with_label_values(&[outcome(ok)])
+        .observe(latency.as_secs_f64());
+}
+
+pub fn branched(trigger: &str, ok: bool) {
+    BRANCHES.with_label_values(&[trigger, outcome(ok)]).inc();
+}
+
+pub fn event(action: Action) {
+    EVENTS.with_label_values(&[action.as_str()]).inc();
+}
+
+// Reads `PROMETHEUS_ADDR`. `None` when it isn't set, which disables the endpoint.
+pub fn addr_from_env() -> Result<Option<SocketAddr>> {
+    match env::var("PROMETHEUS_ADDR") {
+        Ok(addr) if !addr.is_empty() => addr
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow!("invalid `PROMETHEUS_ADDR` {}: {}", addr, e)),
+        _ => Ok(None),
+    }
+}
+
+// Serves `GET /metrics` in the Prometheus text format until `shutdown` resolves.
+pub async fn serve(addr: SocketAddr, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    info!(
+        "[METRICS] Serving Prometheus metrics on http://{}/metrics",
+        addr
+    );
+    server.with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = Response::builder();
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(response
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .expect("status is valid"));
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("[METRICS] Unexpected error encoding metrics: {}", e);
+        return Ok(response
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .expect("status is valid"));
+    }
+
+    Ok(response
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .expect("content type is valid"))
+}
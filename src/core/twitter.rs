@@ -1,262 +1,1183 @@
-use super::character::Character;
-use crate::clients::twitter::twitter::{Client as TwitterClient, TwitterAuth};
+use super::admin::{self, AdminCall, AdminConfig, AdminRequest, AdminResponse};
+use super::character::{Character, Persona, PromptInputs, PromptOverrides, TWITTER};
+use super::events::{Action, Event, EventInputs, EventLog};
+use super::llm::{
+    billed, with_retries, Embedder, Extractor, Generation, Llm, Prices, RetryConfig, TokenUsage,
+};
+use super::logging;
+use super::media::{Attachment, Media, MediaImage};
+use super::mentions::{
+    is_blocked, MentionQueue, QueuedMention, QueuedSocialMention, ReplySelection,
+    SocialMentionQueue, MENTION_MAX_AGE_HOURS, REPLIES_PER_CYCLE,
+};
+use super::report;
+use super::scheduler::{Scheduler, SchedulerConfig, Task};
+use super::state::{AgentState, PostedTweet};
+use super::telemetry;
+use crate::clients::twitter::{
+    rate_limit::Endpoint,
+    twitter::{AuthoredTweet, Client as TwitterClient, TwitterAuth},
+};
+use crate::clients::{
+    retry::UnknownOutcome,
+    social::{SocialClient, SocialPost},
+};
 use crate::core::Message;
-use crate::db::mongo::{mongo::Client as MongoClient, Credentials as MongoCredentials};
-use anyhow::{Error, Result};
-use chrono::Utc;
-use log::{error, info};
-use rand::rngs::ThreadRng;
-use rand::{seq::SliceRandom, thread_rng, Rng};
+use crate::db::store::{Engagement, Scope, Storage, Store, StoreConfig, Usage};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use hyper::StatusCode;
+use log::{error, info, warn};
+use rand::thread_rng;
 use rig::{
-    agent::Agent,
-    completion::{Chat, Message as CompletionMessage},
-    embeddings::{Embedding, EmbeddingsBuilder},
-    providers::{
-        anthropic::{completion::CompletionModel as AnthropicCompletionModel, ClientBuilder},
-        openai::{Client, EmbeddingModel, TEXT_EMBEDDING_ADA_002},
+    completion::Message as CompletionMessage, embeddings::Embedding,
+    providers::openai::TEXT_EMBEDDING_ADA_002, OneOrMany,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    signal,
+    sync::{
+        mpsc::{self, error::TrySendError, Receiver, Sender},
+        watch,
     },
-    OneOrMany,
+    time::sleep,
 };
-use std::time::Duration;
-use tokio::time::sleep;
+use twitter_v2::id::NumericId;
 
 pub struct Instance {
-    agent: Agent<AnthropicCompletionModel>,
-    embedding_model: EmbeddingModel,
-    twitter_client: TwitterClient,
-    mongo_client: MongoClient,
+    llm: Llm,
+    reply_selector: Extractor<ReplySelection>,
+    embedder: Embedder,
+    twitter_client: Arc<TwitterClient>,
+    social_clients: Arc<Vec<SocialClient>>,
+    store: Arc<Store>,
+    events: Arc<EventLog>,
     character: Character,
+    timeline: Vec<String>,
+    media: Media,
+    // When a post was last generated for each platform, for per-platform post intervals.
+    last_posted: HashMap<String, DateTime<Utc>>,
+    mention_queue: Arc<Mutex<MentionQueue>>,
+    social_queue: Arc<Mutex<SocialMentionQueue>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    state: Arc<Mutex<AgentState>>,
+    use_stats: bool,
+    admin: Option<AdminConfig>,
+    metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shutdown {
+    Running,
+    // Signal received: no new work is started, in-flight work is finished.
+    Draining,
+    // Grace period over or second signal: pending work is dropped.
+    Cancelled,
+}
+
+// Counters for the shutdown summary.
+#[derive(Default)]
+struct RunSummary {
+    posts: AtomicUsize,
+    replies: AtomicUsize,
+    mentions_read: AtomicUsize,
+    memories: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+// The running character, as shown by the admin API. Refreshed by the generation worker.
+#[derive(Debug, Clone, Serialize)]
+struct CharacterStatus {
+    name: String,
+    alias: String,
+    version: u8,
+    posts_since_branch: u8,
+    previous_posts: Vec<String>,
+}
+
+impl CharacterStatus {
+    fn of(character: &Character) -> Self {
+        Self {
+            name: character.character_name.clone(),
+            alias: character.alias.clone(),
+            version: character.version,
+            posts_since_branch: character.posts_since_branch,
+            previous_posts: character.previous_posts.iter().cloned().collect(),
+        }
+    }
+}
+
+// Generated replies waiting for an operator's approval, by id.
+#[derive(Default)]
+struct PendingReplies {
+    next_id: u64,
+    replies: BTreeMap<u64, (DateTime<Utc>, PublishTask)>,
+}
+
+impl PendingReplies {
+    fn hold(&mut self, task: PublishTask) -> u64 {
+        self.next_id += 1;
+        self.replies.insert(self.next_id, (Utc::now(), task));
+        self.next_id
+    }
+
+    fn take(&mut self, id: u64) -> Option<(DateTime<Utc>, PublishTask)> {
+        self.replies.remove(&id)
+    }
+
+    // Puts a reply taken for approval back under its id.
+    fn restore(&mut self, id: u64, held_at: DateTime<Utc>, task: PublishTask) {
+        self.replies.insert(id, (held_at, task));
+    }
+
+    // Removes and returns the replies held for longer than `max_age`.
+    fn expire(&mut self, max_age: ChronoDuration) -> Vec<(u64, PublishTask)> {
+        let cutoff = Utc::now() - max_age;
+        let expired = self
+            .replies
+            .iter()
+            .filter(|(_, (held_at, _))| *held_at < cutoff)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|id| self.replies.remove(&id).map(|(_, task)| (id, task)))
+            .collect()
+    }
+}
+
+// Handles shared between the pipeline workers.
+#[derive(Clone)]
+struct Pipeline {
+    twitter_client: Arc<TwitterClient>,
+    social_clients: Arc<Vec<SocialClient>>,
+    store: Arc<Store>,
+    events: Arc<EventLog>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    mention_queue: Arc<Mutex<MentionQueue>>,
+    social_queue: Arc<Mutex<SocialMentionQueue>>,
+    state: Arc<Mutex<AgentState>>,
+    character: Arc<Mutex<CharacterStatus>>,
+    pending_replies: Arc<Mutex<PendingReplies>>,
+    summary: Arc<RunSummary>,
+    shutdown: watch::Receiver<Shutdown>,
+    started_at: DateTime<Utc>,
     use_stats: bool,
+    approve_replies: bool,
+}
+
+impl Pipeline {
+    fn shutdown(&self) -> Shutdown {
+        *self.shutdown.borrow()
+    }
+}
+
+// Work for the generation worker, which owns the agent and the character.
+enum GenerateTask {
+    Post,
+    Mentions {
+        mentions: Vec<AuthoredTweet>,
+        // Mentions from the other platforms, with the index of their client.
+        social: Vec<(usize, SocialPost)>,
+        // Replies allowed this cycle, shared by all platforms.
+        budget: usize,
+    },
+    Branch,
 }
 
+// Generated content waiting to be published, each with the event recorded once it is.
+// `version` is the character version that generated it, so stats still land on the
+// right version if a branch happens meanwhile.
+enum PublishTask {
+    Post {
+        // `None` when Twitter isn't due for a post.
+        text: Option<(String, Event)>,
+        // Posts for the other platforms, with the index of their client.
+        variants: Vec<(usize, String, Event)>,
+        version: u8,
+        attachment: Option<Attachment>,
+    },
+    Reply {
+        mention: QueuedMention,
+        text: String,
+        version: u8,
+        event: Event,
+    },
+    SocialReply {
+        client: usize,
+        mention: QueuedSocialMention,
+        text: String,
+        event: Event,
+    },
+}
+
+// Queued mentions listed by the admin API.
+const QUEUE_PREVIEW_SIZE: usize = 10;
+
+// Number of new mentions fetched per cycle before triage.
+const MENTIONS_FETCH_SIZE: usize = 20;
+// Number of top ranked mentions offered to the model per reply.
+const REPLY_CANDIDATES: usize = 5;
+// Maximum number of earlier tweets in a thread passed as reply history.
+const MAX_THREAD_DEPTH: usize = 8;
+
+// Number of timeline tweets fetched and kept as reference for posts.
+const TIMELINE_SIZE: usize = 10;
+// Longest timeline entry (in characters) included in the post prompt.
+const TIMELINE_ENTRY_MAX_CHARS: usize = 200;
+
+// Engagement metrics are collected for tweets up to this old, at most 100 per run.
+const METRICS_MAX_AGE_DAYS: i64 = 7;
+const METRICS_MAX_TWEETS: usize = 100;
+
+// Tasks buffered between pipeline stages. Scheduled runs are skipped while the
+// next stage is still this far behind, instead of piling up.
+const PIPELINE_QUEUE_SIZE: usize = 2;
+// Messages buffered for the memory worker, so slow embeddings don't hold up generation.
+const MEMORY_QUEUE_SIZE: usize = 64;
+// How long in-flight work may take to finish after a shutdown signal before it is cancelled.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
 impl Instance {
     pub async fn new(
         anthropic_api_key: &str,
         openai_api_key: &str,
-        mongo_credentials: MongoCredentials,
+        store_config: StoreConfig,
         twitter_credentials: TwitterAuth,
-        character: Character,
+        mut character: Character,
         use_stats: bool,
+        environment: String,
     ) -> Result<Self> {
-        let anthropic = ClientBuilder::new(anthropic_api_key).build();
-        let embedding_model = Client::new(openai_api_key).embedding_model(TEXT_EMBEDDING_ADA_002);
-        let twitter_client = TwitterClient::new(twitter_credentials).await;
-        let mongo_client = MongoClient::new(mongo_credentials).await?;
+        let embedder = Embedder::new(openai_api_key, TEXT_EMBEDDING_ADA_002);
+        let mut twitter_client = TwitterClient::new(twitter_credentials).await?;
+        let social_clients = SocialClient::from_env().await?;
+        let scope = Scope {
+            character: character.character_name.clone(),
+            environment,
+        };
+        let store = Arc::new(Store::open(store_config, scope.clone()).await?);
+        let mention_queue = MentionQueue::load(&character.character_name)?;
+        let social_queue = SocialMentionQueue::load(&character.character_name)?;
+        let mut scheduler_config = SchedulerConfig::from_env()?;
+        // Engagement metrics are only collected into the stats.
+        if !use_stats {
+            scheduler_config.metrics_interval_minutes = None;
+        }
+        let mut scheduler = Scheduler::new(scheduler_config);
+
+        let state = AgentState::load(&character.character_name)?;
+        state.restore(&mut character);
+        scheduler.restore_usage(&state.daily_usage);
+        if let Some(latest_mention_id) = state.latest_mention_id {
+            twitter_client.set_latest_mention_id(NumericId::new(latest_mention_id));
+        }
+        twitter_client.set_monthly_usage(state.twitter_usage.clone());
+        for (platform, posted_at) in &state.last_posts {
+            telemetry::restore_last_post(platform, *posted_at);
+        }
+        let state = Arc::new(Mutex::new(state));
+        // Monthly usage is saved as soon as it changes, so a crash can't reset the caps.
+        let usage_state = state.clone();
+        twitter_client.on_usage_change(move |usage| {
+            let mut state = usage_state.lock().unwrap();
+            state.twitter_usage = usage.clone();
+            if let Err(e) = state.save() {
+                error!("[TWITTER] Unexpected error saving Twitter usage: {}", e);
+            }
+        });
+        for client in &social_clients {
+            if let Some(cursor) = state
+                .lock()
+                .unwrap()
+                .social_cursors
+                .get(client.platform().name())
+            {
+                client.set_cursor(cursor);
+            }
+        }
+        info!(
+            "[TWITTER] Connected to {} other platform(s)",
+            social_clients.len()
+        );
+        info!(
+            "[TWITTER] Using {} storage ({} / {})",
+            store.name(),
+            scope.character,
+            scope.environment
+        );
 
+        let llm = Llm::new(anthropic_api_key, openai_api_key, &character.bio, 4096, 1.0)?;
         Ok(Self {
-            agent: anthropic
-                .agent("claude-3-5-sonnet-20241022")
-                .max_tokens(4096)
-                .preamble(&character.bio)
-                .temperature(1.0)
-                .build(),
-            embedding_model,
+            reply_selector: llm.extractor::<ReplySelection>(&character.bio)?,
+            llm,
+            embedder,
             character,
-            twitter_client,
-            mongo_client,
+            timeline: Vec::new(),
+            media: Media::from_env(openai_api_key)?,
+            last_posted: HashMap::new(),
+            mention_queue: Arc::new(Mutex::new(mention_queue)),
+            social_queue: Arc::new(Mutex::new(social_queue)),
+            scheduler: Arc::new(Mutex::new(scheduler)),
+            state,
+            twitter_client: Arc::new(twitter_client),
+            social_clients: Arc::new(social_clients),
+            events: Arc::new(EventLog::new(store.clone(), use_stats)),
+            store,
             use_stats,
+            admin: AdminConfig::from_env()?,
+            metrics_addr: telemetry::addr_from_env()?,
         })
     }
 
-    // When implementing more than one client:
-    // Runs a loop processing each task request on the main thread, and executes them sequentially
-    // Flow is to recv task in queue -> generate response -> match handler with client enum -> `publish()`
-    pub async fn run(&mut self) {
-        info!("[TWITTER] Loop started now waiting..");
+    // Runs the task pipeline: the scheduler queues tasks and separate workers poll mentions,
+    // generate responses, publish them and write memories, each at their own pace.
+    // Flow is to recv task in queue -> generate response -> `publish()` / `reply()`
+    // On SIGINT/SIGTERM the scheduler stops and in-flight work gets `SHUTDOWN_GRACE_PERIOD`
+    // to finish before it is cancelled. State is then flushed and the client is killed.
+    pub async fn run(mut self) {
+        info!("[TWITTER] Pipeline started now waiting..");
+        let started_at = Utc::now();
 
-        // Create RNG once, outside the loop
-        let mut rng = thread_rng();
-        loop {
-            if self.use_stats {
-                let _ = self.version_doc_check().await;
+        if self.use_stats {
+            if let Err(e) = self.upsert_version_doc().await {
+                error!("[STATS_DB] Failed to upsert version document: {}", e);
             }
+        }
 
-            //Randomly execute between 10 and 11 minutes.
-            sleep(Duration::from_secs(rng.gen_range(10..11) * 60)).await;
+        let (shutdown_tx, shutdown_rx) = watch::channel(Shutdown::Running);
+        let (poll_tx, poll_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+        let (generate_tx, generate_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+        let (publish_tx, publish_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+        let (memory_tx, memory_rx) = mpsc::channel(MEMORY_QUEUE_SIZE);
+        let (metrics_tx, metrics_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+        let (approved_tx, approved_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+        let admin_config = self.admin.take();
 
-            // Generate number 0-99 for percentage-based selection
-            match rng.gen_range(0..100) {
-                0..79 => {
-                    let prompt = self.gen_twitter_post_prompt(&mut rng);
+        let pipeline = Pipeline {
+            twitter_client: self.twitter_client.clone(),
+            social_clients: self.social_clients.clone(),
+            store: self.store.clone(),
+            events: self.events.clone(),
+            scheduler: self.scheduler.clone(),
+            mention_queue: self.mention_queue.clone(),
+            social_queue: self.social_queue.clone(),
+            state: self.state.clone(),
+            character: Arc::new(Mutex::new(CharacterStatus::of(&self.character))),
+            pending_replies: Arc::new(Mutex::new(PendingReplies::default())),
+            summary: Arc::new(RunSummary::default()),
+            shutdown: shutdown_rx,
+            started_at,
+            use_stats: self.use_stats,
+            approve_replies: admin_config
+                .as_ref()
+                .is_some_and(|config| config.approve_replies),
+        };
 
-                    let generated_tweet = match self.handle_generate(&prompt, vec![]).await {
-                        Ok(tweet) => tweet,
-                        Err(e) => {
-                            error!(
-                                "[TWITTER] Unexpected error generating tweet: {}. Skipping...",
-                                e
-                            );
-                            continue;
-                        }
-                    };
-                    info!("[TWITTER] Generated tweet");
+        // The admin server stops taking requests once shutdown starts, which closes
+        // the admin worker and its handle on the generation queue.
+        let admin = admin_config.map(|config| {
+            let (admin_tx, admin_rx) = mpsc::channel(PIPELINE_QUEUE_SIZE);
+            let mut shutdown = pipeline.shutdown.clone();
+            let server = tokio::spawn(async move {
+                let stopped = async move {
+                    let _ = shutdown.wait_for(|state| *state != Shutdown::Running).await;
+                };
+                if let Err(e) = admin::serve(&config, admin_tx, stopped).await {
+                    error!("[ADMIN] Unexpected error serving admin API: {}", e);
+                }
+            });
+            let worker = tokio::spawn(answer_admin(
+                pipeline.clone(),
+                admin_rx,
+                generate_tx.clone(),
+                approved_tx,
+            ));
+            (server, worker)
+        });
+        let metrics_server = self.metrics_addr.map(|addr| {
+            let mut shutdown = pipeline.shutdown.clone();
+            tokio::spawn(async move {
+                let stopped = async move {
+                    let _ = shutdown.wait_for(|state| *state != Shutdown::Running).await;
+                };
+                if let Err(e) = telemetry::serve(addr, stopped).await {
+                    error!("[METRICS] Unexpected error serving metrics: {}", e);
+                }
+            })
+        });
 
-                    self.character.add_previous_post(&generated_tweet);
+        let poller = tokio::spawn(poll_mentions(
+            pipeline.clone(),
+            poll_rx,
+            generate_tx.clone(),
+        ));
+        let publisher = tokio::spawn(publish(
+            pipeline.clone(),
+            publish_rx,
+            approved_rx,
+            memory_tx,
+        ));
+        let memory = tokio::spawn(store_memories(
+            pipeline.clone(),
+            self.embedder.clone(),
+            self.llm.prices().clone(),
+            memory_rx,
+        ));
+        let metrics = tokio::spawn(collect_metrics(pipeline.clone(), metrics_rx));
+        let generator = tokio::spawn(self.generate(pipeline.clone(), generate_rx, publish_tx));
 
-                    match self.twitter_client.publish(&generated_tweet).await {
-                        Ok(_) => info!("[TWITTER] Successfully published tweet"),
-                        Err(e) => error!(
-                            "[TWITTER] Unexpected error occured whilst publishing tweet: {}. Skipping...",
-                            e
-                        ),
-                    }
+        // Dropping the scheduler's senders on shutdown closes the pipeline stage by stage.
+        schedule(&pipeline, poll_tx, generate_tx, metrics_tx).await;
+        shutdown_tx.send_replace(Shutdown::Draining);
+        info!("[TWITTER] Shutting down, finishing in-flight work...");
 
-                    if self.use_stats {
-                        match self
-                            .mongo_client
-                            .stats_inc_tweet_count(self.character.version)
-                            .await
-                        {
-                            Ok(_) => {
-                                info!("[STATS_DB] Incremented tweet count");
-                            }
-                            Err(e) => error!("[STATS_DB] Failed to increment tweet count: {}", e),
-                        }
+        // Generation and polling are only ever cancelled between requests that publish
+        // nothing, so a tweet is never posted without its stats and memory being handled.
+        let mut aborts = vec![
+            poller.abort_handle(),
+            generator.abort_handle(),
+            metrics.abort_handle(),
+        ];
+        let mut workers = vec![poller, generator, publisher, memory, metrics];
+        if let Some((server, worker)) = admin {
+            aborts.extend([server.abort_handle(), worker.abort_handle()]);
+            workers.extend([server, worker]);
+        }
+        if let Some(server) = metrics_server {
+            aborts.push(server.abort_handle());
+            workers.push(server);
+        }
+        let drain = async {
+            for worker in workers {
+                if let Err(e) = worker.await {
+                    if !e.is_cancelled() {
+                        error!("[TWITTER] Pipeline worker failed: {}", e);
                     }
+                }
+            }
+        };
+        tokio::pin!(drain);
 
-                    if self.character.should_branch() {
-                        info!("[TWITTER] Executing lore branching.");
-                        match self.gen_lore_branch().await {
-                            Ok(()) => (),
-                            Err(e) => {
-                                error!("[TWITTER] Unexpected error executing lore branch: {e}. Resetting...")
-                            }
-                        }
-                    }
+        let cancelled = tokio::select! {
+            _ = &mut drain => false,
+            _ = sleep(SHUTDOWN_GRACE_PERIOD) => {
+                warn!("[TWITTER] In-flight work did not finish in time. Cancelling...");
+                true
+            }
+            _ = shutdown_signal() => {
+                warn!("[TWITTER] Second shutdown signal. Cancelling in-flight work...");
+                true
+            }
+        };
+        if cancelled {
+            shutdown_tx.send_replace(Shutdown::Cancelled);
+            for abort in aborts {
+                abort.abort();
+            }
+            drain.await;
+        }
+
+        if let Err(e) = pipeline.twitter_client.kill() {
+            error!("[TWITTER] Unexpected error killing client: {}", e);
+        }
+        for client in pipeline.social_clients.iter() {
+            if let Err(e) = client.kill() {
+                error!(
+                    "[{}] Unexpected error killing client: {}",
+                    client.platform().tag(),
+                    e
+                );
+            }
+        }
+        requeue_pending_replies(&pipeline);
+        if flush_state(&pipeline) {
+            info!("[TWITTER] Saved agent state");
+        }
+        log_summary(&pipeline, started_at);
+        info!("[TWITTER] Pipeline stopped");
+    }
+
+    // Generation worker: turns tasks into posts and replies for the publisher.
+    // Once shutdown starts, fetched mentions are still queued but nothing new is generated.
+    async fn generate(
+        mut self,
+        pipeline: Pipeline,
+        mut generate_rx: Receiver<GenerateTask>,
+        publish_tx: Sender<PublishTask>,
+    ) {
+        while let Some(task) = generate_rx.recv().await {
+            let draining = pipeline.shutdown() != Shutdown::Running;
+            let over_budget = self.scheduler.lock().unwrap().budget_exceeded();
+            match task {
+                GenerateTask::Mentions {
+                    mentions,
+                    social,
+                    budget,
+                } => {
+                    // Mentions are still queued while the daily budget is spent.
+                    let budget = if draining || over_budget { 0 } else { budget };
+                    // Twitter replies come first, the other platforms share what is left.
+                    let replied = self
+                        .handle_mentions(&pipeline, mentions, budget, &publish_tx)
+                        .await;
+                    self.handle_social_mentions(
+                        &pipeline,
+                        social,
+                        budget.saturating_sub(replied),
+                        &publish_tx,
+                    )
+                    .await
+                }
+                _ if draining => {
+                    pipeline.summary.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                _ if over_budget => {
+                    info!("[TWITTER] Daily model budget spent. Skipping generation...");
+                    let event =
+                        Event::skip(Some(self.character.version), None, "daily budget exceeded");
+                    self.events.record(event).await;
+                }
+                GenerateTask::Post => self.handle_post(&publish_tx).await,
+                GenerateTask::Branch => {
+                    info!("[TWITTER] Executing scheduled lore branching.");
+                    self.branch("scheduled").await
                 }
+            }
+            self.state.lock().unwrap().record(&self.character);
+            *pipeline.character.lock().unwrap() = CharacterStatus::of(&self.character);
+            logging::set_version(self.character.version);
+        }
+        info!("[TWITTER] Generation worker stopped");
+    }
+
+    // Generates a post for every platform due for one. Platforms without a section in the
+    // character file share one post, the others get their own in their persona. Lore,
+    // topics and the image are the same for all of them.
+    async fn handle_post(&mut self, publish_tx: &Sender<PublishTask>) {
+        let now = Utc::now();
+        let platforms = std::iter::once((None, TWITTER))
+            .chain(
+                self.social_clients
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, client)| (Some(idx), client.platform().name())),
+            )
+            .map(|(client, platform)| (client, self.character.persona(platform)))
+            .filter(|(_, persona)| self.post_due(persona, now))
+            .collect::<Vec<_>>();
+        let version = self.character.version;
+        if platforms.is_empty() {
+            info!("[TWITTER] No platform is due for a post. Skipping...");
+            let event = Event::skip(Some(version), None, "no platform due for a post");
+            self.events.record(event).await;
+            return;
+        }
+
+        self.refresh_timeline().await;
+        let inputs = self.character.choose_prompt_inputs(
+            &self.character.persona(TWITTER),
+            &mut thread_rng(),
+            &PromptOverrides::default(),
+        );
+        let image = match self.media.choose(&inputs.topics, &self.character).await {
+            Ok(image) => image,
+            Err(e) => {
+                error!(
+                    "[MEDIA] Unexpected error choosing image: {}. Posting without it...",
+                    e
+                );
+                None
+            }
+        };
+
+        let mut text = None;
+        let mut alt_text = None;
+        let mut variants = Vec::new();
+        // Post generated with the base persona, reused by platforms without a section.
+        let mut shared: Option<(String, Event)> = None;
+        for (client, persona) in platforms {
+            // Checked per platform, so one post can't take the budget far past its cap.
+            let reuses_shared = shared.is_some() && client.is_some() && !persona.overridden;
+            if !reuses_shared && self.budget_spent(&persona.platform).await {
+                continue;
+            }
+            let generated = match (&shared, client) {
+                (Some((shared, event)), Some(_)) if !persona.overridden => Ok((
+                    shared.clone(),
+                    // Latency and tokens are only counted for the platform that generated it.
+                    Event {
+                        platform: Some(persona.platform.clone()),
+                        latency_ms: None,
+                        input_tokens: None,
+                        output_tokens: None,
+                        ..event.clone()
+                    },
+                )),
                 _ => {
-                    // 20% chance (80-99) to reply to mentioned tweets.
-                    let mentions = match self.twitter_client.fetch_mentions(5).await {
-                        Ok(mentions) => mentions,
-                        Err(e) => {
-                            error!(
-                                "[TWITTER] Unexpected error fetching previous tweet: {}. Skipping...",
-                                e
-                            );
-                            continue;
-                        }
+                    // Images are only attached on Twitter.
+                    let image = image.as_ref().filter(|_| client.is_none());
+                    let inputs = PromptInputs {
+                        lore: inputs.lore.clone(),
+                        lore_indices: inputs.lore_indices.clone(),
+                        topics: inputs.topics.clone(),
+                        ..self.character.choose_prompt_inputs(
+                            &persona,
+                            &mut thread_rng(),
+                            &PromptOverrides::default(),
+                        )
                     };
+                    let prompt = self.gen_post_prompt(&persona, &inputs, image);
+                    self.handle_generate(Action::Post, &prompt, vec![])
+                        .await
+                        .map(|generation| {
+                            let event = Event::generated(
+                                Action::Post,
+                                version,
+                                &persona.platform,
+                                Some(EventInputs::from(&inputs)),
+                                &generation,
+                            );
+                            (generation.text, event)
+                        })
+                }
+            };
 
-                    if self.use_stats {
-                        match self
-                            .mongo_client
-                            .stats_add_msgs_read(self.character.version, mentions.len() as u32)
-                            .await
-                        {
-                            Ok(_) => {
-                                info!("[STATS_DB] Added read count {}", mentions.len());
-                            }
-                            Err(e) => error!(
-                                "[STATS_DB] Failed to add read count {}: {}",
-                                mentions.len(),
-                                e
-                            ),
-                        }
+            let (generated, event) = match generated {
+                Ok(generated) => generated,
+                Err(e) => {
+                    error!(
+                        "[{}] Unexpected error generating post: {}. Skipping...",
+                        persona.platform.to_uppercase(),
+                        e
+                    );
+                    let event = Event::error(
+                        Some(version),
+                        Some(&persona.platform),
+                        format!("post generation failed: {}", e),
+                    );
+                    self.events.record(event).await;
+                    continue;
+                }
+            };
+            info!("[{}] Generated post", persona.platform.to_uppercase());
+            self.last_posted.insert(persona.platform.clone(), now);
+
+            match client {
+                None => {
+                    // Only a prompt with an image asks for alt text.
+                    let (generated, alt) = match &image {
+                        Some(_) => split_alt_text(&generated),
+                        None => (generated.trim().to_string(), None),
+                    };
+                    let event = Event {
+                        text: Some(generated.clone()),
+                        ..event
+                    };
+                    // A post written around the image doesn't stand on its own on the
+                    // platforms that get it without the image, so they generate their own.
+                    if !persona.overridden && image.is_none() {
+                        shared = Some((generated.clone(), event.clone()));
+                    }
+                    alt_text = alt;
+                    text = Some((generated, event));
+                }
+                Some(idx) => {
+                    if !persona.overridden && shared.is_none() {
+                        shared = Some((generated.clone(), event.clone()));
                     }
+                    variants.push((idx, generated, event));
+                }
+            }
+        }
 
-                    let mentions_str = mentions
-                        .iter()
-                        .enumerate()
-                        .map(|(i, mention)| format!("{} - {}", mention.id, mention.text))
-                        .collect::<Vec<String>>()
-                        .join("\n");
+        let Some(previous_post) = text
+            .as_ref()
+            .map(|(text, _)| text.clone())
+            .or_else(|| variants.first().map(|(_, variant, _)| variant.clone()))
+        else {
+            return;
+        };
+        self.character.add_previous_post(&previous_post);
 
-                    if mentions_str.is_empty() {
-                        info!("No valid mentions to respond to. Skipping...");
-                        continue;
-                    }
+        let task = PublishTask::Post {
+            attachment: image.filter(|_| text.is_some()).map(|image| Attachment {
+                // Fall back to the image description if the model left out the alt text.
+                alt_text: alt_text.unwrap_or_else(|| image.description.clone()),
+                image,
+            }),
+            text,
+            variants,
+            version,
+        };
+        if publish_tx.send(task).await.is_err() {
+            error!("[TWITTER] Publish queue closed. Dropping tweet...");
+        }
 
-                    let reply_idx = match self.choose_reply_idx(mentions_str).await {
-                        Ok(idx) => idx,
-                        Err(e) => {
-                            error!("Unexpected error determining reply idx: {}. Skipping...", e);
-                            continue;
-                        }
-                    };
+        if self.character.should_branch() {
+            info!("[TWITTER] Executing lore branching.");
+            self.branch("posts").await
+        }
+    }
 
-                    for mention in mentions {
-                        if mention.id.as_u64() == (reply_idx as u64) {
-                            info!("[TWITTER] Replying to tweet: {}", mention.text);
-
-                            let message = Message {
-                                id: format!("tweet_{}", mention.id.as_u64()),
-                                content: mention.text.clone(),
-                            };
-
-                            match self.build_embedding(message.clone()).await {
-                                Ok(embedding) => {
-                                    info!("[VEC_DB] Built embedding for tweet: {:?}", embedding);
-                                    if let Err(e) = self
-                                        .mongo_client
-                                        .vec_store_message(embedding, message)
-                                        .await
-                                    {
-                                        error!(
-                                            "[VEC_DB] Unexpected error storing tweet to memory: {}. Continuing...",
-                                            e
-                                        );
-                                    } else {
-                                        info!("[VEC_DB] Stored tweet to memory");
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "[VEC_DB] Unexpected error building embedding for tweet: {}. Continuing...",
-                                        e
-                                    );
-                                }
-                            }
+    // Branches the lore. `reason` is what triggered it, for the event log.
+    async fn branch(&mut self, reason: &str) {
+        let result = self.gen_lore_branch(reason).await;
+        telemetry::branched(reason, result.is_ok());
+        if let Err(e) = result {
+            error!("[TWITTER] Unexpected error executing lore branch: {e}. Resetting...");
+            let event = Event::error(
+                Some(self.character.version),
+                None,
+                format!("{} lore branch failed: {}", reason, e),
+            );
+            self.events.record(event).await;
+        }
+    }
 
-                            let prompt = self.gen_twitter_reply_prompt(mention.text, &mut rng);
-
-                            match self.handle_generate(&prompt, vec![]).await {
-                                Ok(reply) => {
-                                    info!("[TWITTER] Generated reply: {}", reply);
-                                    if let Err(e) =
-                                        self.twitter_client.reply(mention.id, reply.as_str()).await
-                                    {
-                                        error!("[TWITTER] Unexpected error occured replying to thread: {}. Skipping...", e);
-                                    } else {
-                                        info!("[TWITTER] Agent responded successfully");
-
-                                        if self.use_stats {
-                                            match self
-                                            .mongo_client
-                                            .stats_inc_reply_count(self.character.version)
-                                            .await
-                                            {
-                                                Ok(_) => {
-                                                    info!("[STATS_DB] Incremented reply count");
-                                                }
-                                                Err(e) => error!("[STATS_DB] Failed to increment reply count: {}", e),
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("[TWITTER] Unexpected error occurred whilst generating reply to mention: {}. Skipping...", e);
-                                }
-                            }
-                        }
-                    }
+    // Whether the persona's post interval, if any, has passed since its last post.
+    fn post_due(&self, persona: &Persona, now: DateTime<Utc>) -> bool {
+        match (
+            persona.post_interval_minutes,
+            self.last_posted.get(&persona.platform),
+        ) {
+            (Some(minutes), Some(last_posted)) => {
+                now - *last_posted >= ChronoDuration::minutes(minutes as i64)
+            }
+            _ => true,
+        }
+    }
+
+    // Queues new mentions, then generates replies to the best ranked ones until the reply
+    // budget is spent. Mentions that aren't picked stay queued for later cycles.
+    // Returns how many replies were generated.
+    async fn handle_mentions(
+        &mut self,
+        pipeline: &Pipeline,
+        mentions: Vec<AuthoredTweet>,
+        budget: usize,
+        publish_tx: &Sender<PublishTask>,
+    ) -> usize {
+        let fetched = mentions.len();
+        pipeline
+            .summary
+            .mentions_read
+            .fetch_add(fetched, Ordering::Relaxed);
+        {
+            let mut mention_queue = self.mention_queue.lock().unwrap();
+            let accepted = mention_queue.enqueue(mentions, &self.character);
+            info!(
+                "[TWITTER] Queued {} of {} new mentions ({} waiting)",
+                accepted,
+                fetched,
+                mention_queue.len()
+            );
+        }
+
+        if self.use_stats && fetched > 0 {
+            match self
+                .store
+                .stats_add_msgs_read(self.character.version, fetched as u32)
+                .await
+            {
+                Ok(_) => {
+                    info!("[STATS_DB] Added read count {}", fetched);
+                }
+                Err(e) => error!("[STATS_DB] Failed to add read count {}: {}", fetched, e),
+            }
+        }
+
+        let mut replied = 0;
+        for _ in 0..budget {
+            if pipeline.shutdown() != Shutdown::Running || self.budget_spent(TWITTER).await {
+                break;
+            }
+
+            let candidates = self.mention_queue.lock().unwrap().top(REPLY_CANDIDATES);
+            if candidates.is_empty() {
+                info!("[TWITTER] No valid mentions to respond to. Skipping...");
+                break;
+            }
+
+            let reply_id = match self.choose_reply_idx(&candidates).await {
+                Ok(Some(id)) => id,
+                Ok(None) => {
+                    info!("[TWITTER] No queued mention selected for a reply. Skipping...");
+                    // The decline is final, otherwise the same mentions are offered every cycle.
+                    self.mention_queue.lock().unwrap().decline(
+                        &candidates
+                            .iter()
+                            .map(|mention| mention.id)
+                            .collect::<Vec<_>>(),
+                    );
+                    let event = Event::skip(
+                        Some(self.character.version),
+                        Some(TWITTER),
+                        "no queued mention selected for a reply",
+                    );
+                    self.events.record(event).await;
+                    break;
+                }
+                Err(e) => {
+                    error!("Unexpected error determining reply idx: {}. Skipping...", e);
+                    let event = Event::error(
+                        Some(self.character.version),
+                        Some(TWITTER),
+                        format!("reply selection failed: {}", e),
+                    );
+                    self.events.record(event).await;
+                    break;
+                }
+            };
+
+            let Some(mention) = candidates
+                .into_iter()
+                .find(|mention| mention.id == reply_id)
+            else {
+                break;
+            };
+
+            // Generation errors are usually not limited to one mention, so the rest of the
+            // cycle is skipped rather than picking again.
+            let Some((reply, event)) = self.gen_reply(&mention).await else {
+                if self
+                    .mention_queue
+                    .lock()
+                    .unwrap()
+                    .record_failure(mention.id)
+                {
+                    warn!(
+                        "[TWITTER] Reply to mention {} failed too often. Dropping it...",
+                        mention.id
+                    );
                 }
+                break;
+            };
+
+            // Taken off the queue while in flight; requeued if the reply is not posted.
+            self.mention_queue.lock().unwrap().remove(mention.id);
+            replied += 1;
+
+            let task = PublishTask::Reply {
+                mention,
+                text: reply,
+                version: self.character.version,
+                event,
+            };
+            if let Err(e) = publish_tx.send(task).await {
+                if let PublishTask::Reply { mention, .. } = e.0 {
+                    self.mention_queue.lock().unwrap().requeue(mention);
+                }
+                error!("[TWITTER] Publish queue closed. Keeping mention queued...");
+                break;
             }
         }
+
+        if let Err(e) = self.mention_queue.lock().unwrap().save() {
+            error!("[TWITTER] Unexpected error saving mention queue: {}", e);
+        }
+        replied
     }
 
-    fn gen_twitter_post_prompt(&self, rng: &mut ThreadRng) -> String {
+    // Queues new mentions from the other platforms, then replies to the oldest queued ones
+    // until the reply budget is spent. Mentions that aren't answered stay queued for later
+    // cycles.
+    async fn handle_social_mentions(
+        &self,
+        pipeline: &Pipeline,
+        mentions: Vec<(usize, SocialPost)>,
+        budget: usize,
+        publish_tx: &Sender<PublishTask>,
+    ) {
+        pipeline
+            .summary
+            .mentions_read
+            .fetch_add(mentions.len(), Ordering::Relaxed);
+
+        let mut fetched = HashMap::<usize, Vec<SocialPost>>::new();
+        for (client, mention) in mentions {
+            if is_blocked(&mention.text) {
+                let platform = self.social_clients[client].platform().name();
+                let event = Event {
+                    in_reply_to_id: Some(mention.id.clone()),
+                    ..Event::skip(
+                        Some(self.character.version),
+                        Some(platform),
+                        "blocked content",
+                    )
+                };
+                self.events.record(event).await;
+                continue;
+            }
+            fetched.entry(client).or_default().push(mention);
+        }
+        for (client, posts) in fetched {
+            let platform = self.social_clients[client].platform();
+            let mut social_queue = self.social_queue.lock().unwrap();
+            let count = posts.len();
+            let accepted = social_queue.enqueue(platform.name(), posts);
+            info!(
+                "[{}] Queued {} of {} new mentions ({} waiting)",
+                platform.tag(),
+                accepted,
+                count,
+                social_queue.len()
+            );
+        }
+
+        let platforms = self
+            .social_clients
+            .iter()
+            .map(|client| client.platform().name())
+            .collect::<Vec<_>>();
+        let candidates = self.social_queue.lock().unwrap().oldest(&platforms, budget);
+        for mention in candidates {
+            if pipeline.shutdown() != Shutdown::Running
+                || self.budget_spent(&mention.platform).await
+            {
+                break;
+            }
+
+            let Some(client) = platforms
+                .iter()
+                .position(|platform| *platform == mention.platform)
+            else {
+                continue;
+            };
+            let Some((reply, event)) = self
+                .gen_social_reply(&self.social_clients[client], &mention.post)
+                .await
+            else {
+                continue;
+            };
+
+            // Taken off the queue while in flight; requeued if the reply is not posted.
+            self.social_queue
+                .lock()
+                .unwrap()
+                .remove(&mention.platform, &mention.post.id);
+
+            let task = PublishTask::SocialReply {
+                client,
+                mention,
+                text: reply,
+                event,
+            };
+            if let Err(e) = publish_tx.send(task).await {
+                if let PublishTask::SocialReply {
+                    client, mention, ..
+                } = e.0
+                {
+                    self.social_queue.lock().unwrap().requeue(mention);
+                    error!(
+                        "[{}] Publish queue closed. Keeping mention queued...",
+                        self.social_clients[client].platform().tag()
+                    );
+                }
+                break;
+            }
+        }
+
+        if let Err(e) = self.social_queue.lock().unwrap().save() {
+            error!(
+                "[TWITTER] Unexpected error saving social mention queue: {}",
+                e
+            );
+        }
+    }
+
+    // Generates a reply to the mention with the thread as history.
+    async fn gen_reply(&self, mention: &QueuedMention) -> Option<(String, Event)> {
+        info!("[TWITTER] Replying to tweet: {}", mention.text);
+
+        let history = match mention.in_reply_to_id {
+            Some(in_reply_to_id) => match self
+                .twitter_client
+                .fetch_thread(
+                    NumericId::new(in_reply_to_id),
+                    mention.conversation_id.map(NumericId::new),
+                    MAX_THREAD_DEPTH,
+                )
+                .await
+            {
+                Ok(thread) => {
+                    let own_id = self.twitter_client.user_id();
+                    thread_history(
+                        "twitter",
+                        thread.iter().map(|entry| {
+                            (
+                                entry.tweet.author_id == Some(own_id),
+                                entry
+                                    .author
+                                    .as_ref()
+                                    .map_or("unknown", |author| author.username.as_str()),
+                                entry.tweet.text.as_str(),
+                            )
+                        }),
+                    )
+                }
+                Err(e) => {
+                    error!(
+                        "[TWITTER] Unexpected error fetching thread: {}. Replying without context...",
+                        e
+                    );
+                    vec![]
+                }
+            },
+            None => vec![],
+        };
+
+        let persona = self.character.persona(TWITTER);
+        let inputs = self.choose_reply_inputs(&persona);
+        let prompt = self.gen_reply_prompt(&persona, mention.text.clone(), &inputs);
+
+        match self.handle_generate(Action::Reply, &prompt, history).await {
+            Ok(generation) => {
+                info!("[TWITTER] Generated reply: {}", generation.text);
+                let event = Event {
+                    in_reply_to_id: Some(mention.id.to_string()),
+                    ..Event::generated(
+                        Action::Reply,
+                        self.character.version,
+                        TWITTER,
+                        Some(EventInputs::from(&inputs)),
+                        &generation,
+                    )
+                };
+                Some((generation.text, event))
+            }
+            Err(e) => {
+                error!("[TWITTER] Unexpected error occurred whilst generating reply to mention: {}. Skipping...", e);
+                let event = Event {
+                    in_reply_to_id: Some(mention.id.to_string()),
+                    ..Event::error(
+                        Some(self.character.version),
+                        Some(TWITTER),
+                        format!("reply generation failed: {}", e),
+                    )
+                };
+                self.events.record(event).await;
+                None
+            }
+        }
+    }
+
+    // Same as `gen_reply`, for a mention on another platform.
+    async fn gen_social_reply(
+        &self,
+        client: &SocialClient,
+        mention: &SocialPost,
+    ) -> Option<(String, Event)> {
+        let platform = client.platform();
+        info!("[{}] Replying to message: {}", platform.tag(), mention.text);
+
+        let history = match client.fetch_thread(mention, MAX_THREAD_DEPTH).await {
+            Ok(thread) => thread_history(
+                platform.name(),
+                thread
+                    .iter()
+                    .map(|post| (post.is_own, post.author_name.as_str(), post.text.as_str())),
+            ),
+            Err(e) => {
+                error!(
+                    "[{}] Unexpected error fetching thread: {}. Replying without context...",
+                    platform.tag(),
+                    e
+                );
+                vec![]
+            }
+        };
+
+        let persona = self.character.persona(platform.name());
+        let inputs = self.choose_reply_inputs(&persona);
+        let prompt = self.gen_reply_prompt(&persona, mention.text.clone(), &inputs);
+
+        match self.handle_generate(Action::Reply, &prompt, history).await {
+            Ok(generation) => {
+                info!("[{}] Generated reply: {}", platform.tag(), generation.text);
+                let event = Event {
+                    in_reply_to_id: Some(mention.id.clone()),
+                    ..Event::generated(
+                        Action::Reply,
+                        self.character.version,
+                        platform.name(),
+                        Some(EventInputs::from(&inputs)),
+                        &generation,
+                    )
+                };
+                Some((generation.text, event))
+            }
+            Err(e) => {
+                error!(
+                    "[{}] Unexpected error generating reply to mention: {}. Skipping...",
+                    platform.tag(),
+                    e
+                );
+                let event = Event {
+                    in_reply_to_id: Some(mention.id.clone()),
+                    ..Event::error(
+                        Some(self.character.version),
+                        Some(platform.name()),
+                        format!("reply generation failed: {}", e),
+                    )
+                };
+                self.events.record(event).await;
+                None
+            }
+        }
+    }
+
+    // Lore, adjective and style for a reply. Replies are about the mention, so no topics.
+    fn choose_reply_inputs(&self, persona: &Persona) -> PromptInputs {
+        PromptInputs {
+            topics: vec![],
+            ..self.character.choose_prompt_inputs(
+                persona,
+                &mut thread_rng(),
+                &PromptOverrides::default(),
+            )
+        }
+    }
+
+    fn gen_post_prompt(
+        &self,
+        persona: &Persona,
+        inputs: &PromptInputs,
+        image: Option<&MediaImage>,
+    ) -> String {
+        let (image_instructions, image) = match image {
+            Some(image) => (
+                "\n            An image described in <image> is attached to the post, make the post work together with it. After the post, on a new line, write ALT: followed by a single sentence describing the image for people who can't see it.",
+                format!("\n\n            <image>\n            {}\n            </image>", image.description),
+            ),
+            None => ("", String::new()),
+        };
+
         let prompt = format!(
             r"
             <instructions>
             Generate a post in the voice and style of {alias}, aka @{twitter_user_name}. Your response is a unique quote to share with the world. You MUST follow ALL the <rules>.
 
             First go through all of the entries in <previousMessages> and find the most used words and save them to an array stored in <bannedWords>.
-            You are given this twitter timeline as reference to create a relatable message.
-            If you find that the timeline is boring or not helpful, use <lore> as reference to tell a tale of the past.
+            You are given this twitter <timeline> as reference to create a relatable message that reacts to what is happening.
+            If you find that the <timeline> is empty, boring or not helpful, use <lore> as reference to tell a tale of the past.
 
-            Write a single sentence post that is {adjectives} about {topic} (without mentioning {topic} directly), from the perspective of {alias} with {style} style. Try to write something totally different than previous posts. Do not add commentary or acknowledge this request, just write the post.
+            Write a single sentence post that is {adjectives} about {topic} (without mentioning {topic} directly), from the perspective of {alias} with {style} style. Try to write something totally different than previous posts. Do not add commentary or acknowledge this request, just write the post.{image_instructions}
             </instructions>
 
+            <timeline>
+            {timeline}
+            </timeline>{image}
+
             <lore>
             {lore}
             </lore>
@@ -269,41 +1190,20 @@ impl Instance {
             <rules>
             - NEVER use any of the words in <bannedWords> in your response.
             - Given your <instructions>, your response should not contain any questions. 
-            - Less than 280 characters. 
-            - No emojis. 
+            - Less than {max_length} characters. 
+            - {emoji_rule} 
             - Use \\n\\n (double spaces) between statements.
             - Make content have a different purpose than all the entries in <previousMessages>. You are allowed to make things up.
             </rules>",
             alias = self.character.alias,
-            twitter_user_name = self.character.twitter_user_name,
-            lore = self
-                .character
-                .lore
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            topic = self
-                .character
-                .topics
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            adjectives = self
-                .character
-                .adjectives
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join(","),
-            style = self
-                .character
-                .styles
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
+            twitter_user_name = persona.user_name,
+            max_length = persona.max_length,
+            emoji_rule = persona.emoji_rule(),
+            timeline = self.timeline.join("\n"),
+            lore = inputs.lore.join("\n"),
+            topic = inputs.topics.join("\n"),
+            adjectives = inputs.adjective,
+            style = inputs.style,
             previous_messages = self
                 .character
                 .previous_posts
@@ -317,7 +1217,7 @@ impl Instance {
         return prompt;
     }
 
-    fn gen_twitter_reply_prompt(&self, tweet: String, rng: &mut ThreadRng) -> String {
+    fn gen_reply_prompt(&self, persona: &Persona, tweet: String, inputs: &PromptInputs) -> String {
         let prompt = format!(
             r"<instructions>
             Generate a reply in the voice and style of {alias}, aka @{twitter_user_name}. Your reply to <tweet> must follow ALL the <rules>.
@@ -325,9 +1225,10 @@ impl Instance {
             Follow this methodology in numerical order to generate your response:
             <methodology>
             1) Go through all of the entries in <previousMessages> and find the most used words and save them to an array stored in <bannedWords>.
-            2) Check if the user has asked a question in <tweet>. If it is a yes or no question, answer it directly. If it is an open-ended question, answer it with a statement.
-            3) You MUST conduct research on <tweet> via current events on the internet.
-            4) Make it sound like you are talking directly to the user. You MUST directly answer the question in <tweet>.
+            2) Read the conversation so far, if any, so your reply continues the thread instead of repeating it. <tweet> is the latest message in it.
+            3) Check if the user has asked a question in <tweet>. If it is a yes or no question, answer it directly. If it is an open-ended question, answer it with a statement.
+            4) You MUST conduct research on <tweet> via current events on the internet.
+            5) Make it sound like you are talking directly to the user. You MUST directly answer the question in <tweet>.
             </methodology>
 
             Write a single sentence response that is {adjectives} about <tweet>, from the perspective of {alias} with {style} style.
@@ -349,35 +1250,19 @@ impl Instance {
             <rules>
             - NEVER use any of the words in <bannedWords> in your response.
             - Directly answer the question, dont make it a quote.
-            - Less than 280 characters. 
-            - No emojis. 
+            - Less than {max_length} characters. 
+            - {emoji_rule} 
             - Use \\n\\n (double spaces) between statements.
             - Make content have a different purpose than all the entries in <previousMessages>. You are allowed to make things up.
             </rules>",
             alias = self.character.alias,
-            twitter_user_name = self.character.twitter_user_name,
+            twitter_user_name = persona.user_name,
+            max_length = persona.max_length,
+            emoji_rule = persona.emoji_rule(),
             tweet = tweet,
-            lore = self
-                .character
-                .lore
-                .choose_multiple(rng, 3)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            adjectives = self
-                .character
-                .adjectives
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
-            style = self
-                .character
-                .styles
-                .choose_multiple(rng, 1)
-                .cloned()
-                .collect::<Vec<String>>()
-                .join("\n"),
+            lore = inputs.lore.join("\n"),
+            adjectives = inputs.adjective,
+            style = inputs.style,
             previous_messages = self
                 .character
                 .previous_posts
@@ -390,16 +1275,77 @@ impl Instance {
         return prompt;
     }
 
+    // Refreshes the cached timeline digest used by the post prompt.
+    // The cache is kept when nothing new was posted or the fetch fails.
+    async fn refresh_timeline(&mut self) {
+        match self.twitter_client.fetch_timeline(TIMELINE_SIZE).await {
+            Ok(timeline) if !timeline.is_empty() => {
+                self.timeline = summarize_timeline(&timeline);
+                info!(
+                    "[TWITTER] Refreshed timeline with {} entries",
+                    self.timeline.len()
+                );
+            }
+            Ok(_) => info!("[TWITTER] No new timeline entries, using cached timeline"),
+            Err(e) => error!(
+                "[TWITTER] Unexpected error fetching timeline: {}. Using cached timeline...",
+                e
+            ),
+        }
+    }
+
+    // Generates text for `action` and accounts its tokens and cost to the current version.
     async fn handle_generate(
         &self,
+        action: Action,
         prompt: &str,
         history: Vec<CompletionMessage>,
-    ) -> Result<String> {
-        self.agent.chat(prompt, history).await.map_err(Error::new)
+    ) -> Result<Generation> {
+        let generation = self.llm.chat(prompt, history).await;
+        self.record_spent(action, billed(generation.as_ref())).await;
+        generation
     }
 
-    async fn gen_lore_branch(&mut self) -> Result<()> {
-        let response = self.handle_generate(
+    // Accounts a call's tokens and cost, failed attempts included, to the current version.
+    async fn record_spent(&self, action: Action, (tokens, cost_usd): (TokenUsage, f64)) {
+        let usage = Usage {
+            calls: 1,
+            input_tokens: tokens.input_tokens,
+            output_tokens: tokens.output_tokens,
+            cost_usd,
+        };
+        record_usage(
+            &self.store,
+            &self.scheduler,
+            self.use_stats,
+            action.as_str(),
+            self.character.version,
+            usage,
+        )
+        .await;
+    }
+
+    // Whether today's model budget is spent, recording a skip for `platform` if it is.
+    async fn budget_spent(&self, platform: &str) -> bool {
+        if !self.scheduler.lock().unwrap().budget_exceeded() {
+            return false;
+        }
+        info!(
+            "[{}] Daily model budget spent. Skipping generation...",
+            platform.to_uppercase()
+        );
+        let event = Event::skip(
+            Some(self.character.version),
+            Some(platform),
+            "daily budget exceeded",
+        );
+        self.events.record(event).await;
+        true
+    }
+
+    async fn gen_lore_branch(&mut self, reason: &str) -> Result<()> {
+        let generation = self.handle_generate(
+            Action::Branch,
             &format!(
                 r#"
                 <instructions>
@@ -462,89 +1408,1136 @@ impl Instance {
         ).await?;
 
         //Save to file and mutate struct
-        self.character = self.character.save(&response)?;
+        self.character = self.character.save(&generation.text)?;
+        // The character file is in the stats version document, so it isn't repeated here.
+        let event = Event {
+            platform: None,
+            text: None,
+            reason: Some(reason.to_string()),
+            ..Event::generated(
+                Action::Branch,
+                self.character.version,
+                TWITTER,
+                None,
+                &generation,
+            )
+        };
+        self.events.record(event).await;
         if self.use_stats {
-            self.version_doc_check().await?;
+            self.upsert_version_doc().await?;
         }
         Ok(())
     }
 
-    async fn choose_reply_idx(&self, mentions_str: String) -> Result<usize> {
-        let response = self.handle_generate(
-            &format!(
-                r#"
+    // Asks the model to pick one of the candidate mentions via a structured `submit` tool call.
+    // Returns `None` when the model declines to reply to any of them. Its tokens count as
+    // reply usage.
+    async fn choose_reply_idx(&self, candidates: &[QueuedMention]) -> Result<Option<u64>> {
+        let mentions_str = candidates
+            .iter()
+            .map(|mention| format!("{} - {}", mention.id, mention.text))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let prompt = format!(
+            r#"
                 <instructions>
-                Given the following <tweets> mentioning you username {twitter_user_name}, select a of the tweet that you would like to respond to and store the selected index in <selectedID>.
+                Given the following <tweets> mentioning your username {twitter_user_name}, select the tweet that you would like to respond to.
+                Submit its id exactly as written as `selectedId` along with a short `reason`.
+                If none of the tweets deserve a reply, submit `selectedId` as null.
                 </instructions>
 
-                These tweets are in the format of <idx> - <tweet>.
+                These tweets are in the format of <id> - <tweet>.
                 <tweets>
                 {mentions_str}
                 </tweets>
-
-                Your <output> will just be <selectedID> with NO other characters or spaces.:
-                <selectedID>
                 "#,
-                twitter_user_name = self.character.twitter_user_name,
-                mentions_str = mentions_str
-            ),
-            vec![]
-        ).await?;
+            twitter_user_name = self.character.persona(TWITTER).user_name,
+            mentions_str = mentions_str
+        );
+
+        let extracted = self.reply_selector.extract(&prompt).await;
+        let spent = billed(extracted.as_ref().map(|(_, generation)| generation));
+        self.record_spent(Action::Reply, spent).await;
+        let (selection, _) = extracted?;
+
+        info!(
+            "[TWITTER] Reply selection: {:?} ({})",
+            selection.selected_id, selection.reason
+        );
+        selection.validate(candidates)
+    }
+
+    // Creates the stats document of the current version if it doesn't exist yet.
+    pub async fn upsert_version_doc(&self) -> Result<()> {
+        self.store
+            .stats_upsert_version_doc(
+                self.character.version,
+                Utc::now().timestamp() as u32,
+                serde_json::to_string(&self.character)?,
+            )
+            .await?;
+        info!("[STATS_DB] Version document ready");
+        Ok(())
+    }
+
+    async fn build_embedding_many(
+        &self,
+        messages: Vec<Message>,
+    ) -> Result<Vec<(Message, OneOrMany<Embedding>)>> {
+        let documents = messages
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+        let (embeddings, _) = self.embedder.embed(documents).await?;
+        Ok(messages
+            .into_iter()
+            .zip(embeddings.into_iter().map(OneOrMany::one))
+            .collect())
+    }
+}
+
+// Splits the `ALT:` line the post prompt asks for off a generated post.
+fn split_alt_text(generated: &str) -> (String, Option<String>) {
+    match generated.rsplit_once("ALT:") {
+        Some((post, alt_text)) if !alt_text.trim().is_empty() => {
+            (post.trim().to_string(), Some(alt_text.trim().to_string()))
+        }
+        Some((post, _)) => (post.trim().to_string(), None),
+        None => (generated.trim().to_string(), None),
+    }
+}
 
-        let reply_index = response
-            .trim()
-            .parse::<usize>()
-            .expect("Failed to parse reply index");
-        Ok(reply_index)
+// Converts a thread (oldest first) of `(is_own, author, text)` entries into chat history:
+// the agent's own posts become assistant turns and everyone else's become user turns
+// prefixed with their handle. An empty thread gives no history.
+fn thread_history<'a>(
+    platform: &str,
+    thread: impl IntoIterator<Item = (bool, &'a str, &'a str)>,
+) -> Vec<CompletionMessage> {
+    let mut thread = thread.into_iter().peekable();
+    if thread.peek().is_none() {
+        return vec![];
     }
 
-    pub async fn version_doc_check(&self) -> Result<()> {
-        info!("[STATS_DB] Versions document check...");
-        match self
-            .mongo_client
-            .stats_version_doc_exists(self.character.version)
+    let mut history = vec![CompletionMessage {
+        role: "user".to_string(),
+        content: format!(
+            "The following messages are the {} thread leading up to the message you are replying to.",
+            platform
+        ),
+    }];
+
+    for (is_own, author, text) in thread {
+        let (role, content) = if is_own {
+            ("assistant", text.to_string())
+        } else {
+            ("user", format!("@{}: {}", author, text))
+        };
+
+        // Consecutive turns from the same side are merged so roles keep alternating.
+        match history.last_mut() {
+            Some(last) if last.role == role => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&content);
+            }
+            _ => history.push(CompletionMessage {
+                role: role.to_string(),
+                content,
+            }),
+        }
+    }
+
+    history
+}
+
+// Condenses timeline tweets into one `@author: text` line each, dropping links
+// and collapsing whitespace so the prompt stays short.
+fn summarize_timeline(timeline: &[AuthoredTweet]) -> Vec<String> {
+    timeline
+        .iter()
+        .filter_map(|entry| {
+            let text = entry
+                .tweet
+                .text
+                .split_whitespace()
+                .filter(|word| !word.starts_with("http://") && !word.starts_with("https://"))
+                .collect::<Vec<&str>>()
+                .join(" ");
+            if text.is_empty() {
+                return None;
+            }
+
+            let text = match text.char_indices().nth(TIMELINE_ENTRY_MAX_CHARS) {
+                Some((idx, _)) => format!("{}...", &text[..idx]),
+                None => text,
+            };
+            let author = entry
+                .author
+                .as_ref()
+                .map_or("unknown", |author| author.username.as_str());
+
+            Some(format!("@{author}: {text}"))
+        })
+        .take(TIMELINE_SIZE)
+        .collect()
+}
+
+// Persists the mention cursor, previous posts, branching counter and mention queues.
+// Returns whether the agent state was saved.
+fn flush_state(pipeline: &Pipeline) -> bool {
+    let daily_usage = pipeline.scheduler.lock().unwrap().daily_usage();
+    let mut state = pipeline.state.lock().unwrap();
+    state.daily_usage = daily_usage;
+    state.latest_mention_id = Some(pipeline.twitter_client.latest_mention_id().as_u64());
+    state.twitter_usage = pipeline.twitter_client.monthly_usage();
+    for client in pipeline.social_clients.iter() {
+        if let Some(cursor) = client.cursor() {
+            state
+                .social_cursors
+                .insert(client.platform().name().to_string(), cursor);
+        }
+    }
+    let saved = match state.save() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("[TWITTER] Unexpected error saving agent state: {}", e);
+            false
+        }
+    };
+
+    if let Err(e) = pipeline.mention_queue.lock().unwrap().save() {
+        error!("[TWITTER] Unexpected error saving mention queue: {}", e);
+    }
+    if let Err(e) = pipeline.social_queue.lock().unwrap().save() {
+        error!(
+            "[TWITTER] Unexpected error saving social mention queue: {}",
+            e
+        );
+    }
+    saved
+}
+
+fn log_summary(pipeline: &Pipeline, started_at: DateTime<Utc>) {
+    let summary = &pipeline.summary;
+    info!(
+        "[TWITTER] Shutdown summary: ran {} min, {} posts, {} replies, {} mentions read ({} still queued), {} memories stored, {} tasks dropped",
+        (Utc::now() - started_at).num_minutes(),
+        summary.posts.load(Ordering::Relaxed),
+        summary.replies.load(Ordering::Relaxed),
+        summary.mentions_read.load(Ordering::Relaxed),
+        pipeline.mention_queue.lock().unwrap().len()
+            + pipeline.social_queue.lock().unwrap().len(),
+        summary.memories.load(Ordering::Relaxed),
+        summary.dropped.load(Ordering::Relaxed),
+    );
+}
+
+// Waits for due tasks and queues them for the workers until SIGINT/SIGTERM.
+// The agent state is saved on every tick, so a crash loses at most one interval of it.
+async fn schedule(
+    pipeline: &Pipeline,
+    poll_tx: Sender<usize>,
+    generate_tx: Sender<GenerateTask>,
+    metrics_tx: Sender<()>,
+) {
+    let scheduler = &pipeline.scheduler;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (task, due) = scheduler.lock().unwrap().next_due();
+        let delay = (due - Utc::now()).to_std().unwrap_or_default();
+
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = sleep(delay) => {}
+        }
+
+        scheduler.lock().unwrap().advance(task);
+        flush_state(pipeline);
+
+        let endpoint = match task {
+            Task::Post => Some(Endpoint::PostTweet),
+            Task::PollMentions => Some(Endpoint::Mentions),
+            Task::Branch => None,
+            Task::CollectMetrics => Some(Endpoint::TweetLookup),
+        };
+        if let Some(until) =
+            endpoint.and_then(|endpoint| pipeline.twitter_client.blocked_until(endpoint))
+        {
+            // The other platforms aren't held up by Twitter's quota, Twitter calls fail
+            // fast until the reset instead.
+            if pipeline.social_clients.is_empty() || task == Task::CollectMetrics {
+                warn!(
+                    "[TWITTER] Twitter quota exhausted, deferring {:?} until {}",
+                    task, until
+                );
+                scheduler.lock().unwrap().defer(task, until);
+                continue;
+            }
+            warn!(
+                "[TWITTER] Twitter quota exhausted until {}, running {:?} for the other platforms",
+                until, task
+            );
+        }
+        let queued = match task {
+            Task::Post => {
+                if scheduler.lock().unwrap().is_paused() {
+                    info!("[TWITTER] Posting paused. Skipping post...");
+                    pipeline
+                        .events
+                        .record(Event::skip(None, None, "posting paused"))
+                        .await;
+                    continue;
+                }
+                if !scheduler.lock().unwrap().can_post() {
+                    info!(
+                        "[TWITTER] Outside posting window or daily cap reached. Skipping post..."
+                    );
+                    let event =
+                        Event::skip(None, None, "outside posting window or daily cap reached");
+                    pipeline.events.record(event).await;
+                    continue;
+                }
+                generate_tx
+                    .try_send(GenerateTask::Post)
+                    .map_err(|e| matches!(e, TrySendError::Full(_)))
+            }
+            Task::PollMentions => {
+                let held = expire_pending_replies(pipeline).await;
+                let budget = scheduler
+                    .lock()
+                    .unwrap()
+                    .reply_budget(*REPLIES_PER_CYCLE, held);
+                poll_tx
+                    .try_send(budget)
+                    .map_err(|e| matches!(e, TrySendError::Full(_)))
+            }
+            Task::Branch => generate_tx
+                .try_send(GenerateTask::Branch)
+                .map_err(|e| matches!(e, TrySendError::Full(_))),
+            Task::CollectMetrics => metrics_tx
+                .try_send(())
+                .map_err(|e| matches!(e, TrySendError::Full(_))),
+        };
+
+        match queued {
+            Ok(()) => (),
+            Err(true) => {
+                info!("[TWITTER] Pipeline busy, skipping {:?}...", task);
+                let event = Event::skip(None, None, format!("pipeline busy, skipped {:?}", task));
+                pipeline.events.record(event).await;
+            }
+            Err(false) => {
+                error!("[TWITTER] Pipeline closed unexpectedly. Stopping scheduler...");
+                break;
+            }
+        }
+    }
+}
+
+// Resolves on Ctrl-C, or on SIGTERM where available.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("[TWITTER] Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("[TWITTER] Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("[TWITTER] Received SIGINT"),
+        _ = terminate => info!("[TWITTER] Received SIGTERM"),
+    }
+}
+
+// Mention polling worker: fetches new mentions and hands them to the generation worker
+// along with the reply budget of the cycle.
+async fn poll_mentions(
+    pipeline: Pipeline,
+    mut poll_rx: Receiver<usize>,
+    generate_tx: Sender<GenerateTask>,
+) {
+    while let Some(budget) = poll_rx.recv().await {
+        if pipeline.shutdown() != Shutdown::Running {
+            pipeline.summary.dropped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        let mentions = match pipeline
+            .twitter_client
+            .fetch_mentions(MENTIONS_FETCH_SIZE)
             .await
         {
-            Ok(_) => info!("[STATS_DB] Version document exists!"),
-            Err(_) => {
-                match self
-                    .mongo_client
-                    .stats_create_version_doc(
-                        self.character.version,
-                        Utc::now().timestamp() as u32,
-                        serde_json::to_string(&self.character)?,
-                    )
-                    .await
-                {
-                    Ok(_) => {
-                        info!("[STATS_DB] Version document created!");
+            Ok(mentions) => mentions,
+            Err(e) => {
+                error!(
+                    "[TWITTER] Unexpected error fetching mentions: {}. Using queued mentions...",
+                    e
+                );
+                vec![]
+            }
+        };
+
+        let mut social = Vec::new();
+        for (idx, client) in pipeline.social_clients.iter().enumerate() {
+            match client.fetch_mentions().await {
+                Ok(mentions) => social.extend(mentions.into_iter().map(|mention| (idx, mention))),
+                Err(e) => error!(
+                    "[{}] Unexpected error fetching mentions: {}. Skipping...",
+                    client.platform().tag(),
+                    e
+                ),
+            }
+        }
+
+        let task = GenerateTask::Mentions {
+            mentions,
+            social,
+            budget,
+        };
+        if generate_tx.send(task).await.is_err() {
+            break;
+        }
+    }
+    info!("[TWITTER] Mention worker stopped");
+}
+
+// Publishing worker: posts generated tweets and replies, then records stats and caps.
+// Posts also go out on the other platforms that are due for one, without images.
+// Replies that fail to post, or are still pending when shutdown is cancelled, go back
+// to their mention queue. With reply approval on, replies are held for an operator
+// and published once they come back approved over `approved_rx`. Answered mentions go
+// to the memory worker.
+async fn publish(
+    pipeline: Pipeline,
+    mut publish_rx: Receiver<PublishTask>,
+    mut approved_rx: Receiver<PublishTask>,
+    memory_tx: Sender<Message>,
+) {
+    loop {
+        let (task, approved) = tokio::select! {
+            task = publish_rx.recv() => match task {
+                Some(task) => (task, false),
+                None => break,
+            },
+            Some(task) = approved_rx.recv() => (task, true),
+        };
+        if pipeline.shutdown() == Shutdown::Cancelled {
+            pipeline.summary.dropped.fetch_add(1, Ordering::Relaxed);
+            match task {
+                PublishTask::Reply { mention, .. } => {
+                    pipeline.mention_queue.lock().unwrap().requeue(mention)
+                }
+                PublishTask::SocialReply { mention, .. } => {
+                    pipeline.social_queue.lock().unwrap().requeue(mention)
+                }
+                PublishTask::Post { .. } => (),
+            }
+            continue;
+        }
+
+        if pipeline.approve_replies
+            && !approved
+            && matches!(
+                task,
+                PublishTask::Reply { .. } | PublishTask::SocialReply { .. }
+            )
+        {
+            let id = pipeline.pending_replies.lock().unwrap().hold(task);
+            info!("[ADMIN] Reply {} is waiting for approval", id);
+            continue;
+        }
+
+        publish_task(&pipeline, task, &memory_tx).await;
+    }
+
+    // Approvals that came in after the last generated task are held again,
+    // to be requeued with the other pending replies.
+    approved_rx.close();
+    while let Ok(task) = approved_rx.try_recv() {
+        pipeline.pending_replies.lock().unwrap().hold(task);
+    }
+    info!("[TWITTER] Publish worker stopped");
+}
+
+// Publishes one task and records its stats, caps and event.
+async fn publish_task(pipeline: &Pipeline, task: PublishTask, memory_tx: &Sender<Message>) {
+    let Pipeline {
+        twitter_client,
+        social_clients,
+        store,
+        events,
+        scheduler,
+        mention_queue,
+        summary,
+        use_stats,
+        ..
+    } = pipeline;
+
+    match task {
+        PublishTask::Post {
+            text,
+            variants,
+            version,
+            attachment,
+        } => {
+            let mut published_anywhere = match text {
+                Some((text, event)) => {
+                    publish_tweet(pipeline, &text, attachment, version, event).await
+                }
+                None => false,
+            };
+
+            for (client, text, event) in variants {
+                let client = &social_clients[client];
+                match client.publish(&text).await {
+                    Ok(id) => {
+                        info!(
+                            "[{}] Successfully published post (ID: {})",
+                            client.platform().tag(),
+                            id
+                        );
+                        let event = Event {
+                            tweet_id: Some(id),
+                            ..event
+                        };
+                        events.record(event).await;
+                        telemetry::published(client.platform().name(), "post", true);
+                        pipeline
+                            .state
+                            .lock()
+                            .unwrap()
+                            .record_post(client.platform().name(), Utc::now().timestamp());
+                        published_anywhere = true;
                     }
                     Err(e) => {
-                        error!("[STATS_DB] Failed to create version document: {}", e);
+                        error!(
+                            "[{}] Unexpected error publishing post: {}. Skipping...",
+                            client.platform().tag(),
+                            e
+                        );
+                        telemetry::published(client.platform().name(), "post", false);
+                        let event = event.failed(format!("publish failed: {}", e));
+                        events.record(event).await;
                     }
                 }
             }
+
+            if published_anywhere {
+                scheduler.lock().unwrap().record_post();
+                summary.posts.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        PublishTask::Reply {
+            mention,
+            text,
+            version,
+            event,
+        } => {
+            let tweet_id = match twitter_client
+                .reply(NumericId::new(mention.id), text.as_str())
+                .await
+            {
+                Ok(tweet_id) => tweet_id,
+                Err(e) => {
+                    error!(
+                        "[TWITTER] Unexpected error occured replying to thread: {}. Requeueing...",
+                        e
+                    );
+                    telemetry::published(TWITTER, "reply", false);
+                    let event = event.failed(format!("reply failed: {}", e));
+                    events.record(event).await;
+                    let mut mention_queue = mention_queue.lock().unwrap();
+                    mention_queue.requeue(mention);
+                    if let Err(e) = mention_queue.save() {
+                        error!("[TWITTER] Unexpected error saving mention queue: {}", e);
+                    }
+                    return;
+                }
+            };
+            telemetry::published(TWITTER, "reply", true);
+            info!("[TWITTER] Agent responded successfully");
+            record_tweet(pipeline, tweet_id, version, true);
+            let event = Event {
+                tweet_id: Some(tweet_id.as_u64().to_string()),
+                ..event
+            };
+            events.record(event).await;
+            scheduler.lock().unwrap().record_replies(1);
+            summary.replies.fetch_add(1, Ordering::Relaxed);
+            let message = Message {
+                id: format!("tweet_{}", mention.id),
+                content: mention.text,
+            };
+            remember(memory_tx, message).await;
+
+            if *use_stats {
+                match store.stats_inc_reply_count(version).await {
+                    Ok(_) => {
+                        info!("[STATS_DB] Incremented reply count");
+                    }
+                    Err(e) => error!("[STATS_DB] Failed to increment reply count: {}", e),
+                }
+            }
+        }
+        PublishTask::SocialReply {
+            client,
+            mention,
+            text,
+            event,
+        } => {
+            let client = &social_clients[client];
+            let id = match client.reply(&mention.post, &text).await {
+                Ok(id) => id,
+                // The reply may have been posted, so the mention isn't answered again.
+                Err(e) if e.downcast_ref::<UnknownOutcome>().is_some() => {
+                    error!(
+                        "[{}] Reply to {} may not have been posted: {}. Skipping...",
+                        client.platform().tag(),
+                        mention.post.id,
+                        e
+                    );
+                    telemetry::published(client.platform().name(), "reply", false);
+                    let event = event.failed(format!("reply failed: {}", e));
+                    events.record(event).await;
+                    return;
+                }
+                Err(e) => {
+                    error!(
+                        "[{}] Unexpected error replying to {}: {}. Requeueing...",
+                        client.platform().tag(),
+                        mention.post.id,
+                        e
+                    );
+                    telemetry::published(client.platform().name(), "reply", false);
+                    let event = event.failed(format!("reply failed: {}", e));
+                    events.record(event).await;
+                    let mut social_queue = pipeline.social_queue.lock().unwrap();
+                    social_queue.requeue(mention);
+                    if let Err(e) = social_queue.save() {
+                        error!(
+                            "[TWITTER] Unexpected error saving social mention queue: {}",
+                            e
+                        );
+                    }
+                    return;
+                }
+            };
+            telemetry::published(client.platform().name(), "reply", true);
+            info!("[{}] Agent responded successfully", client.platform().tag());
+            let event = Event {
+                tweet_id: Some(id),
+                ..event
+            };
+            events.record(event).await;
+            scheduler.lock().unwrap().record_replies(1);
+            summary.replies.fetch_add(1, Ordering::Relaxed);
+            let message = Message {
+                id: format!("{}_{}", client.platform().name(), mention.post.id),
+                content: mention.post.text,
+            };
+            remember(memory_tx, message).await;
         }
-        Ok(())
     }
+}
 
-    async fn build_embedding(&self, message: Message) -> Result<Embedding> {
-        let embedding = EmbeddingsBuilder::new(self.embedding_model.clone())
-            .document(message.clone())?
-            .build()
-            .await?;
+// Hands an answered mention to the memory worker.
+async fn remember(memory_tx: &Sender<Message>, message: Message) {
+    if memory_tx.send(message).await.is_err() {
+        error!("[VEC_DB] Memory queue closed. Continuing...");
+    }
+}
 
-        Ok(embedding[0].1.first())
+// Posts a tweet, with its image if the upload works, and records its stats and event.
+// Returns whether the tweet was posted.
+async fn publish_tweet(
+    pipeline: &Pipeline,
+    text: &str,
+    attachment: Option<Attachment>,
+    version: u8,
+    event: Event,
+) -> bool {
+    let twitter_client = &pipeline.twitter_client;
+    let media_id = match attachment {
+        Some(attachment) => match twitter_client
+            .upload_media(
+                &attachment.image.bytes,
+                &attachment.image.mime_type,
+                &attachment.alt_text,
+            )
+            .await
+        {
+            Ok(media_id) => Some(media_id),
+            Err(e) => {
+                error!(
+                    "[TWITTER] Unexpected error uploading media: {}. Posting without it...",
+                    e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let published = match media_id {
+        Some(media_id) => twitter_client.publish_with_media(text, &[media_id]).await,
+        None => twitter_client.publish(text).await,
+    };
+    let tweet_id = match published {
+        Ok(tweet_id) => tweet_id,
+        Err(e) => {
+            error!(
+                "[TWITTER] Unexpected error occured whilst publishing tweet: {}. Skipping...",
+                e
+            );
+            telemetry::published(TWITTER, "post", false);
+            let event = event.failed(format!("publish failed: {}", e));
+            pipeline.events.record(event).await;
+            return false;
+        }
+    };
+    telemetry::published(TWITTER, "post", true);
+    pipeline
+        .state
+        .lock()
+        .unwrap()
+        .record_post(TWITTER, Utc::now().timestamp());
+    info!("[TWITTER] Successfully published tweet");
+    record_tweet(pipeline, tweet_id, version, false);
+    let event = Event {
+        tweet_id: Some(tweet_id.as_u64().to_string()),
+        ..event
+    };
+    pipeline.events.record(event).await;
+
+    if pipeline.use_stats {
+        match pipeline.store.stats_inc_tweet_count(version).await {
+            Ok(_) => {
+                info!("[STATS_DB] Incremented tweet count");
+            }
+            Err(e) => error!("[STATS_DB] Failed to increment tweet count: {}", e),
+        }
     }
+    true
+}
 
-    async fn build_embedding_many(
-        &self,
-        messages: Vec<Message>,
-    ) -> Result<Vec<(Message, OneOrMany<Embedding>)>> {
-        let embeddings = EmbeddingsBuilder::new(self.embedding_model.clone())
-            .documents(messages.clone())?
-            .build()
-            .await?;
-        Ok(embeddings)
+// Remembers a published tweet so its engagement metrics can be collected.
+// Saved right away, so a crash doesn't lose the tweets metrics are collected for.
+fn record_tweet(pipeline: &Pipeline, tweet_id: NumericId, version: u8, is_reply: bool) {
+    let now = Utc::now();
+    let mut state = pipeline.state.lock().unwrap();
+    state.record_tweet(
+        PostedTweet {
+            id: tweet_id.as_u64(),
+            version,
+            is_reply,
+            posted_at_unix: now.timestamp(),
+        },
+        (now - ChronoDuration::days(METRICS_MAX_AGE_DAYS)).timestamp(),
+    );
+    if let Err(e) = state.save() {
+        error!("[TWITTER] Unexpected error saving posted tweets: {}", e);
+    }
+}
+
+// Admin worker: answers the admin API's requests from the pipeline's shared state.
+// Posts and branches go through the generation queue like scheduled ones, and respect
+// the pause, the daily post cap and the model budget. Approved replies go to the
+// publish worker.
+async fn answer_admin(
+    pipeline: Pipeline,
+    mut admin_rx: Receiver<AdminCall>,
+    generate_tx: Sender<GenerateTask>,
+    approved_tx: Sender<PublishTask>,
+) {
+    while let Some(AdminCall { request, respond }) = admin_rx.recv().await {
+        info!("[ADMIN] {:?}", request);
+        let response = match request {
+            AdminRequest::Health => {
+                let mut scheduler = pipeline.scheduler.lock().unwrap();
+                let (tokens_today, cost_today) = scheduler.usage_today();
+                AdminResponse::ok(json!({
+                    "status": format!("{:?}", pipeline.shutdown()).to_lowercase(),
+                    "uptime_secs": (Utc::now() - pipeline.started_at).num_seconds(),
+                    "paused": scheduler.is_paused(),
+                    "over_budget": scheduler.budget_exceeded(),
+                    "tokens_today": tokens_today,
+                    "cost_today_usd": cost_today,
+                    "next_post": scheduler.next_post().to_rfc3339(),
+                    "platforms": std::iter::once(TWITTER)
+                        .chain(pipeline.social_clients.iter().map(|client| client.platform().name()))
+                        .collect::<Vec<_>>(),
+                }))
+            }
+            AdminRequest::Character => {
+                AdminResponse::ok(json!(*pipeline.character.lock().unwrap()))
+            }
+            AdminRequest::Queue => {
+                let mention_queue = pipeline.mention_queue.lock().unwrap();
+                AdminResponse::ok(json!({
+                    "queued": mention_queue.len(),
+                    "top": mention_queue.top(QUEUE_PREVIEW_SIZE),
+                    "pending_replies": pipeline.pending_replies.lock().unwrap().replies.len(),
+                }))
+            }
+            AdminRequest::Events { limit } => {
+                AdminResponse::ok(json!(pipeline.events.recent(limit)))
+            }
+            AdminRequest::Stats if !pipeline.use_stats => {
+                AdminResponse::error(StatusCode::CONFLICT, "stats are disabled")
+            }
+            AdminRequest::Stats => match report::summarize(&pipeline.store).await {
+                Ok(summaries) => AdminResponse::ok(json!(summaries)),
+                Err(e) => AdminResponse::error(StatusCode::INTERNAL_SERVER_ERROR, e),
+            },
+            AdminRequest::Pause | AdminRequest::Resume => {
+                let paused = matches!(request, AdminRequest::Pause);
+                pipeline.scheduler.lock().unwrap().set_paused(paused);
+                info!(
+                    "[ADMIN] Posting {}",
+                    if paused { "paused" } else { "resumed" }
+                );
+                AdminResponse::ok(json!({ "paused": paused }))
+            }
+            AdminRequest::Post if pipeline.scheduler.lock().unwrap().is_paused() => {
+                AdminResponse::error(StatusCode::CONFLICT, "posting is paused")
+            }
+            AdminRequest::Post if pipeline.scheduler.lock().unwrap().post_cap_reached() => {
+                AdminResponse::error(StatusCode::CONFLICT, "daily post cap reached")
+            }
+            AdminRequest::Post | AdminRequest::Branch
+                if pipeline.scheduler.lock().unwrap().budget_exceeded() =>
+            {
+                AdminResponse::error(StatusCode::CONFLICT, "daily model budget spent")
+            }
+            AdminRequest::Post | AdminRequest::Branch => {
+                let task = match request {
+                    AdminRequest::Post => GenerateTask::Post,
+                    _ => GenerateTask::Branch,
+                };
+                match generate_tx.try_send(task) {
+                    Ok(()) => AdminResponse::accepted("queued"),
+                    Err(_) => AdminResponse::error(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "generation queue is full, try again later",
+                    ),
+                }
+            }
+            AdminRequest::Replies => {
+                let pending_replies = pipeline.pending_replies.lock().unwrap();
+                let replies = pending_replies
+                    .replies
+                    .iter()
+                    .map(|(id, (held_at, task))| {
+                        let (platform, mention, text) = match task {
+                            PublishTask::Reply { mention, text, .. } => {
+                                (TWITTER, mention.text.as_str(), text)
+                            }
+                            PublishTask::SocialReply {
+                                client,
+                                mention,
+                                text,
+                                ..
+                            } => (
+                                pipeline.social_clients[*client].platform().name(),
+                                mention.post.text.as_str(),
+                                text,
+                            ),
+                            PublishTask::Post { .. } => unreachable!("only replies are held"),
+                        };
+                        json!({
+                            "id": id,
+                            "held_at": held_at.to_rfc3339(),
+                            "platform": platform,
+                            "mention": mention,
+                            "text": text,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                AdminResponse::ok(json!(replies))
+            }
+            AdminRequest::Approve(id) => {
+                let task = pipeline.pending_replies.lock().unwrap().take(id);
+                match task {
+                    Some((held_at, task)) => match approved_tx.try_send(task) {
+                        Ok(()) => AdminResponse::accepted("queued"),
+                        Err(e) => {
+                            // Still pending, to be approved again or requeued on shutdown.
+                            pipeline.pending_replies.lock().unwrap().restore(
+                                id,
+                                held_at,
+                                e.into_inner(),
+                            );
+                            AdminResponse::error(
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "publish queue is full, try again later",
+                            )
+                        }
+                    },
+                    None => AdminResponse::error(
+                        StatusCode::NOT_FOUND,
+                        format!("no pending reply {}", id),
+                    ),
+                }
+            }
+            AdminRequest::Reject(id) => {
+                let task = pipeline.pending_replies.lock().unwrap().take(id);
+                match task {
+                    Some((_, PublishTask::Reply { event, .. }))
+                    | Some((_, PublishTask::SocialReply { event, .. })) => {
+                        let event = Event {
+                            action: Action::Skip,
+                            reason: Some("reply rejected by operator".to_string()),
+                            ..event
+                        };
+                        pipeline.events.record(event).await;
+                        AdminResponse::ok(json!({ "rejected": id }))
+                    }
+                    _ => AdminResponse::error(
+                        StatusCode::NOT_FOUND,
+                        format!("no pending reply {}", id),
+                    ),
+                }
+            }
+        };
+        let _ = respond.send(response);
     }
+    info!("[ADMIN] Admin worker stopped");
+}
+
+// Drops the replies held for approval longer than `MENTION_MAX_AGE_HOURS`, since their
+// mentions are stale by then. Returns how many are still held.
+async fn expire_pending_replies(pipeline: &Pipeline) -> usize {
+    let expired = pipeline
+        .pending_replies
+        .lock()
+        .unwrap()
+        .expire(ChronoDuration::hours(*MENTION_MAX_AGE_HOURS));
+    for (id, task) in expired {
+        if let PublishTask::Reply { event, .. } | PublishTask::SocialReply { event, .. } = task {
+            info!("[ADMIN] Reply {} expired waiting for approval", id);
+            let event = Event {
+                action: Action::Skip,
+                reason: Some("reply expired waiting for approval".to_string()),
+                ..event
+            };
+            pipeline.events.record(event).await;
+        }
+    }
+    pipeline.pending_replies.lock().unwrap().replies.len()
+}
+
+// Replies still waiting for approval on shutdown go back to their mention queue, Twitter
+// replies to the mention queue and the other platforms' to the social queue, so they
+// are generated again on the next run.
+fn requeue_pending_replies(pipeline: &Pipeline) {
+    let replies = std::mem::take(&mut pipeline.pending_replies.lock().unwrap().replies);
+    for (_, task) in replies.into_values() {
+        match task {
+            PublishTask::Reply { mention, .. } => {
+                pipeline.mention_queue.lock().unwrap().requeue(mention)
+            }
+            PublishTask::SocialReply { mention, .. } => {
+                pipeline.social_queue.lock().unwrap().requeue(mention)
+            }
+            _ => {
+                pipeline.summary.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Metrics worker: fetches the public metrics of the agent's recent posts and stores
+// them per tweet on the stats document of the version that wrote them.
+async fn collect_metrics(pipeline: Pipeline, mut metrics_rx: Receiver<()>) {
+    while metrics_rx.recv().await.is_some() {
+        if pipeline.shutdown() != Shutdown::Running {
+            continue;
+        }
+
+        let since = (Utc::now() - ChronoDuration::days(METRICS_MAX_AGE_DAYS)).timestamp();
+        // Posts only, so the engagement totals cover the same tweets as `tweets_sent`.
+        let tweets = pipeline
+            .state
+            .lock()
+            .unwrap()
+            .posted_tweets
+            .iter()
+            .rev()
+            .filter(|tweet| !tweet.is_reply && tweet.posted_at_unix >= since)
+            .take(METRICS_MAX_TWEETS)
+            .cloned()
+            .collect::<Vec<_>>();
+        if tweets.is_empty() {
+            info!("[STATS_DB] No recent tweets to collect metrics for. Skipping...");
+            continue;
+        }
+
+        let ids = tweets
+            .iter()
+            .map(|tweet| NumericId::new(tweet.id))
+            .collect::<Vec<_>>();
+        let metrics = match pipeline.twitter_client.fetch_tweet_metrics(&ids).await {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                error!(
+                    "[STATS_DB] Unexpected error fetching tweet metrics: {}. Skipping...",
+                    e
+                );
+                continue;
+            }
+        };
+
+        let mut by_version = BTreeMap::<u8, Vec<(u64, Engagement)>>::new();
+        for metrics in metrics {
+            let id = metrics.id.as_u64();
+            if let Some(tweet) = tweets.iter().find(|tweet| tweet.id == id) {
+                by_version.entry(tweet.version).or_default().push((
+                    id,
+                    Engagement {
+                        likes: metrics.likes,
+                        retweets: metrics.retweets,
+                        replies: metrics.replies,
+                        quotes: metrics.quotes,
+                        impressions: metrics.impressions,
+                    },
+                ));
+            }
+        }
+
+        for (version, engagement) in by_version {
+            match pipeline
+                .store
+                .stats_record_engagement(version, &engagement)
+                .await
+            {
+                Ok(()) => info!(
+                    "[STATS_DB] Recorded engagement of {} tweets for version {}",
+                    engagement.len(),
+                    version
+                ),
+                Err(e) => error!(
+                    "[STATS_DB] Failed to record engagement for version {}: {}",
+                    version, e
+                ),
+            }
+        }
+    }
+    info!("[STATS_DB] Metrics worker stopped");
+}
+
+// Memory worker: embeds messages and stores them in the vector store.
+async fn store_memories(
+    pipeline: Pipeline,
+    embedder: Embedder,
+    prices: Prices,
+    mut memory_rx: Receiver<Message>,
+) {
+    let retry = RetryConfig::from_env("EMBEDDING");
+
+    while let Some(message) = memory_rx.recv().await {
+        if pipeline.shutdown() == Shutdown::Cancelled {
+            pipeline.summary.dropped.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        let embedding = with_retries("embedding", &retry, || {
+            build_embedding(&embedder, message.clone())
+        })
+        .await;
+
+        if let Ok((_, tokens)) = &embedding {
+            let cost_usd = prices.cost(embedder.model(), tokens).unwrap_or_default();
+            telemetry::spent(embedder.model(), tokens, cost_usd);
+            let usage = Usage {
+                calls: 1,
+                input_tokens: tokens.input_tokens,
+                output_tokens: 0,
+                cost_usd,
+            };
+            let version = pipeline.character.lock().unwrap().version;
+            record_usage(
+                &pipeline.store,
+                &pipeline.scheduler,
+                pipeline.use_stats,
+                "embedding",
+                version,
+                usage,
+            )
+            .await;
+        }
+
+        match embedding {
+            Ok((embedding, _)) => {
+                info!("[VEC_DB] Built embedding for tweet: {:?}", embedding);
+                if let Err(e) = pipeline.store.vec_store_message(embedding, message).await {
+                    error!(
+                        "[VEC_DB] Unexpected error storing tweet to memory: {}. Continuing...",
+                        e
+                    );
+                } else {
+                    pipeline.summary.memories.fetch_add(1, Ordering::Relaxed);
+                    info!("[VEC_DB] Stored tweet to memory");
+                }
+            }
+            Err(e) => {
+                error!(
+                    "[VEC_DB] Unexpected error building embedding for tweet: {}. Continuing...",
+                    e
+                );
+            }
+        }
+    }
+    info!("[VEC_DB] Memory worker stopped");
+}
+
+// Counts model usage towards the daily budget and, with stats on, the version's usage.
+async fn record_usage(
+    store: &Store,
+    scheduler: &Mutex<Scheduler>,
+    use_stats: bool,
+    action: &str,
+    version: u8,
+    usage: Usage,
+) {
+    scheduler
+        .lock()
+        .unwrap()
+        .record_usage(usage.input_tokens + usage.output_tokens, usage.cost_usd);
+    if !use_stats {
+        return;
+    }
+    if let Err(e) = store.stats_add_usage(version, action, &usage).await {
+        error!("[STATS_DB] Failed to record {} usage: {}", action, e);
+    }
+}
+
+async fn build_embedding(embedder: &Embedder, message: Message) -> Result<(Embedding, TokenUsage)> {
+    let started = Instant::now();
+    let embedded = embedder.embed(vec![message.content]).await;
+    telemetry::embedded(started.elapsed(), embedded.is_ok());
+
+    let (embeddings, usage) = embedded?;
+    let embedding = embeddings
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("embedding response was empty"))?;
+    Ok((embedding, usage))
 }
@@ -0,0 +1,659 @@
+use crate::{
+    core::{
+        events::{Action, Event},
+        Message,
+    },
+    db::store::{Engagement, Scope, Storage, Usage, VersionStats},
+};
+use anyhow::Result;
+use chrono::Utc;
+use rig::{embeddings::Embedding, OneOrMany};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScopedEvent {
+    character: String,
+    environment: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Memory {
+    #[serde(default)]
+    character: String,
+    #[serde(default)]
+    environment: String,
+    id: String,
+    content: String,
+    embedding: Vec<f64>,
+}
+
+// Embedded store for deployments without a database server. Stats are kept in
+// `stats.<character>.<environment>.json`, one file per scope so agents sharing the
+// directory don't overwrite each other's. Events are appended to `events.jsonl` and
+// memories to `memory.jsonl`, all under one directory. Memories are searched by brute force, which is fast enough
+// for the few thousand tweets an agent writes.
+pub struct Store {
+    stats_path: PathBuf,
+    events_path: PathBuf,
+    memory_path: PathBuf,
+    // Kept open in append mode, locked per write so events don't interleave.
+    events: Mutex<File>,
+    stats: Mutex<Vec<VersionStats>>,
+    // Locked per write and search so memories don't interleave.
+    memories: Mutex<()>,
+    scope: Scope,
+}
+
+impl Store {
+    pub fn open(dir: &Path, scope: Scope) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stats_path = dir.join(format!(
+            "stats.{}.{}.json",
+            file_safe(&scope.character),
+            file_safe(&scope.environment)
+        ));
+        let events_path = dir.join("events.jsonl");
+        let memory_path = dir.join("memory.jsonl");
+        let events = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&events_path)?;
+
+        // Stores from before stats were split per scope kept every scope in `stats.json`.
+        let legacy_path = dir.join("stats.json");
+        let stats = if stats_path.exists() {
+            serde_json::from_str::<Vec<VersionStats>>(&fs::read_to_string(&stats_path)?)?
+        } else if legacy_path.exists() {
+            serde_json::from_str::<Vec<VersionStats>>(&fs::read_to_string(&legacy_path)?)?
+                .into_iter()
+                .filter(|doc| {
+                    doc.character == scope.character && doc.environment == scope.environment
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            stats_path,
+            events_path,
+            memory_path,
+            events: Mutex::new(events),
+            stats: Mutex::new(stats),
+            memories: Mutex::new(()),
+            scope,
+        })
+    }
+
+    // Applies `update` to the version's document, creating it first if it is missing,
+    // and saves the stats.
+    fn update_stats(
+        &self,
+        version: u8,
+        creation_date_unix: u32,
+        update: impl FnOnce(&mut VersionStats),
+    ) -> Result<()> {
+        let mut stats = self.stats.lock().unwrap();
+        let idx = match stats.iter().position(|doc| {
+            doc.character == self.scope.character
+                && doc.environment == self.scope.environment
+                && doc.version == version as u32
+        }) {
+            Some(idx) => idx,
+            None => {
+                stats.push(VersionStats {
+                    character: self.scope.character.clone(),
+                    environment: self.scope.environment.clone(),
+                    version: version as u32,
+                    tweets_sent: 0,
+                    replies_sent: 0,
+                    messages_read: 0,
+                    creation_date_unix,
+                    character_data: String::new(),
+                    tweets: BTreeMap::new(),
+                    engagement: Engagement::default(),
+                    usage: BTreeMap::new(),
+                });
+                stats.len() - 1
+            }
+        };
+        update(&mut stats[idx]);
+
+        self.save_stats(&stats)
+    }
+
+    fn save_stats(&self, stats: &[VersionStats]) -> Result<()> {
+        let temp_path = self.stats_path.with_extension("tmp");
+        {
+            let mut stats_file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&temp_path)?;
+            serde_json::to_writer_pretty(&mut stats_file, stats)?;
+        }
+        fs::rename(temp_path, &self.stats_path)?;
+
+        Ok(())
+    }
+
+    // Memories are only ever added, so they are appended one JSON line each.
+    fn append_memories(&self, new_memories: Vec<Memory>) -> Result<()> {
+        let mut lines = String::new();
+        for memory in &new_memories {
+            lines.push_str(&serde_json::to_string(memory)?);
+            lines.push('\n');
+        }
+
+        let _memories = self.memories.lock().unwrap();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.memory_path)?
+            .write_all(lines.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl Storage for Store {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    // Creates the version document if it doesn't exist yet. The character data is
+    // refreshed either way.
+    async fn stats_upsert_version_doc(
+        &self,
+        version: u8,
+        creation_date_unix: u32,
+        character_data: String,
+    ) -> Result<()> {
+        self.update_stats(version, creation_date_unix, |doc| {
+            doc.character_data = character_data
+        })
+    }
+
+    async fn stats_inc_tweet_count(&self, version: u8) -> Result<()> {
+        self.update_stats(version, Utc::now().timestamp() as u32, |doc| {
+            doc.tweets_sent += 1
+        })
+    }
+
+    async fn stats_inc_reply_count(&self, version: u8) -> Result<()> {
+        self.update_stats(version, Utc::now().timestamp() as u32, |doc| {
+            doc.replies_sent += 1
+        })
+    }
+
+    async fn stats_add_msgs_read(&self, version: u8, num_msgs: u32) -> Result<()> {
+        self.update_stats(version, Utc::now().timestamp() as u32, |doc| {
+            doc.messages_read += num_msgs as u64
+        })
+    }
+
+    async fn stats_record_engagement(
+        &self,
+        version: u8,
+        tweets: &[(u64, Engagement)],
+    ) -> Result<()> {
+        self.update_stats(version, Utc::now().timestamp() as u32, |doc| {
+            for (id, engagement) in tweets {
+                doc.tweets.insert(id.to_string(), *engagement);
+            }
+            doc.engagement = Engagement::default();
+            for engagement in doc.tweets.values() {
+                doc.engagement.add(engagement);
+            }
+        })
+    }
+
+    async fn stats_add_usage(&self, version: u8, action: &str, usage: &Usage) -> Result<()> {
+        self.update_stats(version, Utc::now().timestamp() as u32, |doc| {
+            doc.usage.entry(action.to_string()).or_default().add(usage)
+        })
+    }
+
+    // Version documents of the scope, by version.
+    async fn stats_versions(&self) -> Result<Vec<VersionStats>> {
+        let mut versions = self
+            .stats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|doc| {
+                doc.character == self.scope.character && doc.environment == self.scope.environment
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        versions.sort_by_key(|doc| doc.version);
+        Ok(versions)
+    }
+
+    // Events of the scope with the given action, oldest first.
+    async fn events(&self, action: Action) -> Result<Vec<Event>> {
+        let _events = self.events.lock().unwrap();
+        let mut events = Vec::new();
+        for line in fs::read_to_string(&self.events_path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let scoped = serde_json::from_str::<ScopedEvent>(line)?;
+            if scoped.character == self.scope.character
+                && scoped.environment == self.scope.environment
+                && scoped.event.action == action
+            {
+                events.push(scoped.event);
+            }
+        }
+
+        Ok(events)
+    }
+
+    async fn append_event(&self, event: &Event) -> Result<()> {
+        let mut line = serde_json::to_string(&ScopedEvent {
+            character: self.scope.character.clone(),
+            environment: self.scope.environment.clone(),
+            event: event.clone(),
+        })?;
+        line.push('\n');
+
+        self.events.lock().unwrap().write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    async fn vec_store_message(&self, embedding: Embedding, message: Message) -> Result<()> {
+        self.append_memories(vec![Memory {
+            character: self.scope.character.clone(),
+            environment: self.scope.environment.clone(),
+            id: message.id,
+            content: message.content,
+            embedding: embedding.vec,
+        }])
+    }
+
+    async fn vec_store_message_many(
+        &self,
+        embeddings: Vec<(Message, OneOrMany<Embedding>)>,
+    ) -> Result<()> {
+        let memories = embeddings
+            .into_iter()
+            .map(|(message, embedding)| Memory {
+                character: self.scope.character.clone(),
+                environment: self.scope.environment.clone(),
+                id: message.id,
+                content: message.content,
+                embedding: embedding.first().vec,
+            })
+            .collect::<Vec<_>>();
+        self.append_memories(memories)
+    }
+
+    // The `limit` memories of the scope closest to `query` by cosine similarity, best first.
+    async fn vec_search(&self, query: &[f64], limit: usize) -> Result<Vec<(f64, Message)>> {
+        let memories = {
+            let _memories = self.memories.lock().unwrap();
+            if !self.memory_path.exists() {
+                return Ok(Vec::new());
+            }
+            fs::read_to_string(&self.memory_path)?
+        };
+
+        let mut scored = Vec::new();
+        for line in memories.lines().filter(|line| !line.trim().is_empty()) {
+            let memory = serde_json::from_str::<Memory>(line)?;
+            if memory.character == self.scope.character
+                && memory.environment == self.scope.environment
+            {
+                scored.push((cosine_similarity(query, &memory.embedding), memory));
+            }
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(score, memory)| {
+                (
+                    score,
+                    Message {
+                        id: memory.id,
+                        content: memory.content,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+// Keeps letters, digits, `-` and `_` of a scope name, so it can be part of a file name.
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// 0 when the lengths differ or either vector is all zeros.
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f64>();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, Rng};
+
+    // Fresh directory under the system temp dir, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("loreweaver-local-{}", thread_rng().gen::<u64>()));
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn scope(character: &str, environment: &str) -> Scope {
+        Scope {
+            character: character.to_string(),
+            environment: environment.to_string(),
+        }
+    }
+
+    fn engagement(likes: u64, impressions: u64) -> Engagement {
+        Engagement {
+            likes,
+            impressions,
+            ..Engagement::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_survive_a_reopen() {
+        let dir = TempDir::new();
+        {
+            let store = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+            store
+                .stats_upsert_version_doc(1, 1_700_000_000, "{}".to_string())
+                .await
+                .unwrap();
+            store.stats_inc_tweet_count(1).await.unwrap();
+            store.stats_inc_tweet_count(1).await.unwrap();
+            store.stats_inc_reply_count(1).await.unwrap();
+            store.stats_add_msgs_read(1, 7).await.unwrap();
+            // Counters land on a version even before its document is created.
+            store.stats_inc_tweet_count(2).await.unwrap();
+            store
+                .stats_record_engagement(1, &[(10, engagement(3, 100)), (11, engagement(1, 50))])
+                .await
+                .unwrap();
+            // Newer metrics of a tweet replace the older ones in the totals.
+            store
+                .stats_record_engagement(1, &[(10, engagement(5, 120))])
+                .await
+                .unwrap();
+            let usage = Usage {
+                calls: 1,
+                input_tokens: 200,
+                output_tokens: 50,
+                cost_usd: 0.01,
+            };
+            store.stats_add_usage(1, "post", &usage).await.unwrap();
+            store.stats_add_usage(1, "post", &usage).await.unwrap();
+        }
+
+        let store = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let versions = store.stats_versions().await.unwrap();
+
+        assert_eq!(
+            versions.iter().map(|doc| doc.version).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let doc = &versions[0];
+        assert_eq!(doc.creation_date_unix, 1_700_000_000);
+        assert_eq!(doc.character_data, "{}");
+        assert_eq!(
+            (doc.tweets_sent, doc.replies_sent, doc.messages_read),
+            (2, 1, 7)
+        );
+        assert_eq!(doc.tweets.len(), 2);
+        assert_eq!((doc.engagement.likes, doc.engagement.impressions), (6, 170));
+        assert_eq!(doc.usage["post"].calls, 2);
+        assert_eq!(doc.usage["post"].input_tokens, 400);
+        assert_eq!(versions[1].tweets_sent, 1);
+    }
+
+    #[tokio::test]
+    async fn scopes_sharing_a_directory_stay_apart() {
+        let dir = TempDir::new();
+        let dev = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        dev.stats_inc_tweet_count(1).await.unwrap();
+        dev.append_event(&Event::new(Action::Post, Some(1), Some("twitter")))
+            .await
+            .unwrap();
+
+        let prod = Store::open(&dir.0, scope("lore", "prod")).unwrap();
+
+        assert!(prod.stats_versions().await.unwrap().is_empty());
+        assert!(prod.events(Action::Post).await.unwrap().is_empty());
+        assert_eq!(dev.events(Action::Post).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn scopes_writing_stats_to_a_shared_directory_keep_theirs() {
+        let dir = TempDir::new();
+        let dev = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let prod = Store::open(&dir.0, scope("lore", "prod")).unwrap();
+        let other = Store::open(&dir.0, scope("other", "dev")).unwrap();
+
+        // Interleaved, so every store writes after the others have.
+        for _ in 0..2 {
+            dev.stats_inc_tweet_count(1).await.unwrap();
+            prod.stats_inc_reply_count(1).await.unwrap();
+            other.stats_add_msgs_read(1, 5).await.unwrap();
+        }
+
+        let reopened = |character: &str, environment: &str| {
+            Store::open(&dir.0, scope(character, environment)).unwrap()
+        };
+        let dev = reopened("lore", "dev").stats_versions().await.unwrap();
+        let prod = reopened("lore", "prod").stats_versions().await.unwrap();
+        let other = reopened("other", "dev").stats_versions().await.unwrap();
+
+        assert_eq!(
+            (dev.len(), dev[0].tweets_sent, dev[0].replies_sent),
+            (1, 2, 0)
+        );
+        assert_eq!(
+            (prod.len(), prod[0].tweets_sent, prod[0].replies_sent),
+            (1, 0, 2)
+        );
+        assert_eq!((other.len(), other[0].messages_read), (1, 10));
+    }
+
+    #[tokio::test]
+    async fn stats_of_a_shared_stats_json_are_picked_up_per_scope() {
+        let dir = TempDir::new();
+        {
+            let dev = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+            dev.stats_inc_tweet_count(1).await.unwrap();
+            let prod = Store::open(&dir.0, scope("lore", "prod")).unwrap();
+            prod.stats_inc_tweet_count(3).await.unwrap();
+        }
+        let mut legacy = Vec::new();
+        for entry in fs::read_dir(&dir.0).unwrap() {
+            let path = entry.unwrap().path();
+            if path.to_string_lossy().contains("stats.") {
+                legacy.extend(
+                    serde_json::from_str::<Vec<VersionStats>>(&fs::read_to_string(&path).unwrap())
+                        .unwrap(),
+                );
+                fs::remove_file(path).unwrap();
+            }
+        }
+        fs::write(
+            dir.0.join("stats.json"),
+            serde_json::to_string(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        let dev = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let versions = dev.stats_versions().await.unwrap();
+
+        assert_eq!(
+            versions.iter().map(|doc| doc.version).collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[tokio::test]
+    async fn events_survive_a_reopen_in_order() {
+        let dir = TempDir::new();
+        {
+            let store = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+            for (action, text) in [
+                (Action::Post, "first"),
+                (Action::Reply, "a reply"),
+                (Action::Post, "second"),
+            ] {
+                let event = Event {
+                    text: Some(text.to_string()),
+                    ..Event::new(action, Some(1), Some("twitter"))
+                };
+                store.append_event(&event).await.unwrap();
+            }
+        }
+
+        let store = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let posts = store.events(Action::Post).await.unwrap();
+
+        assert_eq!(
+            posts
+                .iter()
+                .map(|event| event.text.as_deref().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        assert_eq!(posts[0].platform.as_deref(), Some("twitter"));
+    }
+
+    #[tokio::test]
+    async fn memories_are_appended_with_their_scope() {
+        let dir = TempDir::new();
+        let store = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let embedding = |document: &str, vec: Vec<f64>| Embedding {
+            document: document.to_string(),
+            vec,
+        };
+        let message = |id: &str, content: &str| Message {
+            id: id.to_string(),
+            content: content.to_string(),
+        };
+
+        store
+            .vec_store_message(embedding("hi", vec![0.1, 0.2]), message("tweet_1", "hi"))
+            .await
+            .unwrap();
+        store
+            .vec_store_message_many(vec![(
+                message("tweet_2", "hello"),
+                OneOrMany::one(embedding("hello", vec![0.3, 0.4])),
+            )])
+            .await
+            .unwrap();
+
+        let memories = fs::read_to_string(dir.0.join("memory.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Memory>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            memories
+                .iter()
+                .map(|memory| (memory.id.as_str(), memory.content.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("tweet_1", "hi"), ("tweet_2", "hello")]
+        );
+        assert_eq!(memories[1].embedding, vec![0.3, 0.4]);
+        assert!(memories
+            .iter()
+            .all(|memory| memory.character == "lore" && memory.environment == "dev"));
+    }
+
+    #[tokio::test]
+    async fn vec_search_returns_the_nearest_memories_of_the_scope() {
+        let dir = TempDir::new();
+        let dev = Store::open(&dir.0, scope("lore", "dev")).unwrap();
+        let prod = Store::open(&dir.0, scope("lore", "prod")).unwrap();
+        let memory = |id: &str, vec: Vec<f64>| {
+            (
+                Message {
+                    id: id.to_string(),
+                    content: format!("content of {}", id),
+                },
+                OneOrMany::one(Embedding {
+                    document: id.to_string(),
+                    vec,
+                }),
+            )
+        };
+
+        dev.vec_store_message_many(vec![
+            memory("east", vec![1.0, 0.0]),
+            memory("north", vec![0.0, 1.0]),
+            memory("north_east", vec![0.7, 0.7]),
+        ])
+        .await
+        .unwrap();
+        // Closest of all, but stored by another scope.
+        prod.vec_store_message_many(vec![memory("prod_east", vec![1.0, 0.01])])
+            .await
+            .unwrap();
+
+        let results = dev.vec_search(&[1.0, 0.1], 2).await.unwrap();
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|(_, message)| message.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["east", "north_east"]
+        );
+        assert_eq!(results[0].1.content, "content of east");
+        assert!(results[0].0 > results[1].0);
+        assert_eq!(prod.vec_search(&[0.0, 1.0], 5).await.unwrap().len(), 1);
+    }
+}
@@ -1 +1,3 @@
+pub mod local;
 pub mod mongo;
+pub mod store;
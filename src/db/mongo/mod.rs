@@ -4,5 +4,8 @@ pub struct Credentials {
     pub conn_url: String,
     pub db: String,
     pub vec_collection: String,
+    // Atlas vector search index on the memory collection.
+    pub vec_index: String,
     pub stats_collection: String,
+    pub events_collection: String,
 }
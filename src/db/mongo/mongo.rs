@@ -1,144 +1,429 @@
-use crate::core::Message;
-use crate::db::mongo::Credentials;
+use crate::core::{
+    events::{Action, Event},
+    Message,
+};
+use crate::db::{
+    mongo::Credentials,
+    store::{Engagement, Scope, Storage, Usage, VersionStats},
+};
 use anyhow::{anyhow, Error, Result};
+use chrono::Utc;
+use log::{debug, info, warn};
 use rig::{embeddings::Embedding, OneOrMany};
 
 use mongodb::{
-    bson::{doc, Document},
-    options::ClientOptions,
-    Client as MongoClient, Collection,
+    bson::{doc, from_document, to_bson, to_document, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, IndexOptions, UpdateModifications},
+    Client as MongoClient, Collection, IndexModel,
 };
 
+// Counters of a version document.
+const STATS_COUNTERS: [&str; 3] = ["tweets_sent", "replies_sent", "messages_read"];
+// Metrics of an `Engagement`, as stored per tweet and summed per version.
+const ENGAGEMENT_METRICS: [&str; 5] = ["likes", "retweets", "replies", "quotes", "impressions"];
+// Server error code of a unique index violation.
+const DUPLICATE_KEY: i32 = 11000;
+
 pub struct Client {
     pub client: MongoClient,
     vec_db: Collection<Document>,
+    vec_index: String,
     stats_db: Collection<Document>,
+    events_db: Collection<Document>,
+    scope: Scope,
 }
 
 impl Client {
-    pub async fn new(creds: Credentials) -> Result<Self, Error> {
+    pub async fn new(creds: Credentials, scope: Scope) -> Result<Self, Error> {
         let opts = ClientOptions::parse(creds.conn_url.clone()).await?;
 
         let client = MongoClient::with_options(opts.clone())?;
 
         let vec_db = client.database(&creds.db).collection(&creds.vec_collection);
 
-        println!("{}", &creds.stats_collection);
-        println!("{}", &creds.vec_collection);
+        debug!(
+            "[MONGO] Using database {} (vectors: {}, stats: {}, events: {})",
+            creds.db, creds.vec_collection, creds.stats_collection, creds.events_collection
+        );
 
         let stats_db = client
             .database(&creds.db)
             .collection(&creds.stats_collection);
+        let events_db = client
+            .database(&creds.db)
+            .collection(&creds.events_collection);
+
+        backfill_scope(&vec_db, &scope, "VEC_DB").await?;
+
+        // One version document per character version and environment. Startup fails if
+        // duplicates from before the index exist, as the counters would be split between
+        // them; they need to be merged by hand first.
+        if !creds.stats_collection.is_empty() {
+            backfill_scope(&stats_db, &scope, "STATS_DB").await?;
+            let index = IndexModel::builder()
+                .keys(doc! { "character": 1, "environment": 1, "version": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build();
+            if let Err(e) = stats_db.create_index(index).await {
+                let duplicates = duplicate_versions(&stats_db).await?;
+                if duplicates.is_empty() {
+                    return Err(anyhow!("failed to create the version index: {}", e));
+                }
+                return Err(anyhow!(
+                    "failed to create the version index, merge the duplicate version documents first: {}",
+                    duplicates.join(", ")
+                ));
+            }
+        }
+
+        // Events are read back per scope in time order.
+        if !creds.events_collection.is_empty() {
+            let index = IndexModel::builder()
+                .keys(doc! { "character": 1, "environment": 1, "timestamp_unix": 1 })
+                .build();
+            if let Err(e) = events_db.create_index(index).await {
+                warn!("[STATS_DB] Failed to create the event index: {}", e);
+            }
+        }
 
         Ok(Self {
             client,
             vec_db,
+            vec_index: creds.vec_index,
             stats_db,
+            events_db,
+            scope,
         })
     }
 
-    pub async fn stats_create_version_doc(
+    // Increments a counter, creating the version document with the other counters at 0
+    // if it is missing.
+    async fn stats_inc(&self, version: u8, counter: &str, amount: i64) -> Result<()> {
+        let mut on_insert = doc! { "creation_date_unix": Utc::now().timestamp() as u32 };
+        for other in STATS_COUNTERS.iter().filter(|other| **other != counter) {
+            on_insert.insert(*other, 0_i64);
+        }
+        let update = doc! {
+            "$inc": { counter: amount },
+            "$setOnInsert": on_insert,
+        };
+
+        self.stats_upsert(version, update.into()).await
+    }
+
+    async fn stats_upsert(&self, version: u8, update: UpdateModifications) -> Result<()> {
+        let mut filter = self.scope_filter();
+        filter.insert("version", version as u32);
+
+        match self
+            .stats_db
+            .update_one(filter.clone(), update.clone())
+            .upsert(true)
+            .await
+        {
+            Ok(_) => Ok(()),
+            // Two upserts racing to insert the same document: one wins and the other
+            // fails on the unique index, after which the document exists to update.
+            Err(e) if is_duplicate_key(&e) => {
+                self.stats_db.update_one(filter, update).await?;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Fields every stats, event and memory document of the scope carries.
+    fn scope_filter(&self) -> Document {
+        doc! { "character": &self.scope.character, "environment": &self.scope.environment }
+    }
+}
+
+impl Storage for Client {
+    fn name(&self) -> &'static str {
+        "mongo"
+    }
+
+    // Creates the version document if it doesn't exist yet, in one atomic upsert.
+    // The character data is refreshed either way.
+    async fn stats_upsert_version_doc(
         &self,
         version: u8,
         creation_date_unix: u32,
         character_data: String,
     ) -> Result<()> {
-        let version_doc = doc! { "version": version as u32,
-        "tweets_sent": 0,
-        "replies_sent": 0,
-        "messages_read": 0,
-        "creation_date_unix": creation_date_unix,
-        "character_data": character_data };
-
-        self.stats_db.insert_one(version_doc).await?;
+        let mut on_insert = doc! { "creation_date_unix": creation_date_unix };
+        for counter in STATS_COUNTERS {
+            on_insert.insert(counter, 0_i64);
+        }
+        let update = doc! {
+            "$set": { "character_data": character_data },
+            "$setOnInsert": on_insert,
+        };
 
-        Ok(())
+        self.stats_upsert(version, update.into()).await
     }
 
-    pub async fn stats_version_doc_exists(&self, version: u8) -> Result<()> {
-        let filter = doc! { "version": version as u32};
-
-        let res = self.stats_db.find_one(filter).await?;
+    async fn stats_inc_tweet_count(&self, version: u8) -> Result<()> {
+        self.stats_inc(version, "tweets_sent", 1).await
+    }
 
-        if res.is_none() {
-            return Err(anyhow!("No document found for version"));
-        }
+    async fn stats_inc_reply_count(&self, version: u8) -> Result<()> {
+        self.stats_inc(version, "replies_sent", 1).await
+    }
 
-        Ok(())
+    async fn stats_add_msgs_read(&self, version: u8, num_msgs: u32) -> Result<()> {
+        self.stats_inc(version, "messages_read", num_msgs as i64)
+            .await
     }
 
-    pub async fn stats_inc_tweet_count(&self, version: u8) -> Result<u64, Error> {
-        let filter = doc! { "version": version as u32 };
-        let update = doc! {
-            "$inc": { "tweets_sent": 1 }
+    // Sets `tweets.<id>` to the latest metrics of each tweet, then recomputes the
+    // `engagement` totals from all of the version's tweets, in one pipeline update.
+    async fn stats_record_engagement(
+        &self,
+        version: u8,
+        tweets: &[(u64, Engagement)],
+    ) -> Result<()> {
+        let updated_at_unix = Utc::now().timestamp();
+        let mut set_tweets = doc! {
+            "creation_date_unix": { "$ifNull": ["$creation_date_unix", updated_at_unix as u32] },
         };
+        for (id, engagement) in tweets {
+            set_tweets.insert(
+                format!("tweets.{}", id),
+                doc! {
+                    "likes": engagement.likes as i64,
+                    "retweets": engagement.retweets as i64,
+                    "replies": engagement.replies as i64,
+                    "quotes": engagement.quotes as i64,
+                    "impressions": engagement.impressions as i64,
+                    "updated_at_unix": updated_at_unix,
+                },
+            );
+        }
 
-        let update_res = self.stats_db.update_one(filter, update).await?;
-
-        if update_res.modified_count == 0 {
-            return Err(anyhow!("No document found for version"));
+        let mut totals = Document::new();
+        for metric in ENGAGEMENT_METRICS {
+            totals.insert(
+                metric,
+                doc! { "$sum": {
+                    "$map": {
+                        "input": { "$objectToArray": "$tweets" },
+                        "as": "tweet",
+                        "in": format!("$$tweet.v.{}", metric),
+                    }
+                } },
+            );
         }
 
-        Ok(update_res.modified_count)
+        let pipeline = vec![
+            doc! { "$set": set_tweets },
+            doc! { "$set": { "engagement": totals } },
+        ];
+        self.stats_upsert(version, pipeline.into()).await
     }
 
-    pub async fn stats_inc_reply_count(&self, version: u8) -> Result<u64, Error> {
-        let filter = doc! { "version": version as u32 };
+    async fn stats_add_usage(&self, version: u8, action: &str, usage: &Usage) -> Result<()> {
+        let mut on_insert = doc! { "creation_date_unix": Utc::now().timestamp() as u32 };
+        for counter in STATS_COUNTERS {
+            on_insert.insert(counter, 0_i64);
+        }
         let update = doc! {
-            "$inc": { "replies_sent": 1 }
+            "$inc": {
+                format!("usage.{}.calls", action): usage.calls as i64,
+                format!("usage.{}.input_tokens", action): usage.input_tokens as i64,
+                format!("usage.{}.output_tokens", action): usage.output_tokens as i64,
+                format!("usage.{}.cost_usd", action): usage.cost_usd,
+            },
+            "$setOnInsert": on_insert,
         };
 
-        let update_res = self.stats_db.update_one(filter, update).await?;
+        self.stats_upsert(version, update.into()).await
+    }
 
-        if update_res.modified_count == 0 {
-            return Err(anyhow!("No document found for version"));
+    async fn stats_versions(&self) -> Result<Vec<VersionStats>> {
+        let mut cursor = self
+            .stats_db
+            .find(self.scope_filter())
+            .sort(doc! { "version": 1 })
+            .await?;
+        let mut versions = Vec::new();
+        while cursor.advance().await? {
+            versions.push(from_document::<VersionStats>(
+                cursor.deserialize_current()?,
+            )?);
         }
 
-        Ok(update_res.modified_count)
+        Ok(versions)
     }
 
-    pub async fn stats_add_msgs_read(&self, version: u8, num_msgs: u32) -> Result<u64, Error> {
-        let filter = doc! { "version": version as u32 };
-        let update = doc! {
-            "$inc": { "messages_read": num_msgs }
-        };
-
-        let update_res = self.stats_db.update_one(filter, update).await?;
-
-        if update_res.modified_count == 0 {
-            return Err(anyhow!("No document found for version"));
+    async fn events(&self, action: Action) -> Result<Vec<Event>> {
+        let mut filter = self.scope_filter();
+        filter.insert("action", to_bson(&action)?);
+        let mut cursor = self
+            .events_db
+            .find(filter)
+            .sort(doc! { "timestamp_unix": 1 })
+            .await?;
+        let mut events = Vec::new();
+        while cursor.advance().await? {
+            events.push(from_document::<Event>(cursor.deserialize_current()?)?);
         }
 
-        Ok(update_res.modified_count)
+        Ok(events)
+    }
+
+    async fn append_event(&self, event: &Event) -> Result<()> {
+        let mut document = self.scope_filter();
+        document.extend(to_document(event)?);
+        self.events_db.insert_one(document).await?;
+        Ok(())
     }
 
     // Store embedding to vector store (serves as Agent's memory)
-    pub async fn vec_store_message(&self, embedding: Embedding, message: Message) -> Result<()> {
-        let document = doc! {
+    async fn vec_store_message(&self, embedding: Embedding, message: Message) -> Result<()> {
+        let mut document = self.scope_filter();
+        document.extend(doc! {
             "id": message.id.clone(),
             "content": message.content.clone(),
             "embedding": embedding.vec,
-        };
+        });
         self.vec_db.insert_one(document).await?;
         Ok(())
     }
 
     // Store many embeddings to vector store (serves as Agent's memory)
-    pub async fn vec_store_message_many(
+    async fn vec_store_message_many(
         &self,
         embeddings: Vec<(Message, OneOrMany<Embedding>)>,
     ) -> Result<()> {
         let documents = embeddings
             .iter()
             .map(|(Message { id, content, .. }, embedding)| {
-                doc! {
+                let mut document = self.scope_filter();
+                document.extend(doc! {
                     "id": id.clone(),
                     "definition": content.clone(),
                     "embedding": embedding.first().vec,
-                }
+                });
+                document
             })
             .collect::<Vec<_>>();
         self.vec_db.insert_many(documents).await?;
         Ok(())
     }
+
+    // Atlas vector search over the stored embeddings, which needs a vector search index
+    // on the `embedding` field of the collection with `character` and `environment` as
+    // filter fields.
+    async fn vec_search(&self, query: &[f64], limit: usize) -> Result<Vec<(f64, Message)>> {
+        let pipeline = vec![
+            doc! {
+                "$vectorSearch": {
+                    "index": self.vec_index.clone(),
+                    "path": "embedding",
+                    "queryVector": query.to_vec(),
+                    "numCandidates": (limit * 10) as u32,
+                    "limit": limit as u32,
+                    "filter": self.scope_filter(),
+                }
+            },
+            doc! {
+                "$project": {
+                    "_id": 0,
+                    "id": 1,
+                    "content": 1,
+                    "definition": 1,
+                    "score": { "$meta": "vectorSearchScore" },
+                }
+            },
+        ];
+
+        let mut cursor = self.vec_db.aggregate(pipeline).await?;
+        let mut results = Vec::new();
+        while cursor.advance().await? {
+            let document = cursor.deserialize_current()?;
+            // Memories stored in bulk keep their text under `definition`.
+            let content = document
+                .get_str("content")
+                .or_else(|_| document.get_str("definition"))?;
+            results.push((
+                document.get_f64("score")?,
+                Message {
+                    id: document.get_str("id")?.to_string(),
+                    content: content.to_string(),
+                },
+            ));
+        }
+
+        Ok(results)
+    }
+}
+
+// Character versions with more than one version document, as `character/environment/vN (count)`.
+async fn duplicate_versions(collection: &Collection<Document>) -> Result<Vec<String>> {
+    let pipeline = vec![
+        doc! {
+            "$group": {
+                "_id": { "character": "$character", "environment": "$environment", "version": "$version" },
+                "count": { "$sum": 1 },
+            }
+        },
+        doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let mut duplicates = Vec::new();
+    while cursor.advance().await? {
+        let document = cursor.deserialize_current()?;
+        let key = document.get_document("_id")?;
+        duplicates.push(format!(
+            "{}/{}/v{} ({})",
+            key.get_str("character").unwrap_or("?"),
+            key.get_str("environment").unwrap_or("?"),
+            key.get("version")
+                .map_or("?".to_string(), |version| version.to_string()),
+            document
+                .get("count")
+                .map_or("?".to_string(), |count| count.to_string())
+        ));
+    }
+
+    Ok(duplicates)
+}
+
+// Documents written before stats and memories were scoped have no `character`, or no
+// `environment`, so no scope would ever read or update them again. The first character
+// started on the collection claims them; after that there is nothing left to backfill.
+async fn backfill_scope(
+    collection: &Collection<Document>,
+    scope: &Scope,
+    // Log prefix of the collection.
+    tag: &str,
+) -> Result<()> {
+    let unscoped = doc! { "$or": [
+        { "character": { "$exists": false } },
+        { "character": &scope.character, "environment": { "$exists": false } },
+    ] };
+    let update = doc! { "$set": {
+        "character": &scope.character,
+        "environment": &scope.environment,
+    } };
+
+    let result = collection.update_many(unscoped, update).await?;
+    if result.modified_count > 0 {
+        info!(
+            "[{}] Backfilled {} unscoped documents into {} / {}",
+            tag, result.modified_count, scope.character, scope.environment
+        );
+    }
+
+    Ok(())
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY
+    )
 }
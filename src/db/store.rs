@@ -0,0 +1,293 @@
+use super::{
+    local::local::Store as LocalStore,
+    mongo::{mongo::Client as MongoClient, Credentials as MongoCredentials},
+};
+use crate::core::{
+    events::{Action, Event},
+    Message,
+};
+use anyhow::Result;
+use rig::{embeddings::Embedding, OneOrMany};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, future::Future, path::PathBuf};
+
+// Whose stats and memories a store reads and writes. Documents are keyed by character
+// name and deployment environment, so characters and deployments sharing one database
+// don't mix their data.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub character: String,
+    // e.g. `dev` or `prod`
+    pub environment: String,
+}
+
+// Public metrics of a tweet, or their sum over a version's tweets.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Engagement {
+    pub likes: u64,
+    pub retweets: u64,
+    pub replies: u64,
+    pub quotes: u64,
+    pub impressions: u64,
+}
+
+impl Engagement {
+    pub fn add(&mut self, other: &Engagement) {
+        self.likes += other.likes;
+        self.retweets += other.retweets;
+        self.replies += other.replies;
+        self.quotes += other.quotes;
+        self.impressions += other.impressions;
+    }
+}
+
+// Tokens and cost of the model calls made for one kind of action.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.calls += other.calls;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+// Stats document of one character version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionStats {
+    #[serde(default)]
+    pub character: String,
+    #[serde(default)]
+    pub environment: String,
+    pub version: u32,
+    #[serde(default)]
+    pub tweets_sent: u64,
+    #[serde(default)]
+    pub replies_sent: u64,
+    #[serde(default)]
+    pub messages_read: u64,
+    pub creation_date_unix: u32,
+    #[serde(default)]
+    pub character_data: String,
+    // Latest metrics of the version's tweets, by tweet id.
+    #[serde(default)]
+    pub tweets: BTreeMap<String, Engagement>,
+    #[serde(default)]
+    pub engagement: Engagement,
+    // Model usage by action: post, reply, branch or embedding.
+    #[serde(default)]
+    pub usage: BTreeMap<String, Usage>,
+}
+
+pub enum StoreConfig {
+    Mongo(MongoCredentials),
+    // Directory of the embedded store.
+    Local(PathBuf),
+}
+
+// What the agent keeps in storage: stats counters, version documents, the event log and
+// vector memory. Implemented by every backend, and by `Store` for whichever is configured.
+// Calls return `Send` futures so the pipeline workers can run them on any thread.
+pub trait Storage {
+    // Short name of the backend for logs, e.g. `mongo`.
+    fn name(&self) -> &'static str;
+
+    // Creates the stats document of a character version if it doesn't exist yet.
+    fn stats_upsert_version_doc(
+        &self,
+        version: u8,
+        creation_date_unix: u32,
+        character_data: String,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // Counters are upserted, so they are recorded even before the version document exists.
+    fn stats_inc_tweet_count(&self, version: u8) -> impl Future<Output = Result<()>> + Send;
+
+    fn stats_inc_reply_count(&self, version: u8) -> impl Future<Output = Result<()>> + Send;
+
+    fn stats_add_msgs_read(
+        &self,
+        version: u8,
+        num_msgs: u32,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // Stores the latest metrics of a version's tweets, by tweet id, and refreshes the
+    // version's engagement totals.
+    fn stats_record_engagement(
+        &self,
+        version: u8,
+        tweets: &[(u64, Engagement)],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // Adds the tokens and cost of model calls made for `action` to the version's usage.
+    fn stats_add_usage(
+        &self,
+        version: u8,
+        action: &str,
+        usage: &Usage,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // Version documents of the scope, by version.
+    fn stats_versions(&self) -> impl Future<Output = Result<Vec<VersionStats>>> + Send;
+
+    // Events of the scope with the given action, oldest first.
+    fn events(&self, action: Action) -> impl Future<Output = Result<Vec<Event>>> + Send;
+
+    // Appends an event to the event log. Events are never updated or removed.
+    fn append_event(&self, event: &Event) -> impl Future<Output = Result<()>> + Send;
+
+    // Store embedding to vector store (serves as Agent's memory)
+    fn vec_store_message(
+        &self,
+        embedding: Embedding,
+        message: Message,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn vec_store_message_many(
+        &self,
+        embeddings: Vec<(Message, OneOrMany<Embedding>)>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    // Memories of the scope most similar to `query`, best first, with their similarity score.
+    fn vec_search(
+        &self,
+        query: &[f64],
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<(f64, Message)>>> + Send;
+}
+
+// The configured backend: MongoDB for production, or an embedded file store so small
+// deployments and local runs don't need a database server.
+pub enum Store {
+    Mongo(MongoClient),
+    Local(LocalStore),
+}
+
+impl Store {
+    pub async fn open(config: StoreConfig, scope: Scope) -> Result<Self> {
+        match config {
+            StoreConfig::Mongo(creds) => Ok(Store::Mongo(MongoClient::new(creds, scope).await?)),
+            StoreConfig::Local(dir) => Ok(Store::Local(LocalStore::open(&dir, scope)?)),
+        }
+    }
+}
+
+impl Storage for Store {
+    fn name(&self) -> &'static str {
+        match self {
+            Store::Mongo(client) => client.name(),
+            Store::Local(store) => store.name(),
+        }
+    }
+
+    async fn stats_upsert_version_doc(
+        &self,
+        version: u8,
+        creation_date_unix: u32,
+        character_data: String,
+    ) -> Result<()> {
+        match self {
+            Store::Mongo(client) => {
+                client
+                    .stats_upsert_version_doc(version, creation_date_unix, character_data)
+                    .await
+            }
+            Store::Local(store) => {
+                store
+                    .stats_upsert_version_doc(version, creation_date_unix, character_data)
+                    .await
+            }
+        }
+    }
+
+    async fn stats_inc_tweet_count(&self, version: u8) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.stats_inc_tweet_count(version).await,
+            Store::Local(store) => store.stats_inc_tweet_count(version).await,
+        }
+    }
+
+    async fn stats_inc_reply_count(&self, version: u8) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.stats_inc_reply_count(version).await,
+            Store::Local(store) => store.stats_inc_reply_count(version).await,
+        }
+    }
+
+    async fn stats_add_msgs_read(&self, version: u8, num_msgs: u32) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.stats_add_msgs_read(version, num_msgs).await,
+            Store::Local(store) => store.stats_add_msgs_read(version, num_msgs).await,
+        }
+    }
+
+    async fn stats_record_engagement(
+        &self,
+        version: u8,
+        tweets: &[(u64, Engagement)],
+    ) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.stats_record_engagement(version, tweets).await,
+            Store::Local(store) => store.stats_record_engagement(version, tweets).await,
+        }
+    }
+
+    async fn stats_add_usage(&self, version: u8, action: &str, usage: &Usage) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.stats_add_usage(version, action, usage).await,
+            Store::Local(store) => store.stats_add_usage(version, action, usage).await,
+        }
+    }
+
+    async fn stats_versions(&self) -> Result<Vec<VersionStats>> {
+        match self {
+            Store::Mongo(client) => client.stats_versions().await,
+            Store::Local(store) => store.stats_versions().await,
+        }
+    }
+
+    async fn events(&self, action: Action) -> Result<Vec<Event>> {
+        match self {
+            Store::Mongo(client) => client.events(action).await,
+            Store::Local(store) => store.events(action).await,
+        }
+    }
+
+    async fn append_event(&self, event: &Event) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.append_event(event).await,
+            Store::Local(store) => store.append_event(event).await,
+        }
+    }
+
+    async fn vec_store_message(&self, embedding: Embedding, message: Message) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.vec_store_message(embedding, message).await,
+            Store::Local(store) => store.vec_store_message(embedding, message).await,
+        }
+    }
+
+    async fn vec_store_message_many(
+        &self,
+        embeddings: Vec<(Message, OneOrMany<Embedding>)>,
+    ) -> Result<()> {
+        match self {
+            Store::Mongo(client) => client.vec_store_message_many(embeddings).await,
+            Store::Local(store) => store.vec_store_message_many(embeddings).await,
+        }
+    }
+
+    async fn vec_search(&self, query: &[f64], limit: usize) -> Result<Vec<(f64, Message)>> {
+        match self {
+            Store::Mongo(client) => client.vec_search(query, limit).await,
+            Store::Local(store) => store.vec_search(query, limit).await,
+        }
+    }
+}
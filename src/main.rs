@@ -1,41 +1,28 @@
+// `#[derive(Embed)]` refers to the rig crate as `rig_core`.
+extern crate rig as rig_core;
+
 pub mod clients;
 pub mod core;
 pub mod db;
 
-use anyhow::Result;
-use chrono::{TimeZone, Utc};
+use anyhow::{bail, Context, Result};
 use clients::twitter::twitter::TwitterAuth;
 use core::{
-    character::Character, cli::Instance as CliInstance, twitter::Instance as TwitterInstance,
+    character::Character,
+    cli::Instance as CliInstance,
+    logging::{self, LogConfig},
+    report,
+    twitter::Instance as TwitterInstance,
+};
+use db::{
+    mongo::Credentials as MongoCredentials,
+    store::{Scope, Store, StoreConfig},
 };
-use db::mongo::Credentials as MongoCredentials;
 use dotenv::from_filename;
-use fern::colors::ColoredLevelConfig;
-use std::env;
+use std::{env, path::PathBuf};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let colors = ColoredLevelConfig::new()
-        .info(fern::colors::Color::BrightGreen)
-        .error(fern::colors::Color::BrightRed)
-        .warn(fern::colors::Color::BrightYellow);
-
-    fern::Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "[{} | {} | loreweaver] {}",
-                Utc.timestamp_millis(Utc::now().timestamp_millis())
-                    .format("%H:%M:%S.%3f")
-                    .to_string(),
-                colors.color(record.level()),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Info)
-        .chain(std::io::stdout())
-        .apply()
-        .unwrap();
-
     let args = env::args().collect::<Vec<String>>();
     let stage = args
         .get(1)
@@ -49,29 +36,64 @@ async fn main() -> Result<()> {
     if let Err(e) = from_filename(format!(".env.{stage}")) {
         panic!("fatal error occurred loading env file: {e}");
     }
-
-    let anthropic_api_key = env::var("ANTHROPIC_API_KEY")
-        .expect("`ANTHROPIC_API_KEY` is a required environment variable");
-    let openai_api_key =
-        env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY` is a required environment variable");
+    logging::init(&LogConfig::from_env()?)?;
 
     let use_stats =
         env::var("USE_STATS").expect("USE_STATS is a required environment variable") == "true";
-    let stats_collection = if use_stats {
-        env::var("MONGO_CONN_STATS_COLLECTION")
-            .expect("MONGO_CONN_STATS_COLLECTION is a required environment variable")
-    } else {
-        String::new()
+    let store_config = match env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") => StoreConfig::Local(PathBuf::from(
+            env::var("LOCAL_STORAGE_DIR").unwrap_or_else(|_| "storage".to_string()),
+        )),
+        Ok("mongo") | Ok("") | Err(_) => StoreConfig::Mongo(MongoCredentials {
+            conn_url: env::var("MONGO_CONN_URL")
+                .expect("MONGO_CONN_URL` is a required environment variable"),
+            db: env::var("MONGO_CONN_DB")
+                .expect("MONGO_CONN_DB` is a required environment variable"),
+            vec_collection: env::var("MONGO_CONN_VEC_COLLECTION")
+                .expect("MONGO_CONN_VEC_COLLECTION` is a required environment variable"),
+            vec_index: env::var("MONGO_CONN_VEC_INDEX")
+                .unwrap_or_else(|_| "vector_index".to_string()),
+            stats_collection: if use_stats {
+                env::var("MONGO_CONN_STATS_COLLECTION")
+                    .expect("MONGO_CONN_STATS_COLLECTION is a required environment variable")
+            } else {
+                String::new()
+            },
+            events_collection: if use_stats {
+                env::var("MONGO_CONN_EVENTS_COLLECTION").unwrap_or_else(|_| "events".to_string())
+            } else {
+                String::new()
+            },
+        }),
+        Ok(backend) => panic!("unknown `STORAGE_BACKEND` {backend}: expected mongo or local"),
     };
+    let environment = env::var("DEPLOYMENT_ENV")
+        .ok()
+        .filter(|val| !val.is_empty())
+        .unwrap_or_else(|| stage.to_string());
 
-    let mongo_credentials = MongoCredentials {
-        conn_url: env::var("MONGO_CONN_URL")
-            .expect("MONGO_CONN_URL` is a required environment variable"),
-        db: env::var("MONGO_CONN_DB").expect("MONGO_CONN_DB` is a required environment variable"),
-        vec_collection: env::var("MONGO_CONN_VEC_COLLECTION")
-            .expect("MONGO_CONN_VEC_COLLECTION` is a required environment variable"),
-        stats_collection: stats_collection,
-    };
+    let character = Character::load(&character_name)?;
+    logging::set_character(&character.character_name, &environment);
+    logging::set_version(character.version);
+
+    // `stats [compare <a> <b> | export <csv|json> [path]]` reports the character's stats
+    // instead of running the agent.
+    if args.get(3).map(String::as_str) == Some("stats") {
+        if !use_stats {
+            bail!("stats are disabled for this stage (USE_STATS=false)");
+        }
+        let scope = Scope {
+            character: character.character_name.clone(),
+            environment,
+        };
+        let store = Store::open(store_config, scope).await?;
+        return report::run(&store, &args[4..]).await;
+    }
+
+    let anthropic_api_key = env::var("ANTHROPIC_API_KEY")
+        .expect("`ANTHROPIC_API_KEY` is a required environment variable");
+    let openai_api_key =
+        env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY` is a required environment variable");
 
     let twitter_credentials = TwitterAuth {
         api_key: env::var("TWITTER_API_KEY")
@@ -82,10 +104,15 @@ async fn main() -> Result<()> {
             .expect("`TWITTER_ACCESS_TOKEN` is a required environment variable"),
         access_token_secret: env::var("TWITTER_ACCESS_TOKEN_SECRET")
             .expect("`TWITTER_ACCESS_TOKEN_SECRET` is a required environment variable"),
+        user_id: env::var("TWITTER_USER_ID")
+            .ok()
+            .filter(|val| !val.is_empty())
+            .map(|val| {
+                val.parse::<u64>()
+                    .expect("`TWITTER_USER_ID` must be a numeric user id")
+            }),
     };
 
-    let character = Character::load(&character_name)?;
-
     if env::var("USE_CLI").map_or(false, |val| val == "true") {
         let mut cli_instance = CliInstance::new(&anthropic_api_key, character)
             .await
@@ -95,16 +122,17 @@ async fn main() -> Result<()> {
             .await
             .expect("Failed to run CLI instance");
     } else {
-        let mut twitter_instance = TwitterInstance::new(
+        let twitter_instance = TwitterInstance::new(
             &anthropic_api_key,
             &openai_api_key,
-            mongo_credentials,
+            store_config,
             twitter_credentials,
             character,
             use_stats,
+            environment,
         )
         .await
-        .expect("Failed to create CLI instance");
+        .context("Failed to start the twitter agent")?;
         twitter_instance.run().await
     }
     Ok(())